@@ -0,0 +1,73 @@
+// The crate is a binary only, so the module is compiled into the benchmark directly.
+// Its unit tests are compiled in too under `cargo test`, but never run here.
+#[allow(dead_code)]
+#[path = "../src/numeric_league_util.rs"]
+mod numeric_league_util;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use numeric_league_util::{
+    league_to_numeric, numeric_to_league, team_avg_rank_str, ApexCutoffs, EloRounding,
+};
+
+fn lobby(ranks: &[(&str, &str, i32)]) -> Vec<(String, String, i32)> {
+    ranks
+        .iter()
+        .map(|(tier, rank, lp)| (tier.to_string(), rank.to_string(), *lp))
+        .collect()
+}
+
+fn bench_conversions(c: &mut Criterion) {
+    c.bench_function("league_to_numeric", |b| {
+        b.iter(|| {
+            league_to_numeric(black_box("DIAMOND"), black_box("II"), black_box(57))
+                + league_to_numeric(black_box("GRANDMASTER"), black_box("I"), black_box(412))
+        })
+    });
+    c.bench_function("numeric_to_league", |b| {
+        b.iter(|| {
+            (
+                numeric_to_league(black_box(2257)),
+                numeric_to_league(black_box(2812)),
+            )
+        })
+    });
+}
+
+fn bench_team_avg_rank_str(c: &mut Criterion) {
+    let diamond = lobby(&[
+        ("DIAMOND", "I", 75),
+        ("DIAMOND", "I", 20),
+        ("DIAMOND", "II", 50),
+        ("DIAMOND", "II", 0),
+        ("DIAMOND", "III", 99),
+        ("DIAMOND", "III", 33),
+        ("DIAMOND", "IV", 12),
+        ("PLATINUM", "I", 80),
+    ]);
+    let apex = lobby(&[
+        ("CHALLENGER", "I", 1144),
+        ("CHALLENGER", "I", 653),
+        ("GRANDMASTER", "I", 506),
+        ("GRANDMASTER", "I", 526),
+        ("MASTER", "I", 192),
+        ("MASTER", "I", 0),
+        ("MASTER", "I", 87),
+        ("DIAMOND", "I", 40),
+    ]);
+    let cutoffs = ApexCutoffs {
+        grandmaster: 200,
+        challenger: 500,
+    };
+    c.bench_function("team_avg_rank_str diamond", |b| {
+        b.iter(|| team_avg_rank_str(black_box(&diamond), None, EloRounding::Nearest, false))
+    });
+    c.bench_function("team_avg_rank_str apex", |b| {
+        b.iter(|| team_avg_rank_str(black_box(&apex), None, EloRounding::Nearest, false))
+    });
+    c.bench_function("team_avg_rank_str apex cutoffs", |b| {
+        b.iter(|| team_avg_rank_str(black_box(&apex), Some(cutoffs), EloRounding::Nearest, false))
+    });
+}
+
+criterion_group!(benches, bench_conversions, bench_team_avg_rank_str);
+criterion_main!(benches);
@@ -0,0 +1,60 @@
+use riven::consts::Region;
+
+/// Major region clusters that can have their own API key, and the variable holding it
+pub const CLUSTER_KEY_VARS: &[(Region, &str)] = &[
+    (Region::EUROPE, "RGAPI_KEY_EUROPE"),
+    (Region::ASIA, "RGAPI_KEY_ASIA"),
+    (Region::AMERICAS, "RGAPI_KEY_AMERICAS"),
+];
+
+/// API key for each cluster: its `RGAPI_KEY_<CLUSTER>` if set, otherwise `RGAPI_KEY`.
+/// `var` looks up an environment variable.
+pub fn cluster_keys(var: impl Fn(&str) -> Option<String>) -> Result<Vec<(Region, String)>, String> {
+    let fallback = var("RGAPI_KEY");
+    CLUSTER_KEY_VARS
+        .iter()
+        .map(|(cluster, name)| {
+            var(name)
+                .or_else(|| fallback.clone())
+                .map(|key| (*cluster, key))
+                .ok_or_else(|| format!("Missing environment variable: RGAPI_KEY or {}", name))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        move |name| vars.get(name).cloned()
+    }
+
+    #[test]
+    fn test_cluster_keys() {
+        let keys = cluster_keys(env(&[("RGAPI_KEY", "k"), ("RGAPI_KEY_ASIA", "asia")])).unwrap();
+        assert_eq!(
+            keys,
+            vec![
+                (Region::EUROPE, "k".to_string()),
+                (Region::ASIA, "asia".to_string()),
+                (Region::AMERICAS, "k".to_string()),
+            ]
+        );
+
+        // Every cluster has its own, no fallback needed
+        let keys = cluster_keys(env(&[
+            ("RGAPI_KEY_EUROPE", "eu"),
+            ("RGAPI_KEY_ASIA", "asia"),
+            ("RGAPI_KEY_AMERICAS", "am"),
+        ]));
+        assert!(keys.is_ok());
+
+        assert!(cluster_keys(env(&[("RGAPI_KEY_EUROPE", "eu")])).is_err());
+    }
+}
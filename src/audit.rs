@@ -0,0 +1,81 @@
+use mongodb::bson::{doc, Document};
+
+/// Outcome of looking at one match in `process_match_id`, for the audit log. A match
+/// already stored (or negatively cached) is seen every cycle without anything changing,
+/// so it has no decision.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Decision {
+    /// New to us, about to be fetched
+    Discovered,
+    Stored,
+    /// Another task stored it between our check and insert
+    SkippedDuplicate,
+    /// Fetch failed, placeholder stored so it isn't fetched again for a while
+    DummyWritten,
+    /// Not found, but within `Config::not_found_grace` so retried later
+    NotFoundRetrying,
+    /// Fewer than `Config::min_ranked_to_store` ranked players
+    FilteredByElo,
+    /// Participant lookups over `Config::max_summoner_calls_per_cycle`, retried next cycle
+    Deferred,
+    /// Fetch failed but the match is within `Config::recent_match_window`, retried later
+    TooRecent,
+    /// Older than `Config::max_match_age`, placeholder stored without enriching it
+    TooOld,
+}
+
+impl Decision {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Decision::Discovered => "discovered",
+            Decision::Stored => "stored",
+            Decision::SkippedDuplicate => "skipped-duplicate",
+            Decision::DummyWritten => "dummy-written",
+            Decision::NotFoundRetrying => "not-found-retrying",
+            Decision::FilteredByElo => "filtered-by-elo",
+            Decision::Deferred => "deferred",
+            Decision::TooRecent => "too-recent",
+            Decision::TooOld => "too-old",
+        }
+    }
+}
+
+/// Audit log event, timestamps are added by the caller
+pub fn event(match_id: &str, region: &str, decision: Decision, reason: Option<&str>) -> Document {
+    let mut event = doc! {
+        "matchId": match_id,
+        "region": region,
+        "decision": decision.as_str(),
+    };
+    if let Some(reason) = reason {
+        event.insert("reason", reason);
+    }
+    event
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event() {
+        assert_eq!(
+            event("EUW1_1", "EUW1", Decision::Stored, None),
+            doc! {"matchId": "EUW1_1", "region": "EUW1", "decision": "stored"}
+        );
+        assert_eq!(
+            event(
+                "EUW1_1",
+                "EUW1",
+                Decision::FilteredByElo,
+                Some("2 ranked players")
+            ),
+            doc! {
+                "matchId": "EUW1_1",
+                "region": "EUW1",
+                "decision": "filtered-by-elo",
+                "reason": "2 ranked players",
+            }
+        );
+    }
+}
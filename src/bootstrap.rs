@@ -0,0 +1,85 @@
+use std::time::Duration;
+
+/// Whether a region task is still throttled after starting on empty caches, see
+/// `Config::bootstrap_slowdown`. Every lookup of the first cycles on a fresh database
+/// is a cache miss, so those cycles make the most API calls.
+#[derive(Debug, Default)]
+pub struct Bootstrap {
+    active: bool,
+    cycles: u32,
+}
+
+impl Bootstrap {
+    pub fn start() -> Bootstrap {
+        Bootstrap {
+            active: true,
+            cycles: 0,
+        }
+    }
+
+    pub fn active(&self) -> bool {
+        self.active
+    }
+
+    /// Count a completed cycle and its cache lookups. Bootstrap ends once a cycle's hit
+    /// rate reaches `min_hit_rate` or after `max_cycles` cycles; returns whether this
+    /// cycle ended it.
+    pub fn end_cycle(
+        &mut self,
+        hits: u64,
+        misses: u64,
+        min_hit_rate: f64,
+        max_cycles: u32,
+    ) -> bool {
+        if !self.active {
+            return false;
+        }
+        self.cycles += 1;
+        let lookups = hits + misses;
+        let warm = lookups > 0 && hits as f64 / lookups as f64 >= min_hit_rate;
+        if warm || self.cycles >= max_cycles {
+            self.active = false;
+            return true;
+        }
+        false
+    }
+}
+
+/// Summoners processed at once and the pacing between starting them, `slowdown` times
+/// slower than `concurrency` and `pacing`
+pub fn throttled(concurrency: usize, pacing: Duration, slowdown: u32) -> (usize, Duration) {
+    let slowdown = slowdown.max(1);
+    ((concurrency / slowdown as usize).max(1), pacing * slowdown)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_end_cycle() {
+        let mut bootstrap = Bootstrap::start();
+        // Cold, and a cycle without lookups isn't warm either
+        assert!(!bootstrap.end_cycle(10, 90, 0.5, 3));
+        assert!(!bootstrap.end_cycle(0, 0, 0.5, 3));
+        assert!(bootstrap.active());
+        assert!(bootstrap.end_cycle(60, 40, 0.5, 3));
+        assert!(!bootstrap.active());
+        assert!(!bootstrap.end_cycle(60, 40, 0.5, 3));
+
+        // Never warms up, ends after the last cycle
+        let mut bootstrap = Bootstrap::start();
+        assert!(!bootstrap.end_cycle(0, 100, 0.5, 2));
+        assert!(bootstrap.end_cycle(0, 100, 0.5, 2));
+
+        assert!(!Bootstrap::default().end_cycle(0, 100, 0.5, 2));
+    }
+
+    #[test]
+    fn test_throttled() {
+        let pacing = Duration::from_millis(100);
+        assert_eq!(throttled(10, pacing, 4), (2, Duration::from_millis(400)));
+        assert_eq!(throttled(10, pacing, 20), (1, Duration::from_secs(2)));
+        assert_eq!(throttled(10, pacing, 0), (10, pacing));
+    }
+}
@@ -0,0 +1,36 @@
+use chrono::{DateTime, Utc};
+use mongodb::bson::Document;
+
+/// Whether a cached document is recent enough to use instead of refetching. Independent
+/// of `_documentExpire`, which only decides when storage drops it. Documents without
+/// `_documentCreated` count as fresh.
+pub fn is_fresh(doc: &Document, now: DateTime<Utc>, max_age: Option<std::time::Duration>) -> bool {
+    let max_age = match max_age.map(chrono::Duration::from_std) {
+        Some(Ok(max_age)) => max_age,
+        // No limit, or one too large to matter
+        _ => return true,
+    };
+    match doc.get_datetime("_documentCreated") {
+        Ok(created) => now - *created <= max_age,
+        Err(_) => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+    use mongodb::bson::doc;
+
+    #[test]
+    fn test_is_fresh() {
+        let now = Utc.ymd(2021, 3, 1).and_hms(12, 0, 0);
+        let doc = doc! {"_documentCreated": now - Duration::hours(2)};
+        let hour = Some(std::time::Duration::from_secs(3600));
+        let day = Some(std::time::Duration::from_secs(86400));
+        assert!(is_fresh(&doc, now, None));
+        assert!(is_fresh(&doc, now, day));
+        assert!(!is_fresh(&doc, now, hour));
+        assert!(is_fresh(&doc! {}, now, hour));
+    }
+}
@@ -0,0 +1,122 @@
+use log::error;
+use std::future::Future;
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot, Mutex};
+
+use crate::error::{CrawlerError, Result};
+
+struct Job<T> {
+    match_id: String,
+    reply: oneshot::Sender<T>,
+}
+
+/// Match fetches of one major region cluster, queued in submission order and drained by
+/// a fixed pool of workers, so the cluster's concurrency doesn't depend on how many
+/// platform tasks are submitting. See `Config::match_fetch_workers`.
+pub struct ClusterQueue<T> {
+    sender: mpsc::UnboundedSender<Job<T>>,
+}
+
+impl<T: Send + 'static> ClusterQueue<T> {
+    /// Spawn `workers` tasks that each run `fetch` on one queued match id at a time
+    pub fn spawn<F, Fut>(workers: usize, fetch: F) -> ClusterQueue<T>
+    where
+        F: Fn(String) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = T> + Send,
+    {
+        let (sender, receiver) = mpsc::unbounded_channel::<Job<T>>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let fetch = Arc::new(fetch);
+        for worker in 0..workers.max(1) {
+            let receiver = receiver.clone();
+            let fetch = fetch.clone();
+            // Respawns the worker if a fetch panics, failing only that fetch's job
+            tokio::spawn(async move {
+                loop {
+                    let (receiver, fetch) = (receiver.clone(), fetch.clone());
+                    let ret = tokio::spawn(async move {
+                        loop {
+                            // The lock is only held while waiting for the next job
+                            let job = match receiver.lock().await.recv().await {
+                                Some(job) => job,
+                                None => return,
+                            };
+                            let result = fetch(job.match_id).await;
+                            // The submitter may have given up, e.g. its cycle timed out
+                            let _ = job.reply.send(result);
+                        }
+                    })
+                    .await;
+                    match ret {
+                        Ok(()) => return,
+                        Err(e) => error!("Match fetch worker {} failed, respawning: {}", worker, e),
+                    }
+                }
+            });
+        }
+        ClusterQueue { sender }
+    }
+
+    /// Queue a fetch and wait for a worker to complete it. Fails if the worker running it
+    /// panicked.
+    pub async fn fetch(&self, match_id: &str) -> Result<T> {
+        let (reply, result) = oneshot::channel();
+        let job = Job {
+            match_id: match_id.to_string(),
+            reply,
+        };
+        self.sender
+            .send(job)
+            .map_err(|_| CrawlerError::Internal("Match fetch workers stopped"))?;
+        result
+            .await
+            .map_err(|_| CrawlerError::Internal("Match fetch worker dropped the job"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_cluster_queue() {
+        let running = Arc::new(AtomicUsize::new(0));
+        let most_running = Arc::new(AtomicUsize::new(0));
+        let queue = {
+            let (running, most_running) = (running.clone(), most_running.clone());
+            ClusterQueue::spawn(2, move |match_id: String| {
+                let (running, most_running) = (running.clone(), most_running.clone());
+                async move {
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    most_running.fetch_max(now, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    format!("fetched {}", match_id)
+                }
+            })
+        };
+
+        let ids: Vec<String> = (0..6).map(|i| format!("EUW1_{}", i)).collect();
+        let results = futures::future::join_all(ids.iter().map(|id| queue.fetch(id))).await;
+        let results: Vec<String> = results.into_iter().map(Result::unwrap).collect();
+        // Each submitter gets its own result, with no more than two fetches at once
+        assert_eq!(results[4], "fetched EUW1_4");
+        assert_eq!(results.len(), 6);
+        assert_eq!(most_running.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_cluster_queue_worker_panic() {
+        let queue = ClusterQueue::spawn(1, |match_id: String| async move {
+            if match_id == "EUW1_bad" {
+                panic!("Unparsable match");
+            }
+            match_id
+        });
+        // The panicking fetch fails on its own and the respawned worker takes the next job
+        assert!(queue.fetch("EUW1_bad").await.is_err());
+        assert_eq!(queue.fetch("EUW1_1").await.unwrap(), "EUW1_1");
+    }
+}
@@ -0,0 +1,69 @@
+use chrono::{DateTime, Datelike, Duration, Utc};
+use riven::consts::Region;
+
+use crate::region_util::region_key;
+
+/// Collection a region task uses for `base`: the shared collection by default, or
+/// `<base>-<region>` when each region has its own, e.g. `matches-4-1-EUW`
+pub fn collection_name(base: &str, region: Region, per_region: bool) -> String {
+    if per_region {
+        format!(
+            "{}-{}",
+            base,
+            region_key(region).expect("unsupported region")
+        )
+    } else {
+        base.to_string()
+    }
+}
+
+/// Monthly partition of `base` holding matches played at `timestamp`,
+/// e.g. `matches-4-1-2024-01`
+pub fn partition_name(base: &str, timestamp: DateTime<Utc>) -> String {
+    format!("{}-{:04}-{:02}", base, timestamp.year(), timestamp.month())
+}
+
+/// Partitions a recently listed match can be in: this month's and last month's. Older
+/// matches are rare in a match list, and if one was already stored its insert still
+/// fails as a duplicate in its own partition.
+pub fn recent_partitions(base: &str, now: DateTime<Utc>) -> Vec<String> {
+    let last_month = now.with_day(1).unwrap() - Duration::days(1);
+    vec![partition_name(base, now), partition_name(base, last_month)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_partitions() {
+        let now = Utc.ymd(2024, 1, 31).and_hms(23, 59, 0);
+        assert_eq!(partition_name("matches-4-1", now), "matches-4-1-2024-01");
+        assert_eq!(
+            recent_partitions("matches-4-1-EUW", now),
+            vec!["matches-4-1-EUW-2024-01", "matches-4-1-EUW-2023-12"]
+        );
+        let now = Utc.ymd(2024, 3, 1).and_hms(0, 0, 0);
+        assert_eq!(
+            recent_partitions("matches-4-1", now),
+            vec!["matches-4-1-2024-03", "matches-4-1-2024-02"]
+        );
+    }
+
+    #[test]
+    fn test_collection_name() {
+        assert_eq!(
+            collection_name("matches-4-1", Region::EUW, false),
+            "matches-4-1"
+        );
+        assert_eq!(
+            collection_name("matches-4-1", Region::EUW, true),
+            "matches-4-1-EUW"
+        );
+        assert_eq!(
+            collection_name("league-4-1", Region::OCE, true),
+            "league-4-1-OCE"
+        );
+    }
+}
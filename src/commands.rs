@@ -0,0 +1,636 @@
+use anyhow::{anyhow, bail, Context};
+use chrono::Utc;
+use futures::stream::StreamExt;
+use mongodb::bson::document::Document;
+use mongodb::bson::{doc, from_document, Bson};
+use mongodb::options::{FindOptions, ReplaceOptions};
+use riven::consts::Region;
+use riven::models::tft_summoner_v1::Summoner;
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+use crate::error::CrawlerError;
+use crate::interning::{self, StringTable};
+use crate::match_doc::{match_fields, raw_match, strip_raw_fields, SCHEMA_VERSION};
+use crate::region_util::major_region;
+use crate::typed_match::{TypedMatch, TypedMatchMode};
+use crate::{Main, Shared, TftQueue, CRAWL_STATE_COLLECTION_NAME, STRING_TABLE_COLLECTION_NAME};
+
+// Source documents between `migrate` checkpoints
+const MIGRATE_CHECKPOINT_INTERVAL: u64 = 1000;
+
+/// `tft-stat reprocess --match EUW1_12345 [--region EUROPE]`
+///
+/// Delete any stored document for the match, run it through `process_match_id`
+/// again and print the resulting document, with interned trait names decoded. The
+/// major region is the one serving the match's platform; `--region` must agree with it.
+pub async fn reprocess(shared: &Shared, args: &[String]) -> anyhow::Result<()> {
+    let match_id = arg_value(args, "--match")?;
+    let region = match_platform(&match_id)?;
+    let region_major = match_major_region(region, arg_value(args, "--region").ok().as_deref())?;
+
+    let main = Main::new(shared, TftQueue::Ranked, region, region_major);
+    let mut deleted_count = 0;
+    for name in main.match_lookup_collections(Utc::now()) {
+        let deleted = shared
+            .db
+            .collection::<Document>(&name)
+            .delete_one(doc! {"_id": &match_id}, None)
+            .await
+            .context("Error deleting existing document")?;
+        deleted_count += deleted.deleted_count;
+    }
+    println!("Deleted {} existing document(s)", deleted_count);
+
+    let ret = main.process_match_id(&match_id, None).await?;
+    println!("process_match_id returned {}", ret);
+
+    let mut stored = find_stored(&main, &match_id).await?;
+    if shared.config.intern_traits {
+        let names = interning::load_names(&shared.db, STRING_TABLE_COLLECTION_NAME).await?;
+        if let Ok(player_data) = stored.get_array_mut("_aggregatedPlayerInfo") {
+            interning::decode_traits(player_data, &names);
+        }
+    }
+    println!(
+        "{}",
+        serde_json::to_string_pretty(
+            &mongodb::bson::Bson::Document(stored).into_relaxed_extjson()
+        )?
+    );
+    Ok(())
+}
+
+/// `tft-stat migrate --from matches-3-5 --to matches-4-1 [--summary-only] [--dry-run]`
+///
+/// Copy the stored matches of one collection into another in the current schema, e.g.
+/// to keep an earlier set's derived fields when rotating collection suffixes. Fields
+/// derived from the match alone are recomputed where the raw match was kept, and
+/// `--summary-only` drops it. Dummies are skipped, as are documents still missing a
+/// derived field; matches already in the destination are left alone. Progress is
+/// checkpointed in the crawl state collection so a rerun resumes where it stopped.
+/// `--dry-run` writes nothing and only reports what would be copied.
+pub async fn migrate(shared: &Shared, args: &[String]) -> anyhow::Result<()> {
+    let from = arg_value(args, "--from")?;
+    let to = arg_value(args, "--to")?;
+    let summary_only = has_flag(args, "--summary-only");
+    let dry_run = has_flag(args, "--dry-run");
+
+    let state = shared
+        .db
+        .collection::<Document>(CRAWL_STATE_COLLECTION_NAME);
+    let checkpoint_id = format!("migrate-{}-{}", from, to);
+    let resume_after = state
+        .find_one(doc! {"_id": &checkpoint_id}, None)
+        .await
+        .context("Error reading migration checkpoint")?
+        .and_then(|checkpoint| checkpoint.get_str("lastId").ok().map(str::to_string));
+    let mut filter = doc! {"_aggregatedPlayerInfo": {"$exists": true}};
+    if let Some(last_id) = &resume_after {
+        println!("Resuming after {}", last_id);
+        filter.insert("_id", doc! {"$gt": last_id});
+    }
+    let mut cursor = shared
+        .db
+        .collection::<Document>(&from)
+        .find(filter, FindOptions::builder().sort(doc! {"_id": 1}).build())
+        .await
+        .context("Error reading source collection")?;
+    let destination = shared.db.collection::<Document>(&to);
+
+    let (mut read, mut written, mut existing, mut incomplete) = (0u64, 0u64, 0u64, 0u64);
+    let mut last_id = None;
+    while let Some(doc) = cursor.next().await {
+        let doc = doc.context("Error reading source collection")?;
+        let id = doc.get_str("_id").unwrap_or_default().to_string();
+        read += 1;
+        match migrated_doc(doc, summary_only) {
+            Err(missing) => {
+                println!("Skipping {}, missing {}", id, missing.join(", "));
+                incomplete += 1;
+            }
+            Ok(_) if dry_run => written += 1,
+            Ok(doc) => match destination.insert_one(doc, None).await {
+                Ok(_) => written += 1,
+                Err(e) => {
+                    let e = CrawlerError::db("Error writing destination collection", e);
+                    if !e.is_duplicate_key() {
+                        return Err(e.into());
+                    }
+                    existing += 1;
+                }
+            },
+        }
+        last_id = Some(id);
+        if read % MIGRATE_CHECKPOINT_INTERVAL == 0 {
+            if !dry_run {
+                write_checkpoint(shared, &checkpoint_id, last_id.as_deref()).await?;
+            }
+            println!("{} read, {} written", read, written);
+        }
+    }
+    if !dry_run {
+        write_checkpoint(shared, &checkpoint_id, last_id.as_deref()).await?;
+    }
+    println!(
+        "{}{} of {} matches copied to {}, {} already there, {} skipped as incomplete",
+        if dry_run { "Dry run: " } else { "" },
+        written,
+        read,
+        to,
+        existing,
+        incomplete
+    );
+    Ok(())
+}
+
+/// `tft-stat diff --a matches-4-1 --b matches-4-1-backup [--ids]`
+///
+/// Compare the match ids stored in two collections, e.g. a backup or a second crawler
+/// instance against the primary. Both are streamed in `_id` order and merged, so
+/// neither is held in memory. Reports how many ids each has that the other lacks, and
+/// the dummy and full documents in each. `--ids` also prints every differing id.
+pub async fn diff(shared: &Shared, args: &[String]) -> anyhow::Result<()> {
+    let a = arg_value(args, "--a")?;
+    let b = arg_value(args, "--b")?;
+    let print_ids = has_flag(args, "--ids");
+
+    // Dummies are told apart by `_matchTimestamp`, which only full documents have
+    let options = FindOptions::builder()
+        .sort(doc! {"_id": 1})
+        .projection(doc! {"_id": 1, "_matchTimestamp": 1})
+        .build();
+    let mut cursor_a = shared
+        .db
+        .collection::<Document>(&a)
+        .find(None, options.clone())
+        .await
+        .with_context(|| format!("Error reading {}", a))?;
+    let mut cursor_b = shared
+        .db
+        .collection::<Document>(&b)
+        .find(None, options)
+        .await
+        .with_context(|| format!("Error reading {}", b))?;
+
+    let mut summary = DiffSummary::default();
+    let mut next_a = next_stored_id(&mut cursor_a, &a).await?;
+    let mut next_b = next_stored_id(&mut cursor_b, &b).await?;
+    loop {
+        let ordering = match (&next_a, &next_b) {
+            (None, None) => break,
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            // MongoDB orders string ids bytewise, as `str` does
+            (Some((id_a, _)), Some((id_b, _))) => id_a.cmp(id_b),
+        };
+        if ordering != Ordering::Greater {
+            let (id, dummy) = next_a.take().unwrap();
+            summary.count_a(dummy);
+            if ordering == Ordering::Less {
+                summary.only_a += 1;
+                if print_ids {
+                    println!("only in {}: {}", a, id);
+                }
+            }
+            next_a = next_stored_id(&mut cursor_a, &a).await?;
+        }
+        if ordering != Ordering::Less {
+            let (id, dummy) = next_b.take().unwrap();
+            summary.count_b(dummy);
+            if ordering == Ordering::Greater {
+                summary.only_b += 1;
+                if print_ids {
+                    println!("only in {}: {}", b, id);
+                }
+            }
+            next_b = next_stored_id(&mut cursor_b, &b).await?;
+        }
+    }
+    println!("{}", summary.report(&a, &b));
+    Ok(())
+}
+
+// Next `_id` of a `diff` stream and whether its document is a dummy
+async fn next_stored_id(
+    cursor: &mut mongodb::Cursor<Document>,
+    name: &str,
+) -> anyhow::Result<Option<(String, bool)>> {
+    let doc = match cursor.next().await {
+        Some(doc) => doc.with_context(|| format!("Error reading {}", name))?,
+        None => return Ok(None),
+    };
+    let id = doc
+        .get_str("_id")
+        .map_err(|_| anyhow!("Non-string _id in {}: {:?}", name, doc.get("_id")))?;
+    Ok(Some((id.to_string(), !doc.contains_key("_matchTimestamp"))))
+}
+
+// Counts for `diff`, a and b being the two collections
+#[derive(Debug, Default, PartialEq)]
+struct DiffSummary {
+    only_a: u64,
+    only_b: u64,
+    dummies_a: u64,
+    full_a: u64,
+    dummies_b: u64,
+    full_b: u64,
+}
+
+impl DiffSummary {
+    fn count_a(&mut self, dummy: bool) {
+        if dummy {
+            self.dummies_a += 1;
+        } else {
+            self.full_a += 1;
+        }
+    }
+
+    fn count_b(&mut self, dummy: bool) {
+        if dummy {
+            self.dummies_b += 1;
+        } else {
+            self.full_b += 1;
+        }
+    }
+
+    fn report(&self, a: &str, b: &str) -> String {
+        format!(
+            "{}: {} full, {} dummies, {} not in {}\n{}: {} full, {} dummies, {} not in {}",
+            a,
+            self.full_a,
+            self.dummies_a,
+            self.only_a,
+            b,
+            b,
+            self.full_b,
+            self.dummies_b,
+            self.only_b,
+            a
+        )
+    }
+}
+
+// Record the last source `_id` a migration has handled
+async fn write_checkpoint(
+    shared: &Shared,
+    checkpoint_id: &str,
+    last_id: Option<&str>,
+) -> anyhow::Result<()> {
+    let last_id = match last_id {
+        Some(last_id) => last_id,
+        None => return Ok(()),
+    };
+    shared
+        .db
+        .collection::<Document>(CRAWL_STATE_COLLECTION_NAME)
+        .replace_one(
+            doc! {"_id": checkpoint_id},
+            doc! {"_id": checkpoint_id, "lastId": last_id},
+            ReplaceOptions::builder().upsert(true).build(),
+        )
+        .await
+        .context("Error writing migration checkpoint")?;
+    Ok(())
+}
+
+// A stored match in the current schema, or the derived fields it is still missing.
+// Every field added up to `SCHEMA_VERSION` comes from the match alone, so a document
+// with its raw match is fully up to date once `match_fields` are recomputed.
+fn migrated_doc(mut doc: Document, summary_only: bool) -> Result<Document, Vec<&'static str>> {
+    if let Some(game) = raw_match(&doc) {
+        doc.extend(match_fields(&game));
+        doc.insert("_schemaVersion", SCHEMA_VERSION);
+    }
+    if summary_only {
+        strip_raw_fields(&mut doc);
+    }
+    let missing = missing_fields(&doc, summary_only);
+    if missing.is_empty() {
+        Ok(doc)
+    } else {
+        Err(missing)
+    }
+}
+
+/// `tft-stat selftest [--platform EUW]`
+///
+/// Smoke test of the whole pipeline for one recent match: top player, match list,
+/// match, enrichment and the stored document, checked for every derived field. Writes
+/// only to `*-selftest` collections, which are dropped afterwards. Fails on any error.
+pub async fn selftest(shared: &Shared, args: &[String]) -> anyhow::Result<()> {
+    let region: Region = match arg_value(args, "--platform") {
+        Ok(value) => value.parse().map_err(|_| anyhow!("Invalid --platform"))?,
+        Err(_) => Region::EUW,
+    };
+    let region_major =
+        major_region(region).ok_or_else(|| anyhow!("Platform {:?} is not supported", region))?;
+
+    // Every collection gets the suffix, including those of optional sinks
+    let shared = Shared {
+        string_table: Arc::new(StringTable::new(&format!(
+            "{}{}",
+            STRING_TABLE_COLLECTION_NAME, SELFTEST_SUFFIX
+        ))),
+        collection_suffix: SELFTEST_SUFFIX.to_string(),
+        ..shared.clone()
+    };
+    let main = Main::new(&shared, TftQueue::Ranked, region, region_major);
+
+    let ret = run_selftest(&main).await;
+    // Monthly match partitions have the suffix before their month
+    let collections = shared
+        .db
+        .list_collection_names(doc! {"name": {"$regex": SELFTEST_SUFFIX}})
+        .await
+        .context("Error listing selftest collections")?;
+    for name in &collections {
+        if let Err(e) = shared.db.collection::<Document>(name).drop(None).await {
+            eprintln!("Error dropping {}: {}", name, e);
+        }
+    }
+    ret?;
+    println!("Selftest passed");
+    Ok(())
+}
+
+const SELFTEST_SUFFIX: &str = "-selftest";
+
+async fn run_selftest(main: &Main) -> anyhow::Result<()> {
+    let (player, match_id) = recent_top_match(main).await?;
+    println!("Processing {} of {}", match_id, player.name);
+
+    let ret = main.process_match_id(&match_id, Some(&player)).await?;
+    if ret != 1 {
+        bail!("process_match_id returned {}, expected a stored match", ret);
+    }
+    let stored = find_stored(main, &match_id).await?;
+    // No raw match in place of `_typedMatch` either
+    let typed_only = main.config.typed_match == TypedMatchMode::Instead;
+    let missing = missing_fields(&stored, main.config.summary_only || typed_only);
+    if !missing.is_empty() {
+        bail!("Stored document is missing {}", missing.join(", "));
+    }
+    if main.config.typed_match != TypedMatchMode::Off {
+        let typed = stored
+            .get_document("_typedMatch")
+            .context("Stored document has no _typedMatch")?;
+        from_document::<TypedMatch>(typed.clone()).context("Error reading _typedMatch")?;
+    }
+    Ok(())
+}
+
+/// The id of the latest match played by the platform's top challenger, and that player.
+/// Straight from the API, bypassing the caches and the crawler's limits.
+pub async fn recent_top_match(main: &Main) -> anyhow::Result<(Summoner, String)> {
+    let league = main
+        .api
+        .tft_league_v1()
+        .get_challenger_league(main.region)
+        .await
+        .context("Error fetching challenger league")?;
+    let summoner_id = &league
+        .entries
+        .first()
+        .ok_or_else(|| anyhow!("Challenger league is empty"))?
+        .summoner_id;
+    let player = main
+        .api
+        .tft_summoner_v1()
+        .get_by_summoner_id(main.region, summoner_id)
+        .await
+        .context("Error fetching summoner")?;
+    let match_ids = main
+        .api
+        .tft_match_v1()
+        .get_match_ids_by_puuid(main.region_major, &player.puuid, Some(1))
+        .await
+        .context("Error fetching match list")?;
+    let match_id = match_ids
+        .first()
+        .ok_or_else(|| anyhow!("{} has no matches", player.name))?
+        .clone();
+    Ok((player, match_id))
+}
+
+// The stored document of a match, from whichever collection it was written to
+async fn find_stored(main: &Main, match_id: &str) -> anyhow::Result<Document> {
+    for name in main.match_lookup_collections(Utc::now()) {
+        let stored = main
+            .db
+            .collection::<Document>(&name)
+            .find_one(doc! {"_id": match_id}, None)
+            .await
+            .context("Error reading back document")?;
+        if let Some(stored) = stored {
+            return Ok(stored);
+        }
+    }
+    Err(anyhow!("No document stored for {}", match_id))
+}
+
+// Derived fields every stored match should have, and the raw match unless summary only
+fn missing_fields(doc: &Document, summary_only: bool) -> Vec<&'static str> {
+    let mut expected = vec![
+        "_region",
+        "_tftSet",
+        "_patch",
+        "_documentCreated",
+        "_matchTimestamp",
+        "_documentExpire",
+        "_aggregatedPlayerInfo",
+        "_avgElo",
+        "_avgEloText",
+        "_lobbyHash",
+        "_schemaVersion",
+    ];
+    if !summary_only {
+        expected.extend(&["metadata", "info"]);
+    }
+    let mut missing: Vec<&str> = expected
+        .into_iter()
+        .filter(|key| matches!(doc.get(key), None | Some(Bson::Null)))
+        .collect();
+    // One entry per participant
+    let participants = doc
+        .get_document("info")
+        .and_then(|info| info.get_array("participants"))
+        .map(Vec::len);
+    let player_data = doc.get_array("_aggregatedPlayerInfo").map(Vec::len);
+    if let (Ok(participants), Ok(player_data)) = (participants, player_data) {
+        if participants != player_data {
+            missing.push("_aggregatedPlayerInfo entries");
+        }
+    }
+    missing
+}
+
+// Value following `name` in the arguments
+fn arg_value(args: &[String], name: &str) -> anyhow::Result<String> {
+    args.iter()
+        .position(|a| a == name)
+        .and_then(|i| args.get(i + 1))
+        .cloned()
+        .ok_or_else(|| anyhow!("Missing argument {}", name))
+}
+
+// Whether a valueless flag is present in the arguments
+fn has_flag(args: &[String], name: &str) -> bool {
+    args.iter().any(|a| a == name)
+}
+
+// Platform of a match from its id prefix, e.g. EUW1_12345 -> EUW
+fn match_platform(match_id: &str) -> anyhow::Result<Region> {
+    let prefix = match match_id.split_once('_') {
+        Some((prefix, _)) => prefix,
+        None => bail!("Match id {} has no platform prefix", match_id),
+    };
+    prefix
+        .parse()
+        .map_err(|_| anyhow!("Unknown platform {} in match id", prefix))
+}
+
+// The major region serving `platform`, checked against a `--region` argument if given
+fn match_major_region(platform: Region, arg: Option<&str>) -> anyhow::Result<Region> {
+    let region_major = major_region(platform)
+        .ok_or_else(|| anyhow!("Platform {:?} is not supported", platform))?;
+    if let Some(value) = arg {
+        let requested: Region = value.parse().map_err(|_| anyhow!("Invalid --region"))?;
+        if requested != region_major {
+            bail!(
+                "--region {:?} does not serve platform {:?}, which is served by {:?}",
+                requested,
+                platform,
+                region_major
+            );
+        }
+    }
+    Ok(region_major)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_match_platform() {
+        assert_eq!(match_platform("EUW1_5123456789").unwrap(), Region::EUW);
+        assert_eq!(match_platform("KR_5123456789").unwrap(), Region::KR);
+        assert_eq!(match_platform("OC1_123").unwrap(), Region::OCE);
+        assert!(match_platform("5123456789").is_err());
+        assert!(match_platform("XX9_1").is_err());
+    }
+
+    #[test]
+    fn test_match_major_region() {
+        assert_eq!(
+            match_major_region(Region::EUW, None).unwrap(),
+            Region::EUROPE
+        );
+        assert_eq!(
+            match_major_region(Region::KR, Some("ASIA")).unwrap(),
+            Region::ASIA
+        );
+        assert!(match_major_region(Region::EUW, Some("AMERICAS")).is_err());
+        assert!(match_major_region(Region::EUW, Some("NOWHERE")).is_err());
+    }
+
+    #[test]
+    fn test_missing_fields() {
+        let mut stored = doc! {
+            "_region": "EUW1",
+            "_tftSet": 5,
+            "_patch": "11.1",
+            "_documentCreated": "",
+            "_matchTimestamp": "",
+            "_documentExpire": "",
+            "_aggregatedPlayerInfo": [{}, {}],
+            "_avgElo": 2400,
+            "_avgEloText": "MASTER I 0LP",
+            "_lobbyHash": "",
+            "_schemaVersion": 2,
+        };
+        assert!(missing_fields(&stored, true).is_empty());
+        assert_eq!(missing_fields(&stored, false), vec!["metadata", "info"]);
+
+        stored.insert("metadata", doc! {});
+        stored.insert("info", doc! {"participants": [{}, {}, {}]});
+        stored.insert("_patch", Bson::Null);
+        assert_eq!(
+            missing_fields(&stored, false),
+            vec!["_patch", "_aggregatedPlayerInfo entries"]
+        );
+    }
+
+    #[test]
+    fn test_arg_value() {
+        let args: Vec<String> = vec!["--region", "EUROPE", "--match", "EUW1_1"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        assert_eq!(arg_value(&args, "--match").unwrap(), "EUW1_1");
+        assert_eq!(arg_value(&args, "--region").unwrap(), "EUROPE");
+        assert!(arg_value(&args, "--other").is_err());
+        assert!(has_flag(&args, "--region"));
+        assert!(!has_flag(&args, "--dry-run"));
+    }
+
+    #[test]
+    fn test_diff_summary() {
+        let mut summary = DiffSummary::default();
+        summary.count_a(false);
+        summary.count_a(true);
+        summary.only_a += 1;
+        summary.count_b(false);
+        assert_eq!(
+            summary.report("matches-4-1", "backup"),
+            "matches-4-1: 1 full, 1 dummies, 1 not in backup\n\
+             backup: 1 full, 0 dummies, 0 not in matches-4-1"
+        );
+    }
+
+    #[test]
+    fn test_migrated_doc() {
+        let summary = doc! {
+            "_id": "EUW1_1",
+            "_region": "EUW1",
+            "_tftSet": 3,
+            "_patch": "10.5",
+            "_documentCreated": "",
+            "_matchTimestamp": "",
+            "_documentExpire": "",
+            "_aggregatedPlayerInfo": [{}],
+            "_avgElo": 2400,
+            "_avgEloText": "MASTER I 0LP",
+        };
+        // No raw match to derive the lobby hash from
+        assert_eq!(
+            migrated_doc(summary.clone(), true),
+            Err(vec!["_lobbyHash", "_schemaVersion"])
+        );
+
+        let mut full = summary;
+        full.insert(
+            "metadata",
+            doc! {"data_version": "5", "match_id": "EUW1_1", "participants": ["a"]},
+        );
+        full.insert(
+            "info",
+            doc! {
+                "game_datetime": 1600000000000i64,
+                "game_length": 2000.5,
+                "game_version": "Version 10.6.313.1234 (Mar 20 2020/12:00:00) [PUBLIC]",
+                "participants": [],
+                "queue_id": 1100,
+                "tft_set_number": 3,
+            },
+        );
+        // Stale derived fields are recomputed
+        full.insert("_patch", Bson::Null);
+        let migrated = migrated_doc(full, true).unwrap();
+        assert_eq!(migrated.get_str("_patch").unwrap(), "10.6");
+        assert_eq!(migrated.get_i32("_schemaVersion").unwrap(), SCHEMA_VERSION);
+        assert!(migrated.contains_key("_lobbyHash"));
+        assert!(!migrated.contains_key("info"));
+    }
+}
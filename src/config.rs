@@ -0,0 +1,790 @@
+use std::num::NonZeroUsize;
+use std::str::FromStr;
+
+use crate::db_policy::DbErrorPolicy;
+use crate::endpoints::Endpoint;
+use crate::fair_share::RegionWeights;
+use crate::match_doc::RawFieldFilter;
+use crate::numeric_league_util::{ApexCutoffs, EloRounding};
+use crate::participant_info::EnrichmentLevel;
+use crate::rate_limit::RateLimit;
+use crate::region_pacing::RegionPacing;
+use crate::scan::ScanConfig;
+use crate::set_check::SetCheck;
+use crate::typed_match::TypedMatchMode;
+use std::time::Duration;
+
+/// Runtime options, read from environment variables at startup.
+/// Every option has a default so a bare `RGAPI_KEY` + `DB_CONNECTION_STRING`
+/// deployment behaves as before.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// STORE_ISO_TIMESTAMPS: alongside each BSON DateTime field (`_matchTimestamp`,
+    /// `_documentCreated`, `_documentExpire`) also store an ISO-8601 string copy
+    /// suffixed with `Iso`. The DateTime fields are kept since the TTL index needs them.
+    pub store_iso_timestamps: bool,
+    /// MAX_CONCURRENT_WRITES: upper bound on MongoDB writes in flight across all
+    /// region tasks. When MongoDB is slow, tasks wait for a slot, which slows the
+    /// crawl down instead of letting pending inserts pile up in memory. Must be positive.
+    pub max_concurrent_writes: usize,
+    /// TAG_DISCOVERED_VIA: stamp stored matches with `_discoveredVia`, the summonerId
+    /// and puuid of the top player whose match history surfaced the match.
+    pub tag_discovered_via: bool,
+    /// RGAPI_KEY_TIER: `development` or `production`, picks the pacing defaults.
+    pub key_tier: KeyTier,
+    /// SUMMONER_PACING_MS: delay between launching each summoner in a cycle.
+    /// Defaults to `key_tier.default_summoner_pacing()`.
+    pub summoner_pacing: Duration,
+    /// REGION_PACING_MS: summoner pacing of particular platforms, e.g. `EUNE=3000` to
+    /// give EUW more of the EUROPE cluster. Each must be positive; unlisted platforms use
+    /// SUMMONER_PACING_MS.
+    pub region_pacing: RegionPacing,
+    /// BOOTSTRAP_SLOWDOWN: when a region task starts with empty summoner and league caches,
+    /// process this many times fewer summoners at once, with this many times the pacing,
+    /// until a cycle's cache hit rate reaches `bootstrap_min_hit_rate` or
+    /// `bootstrap_max_cycles` cycles have completed. Off when unset.
+    pub bootstrap_slowdown: Option<u32>,
+    /// BOOTSTRAP_MIN_HIT_RATE: summoner and league cache hit rate, between 0 and 1, that
+    /// ends bootstrap mode. Defaults to 0.5.
+    pub bootstrap_min_hit_rate: f64,
+    /// BOOTSTRAP_MAX_CYCLES: cycles after which bootstrap mode ends regardless. Defaults to 3.
+    pub bootstrap_max_cycles: u32,
+    /// SUMMARY_ONLY: store only the derived fields of a match (`_avgElo`, `_avgEloText`,
+    /// `_aggregatedPlayerInfo`, timestamps, `_region`, `_tftSet`, `_patch`), not the raw match.
+    pub summary_only: bool,
+    /// TYPED_MATCH: `off`, `alongside` or `instead`. Also store `_typedMatch`, the match in
+    /// the crate's own `typed_match::TypedMatch` shape, next to the raw match or in place of
+    /// it. Converted from the raw response, so a match whose schema drifted gets a dummy
+    /// instead. Without the raw match, `instead` documents are like SUMMARY_ONLY ones to
+    /// `raw_match` and the migrations, which leave them alone. Off by default.
+    pub typed_match: TypedMatchMode,
+    /// MAX_DOCUMENT_BYTES: a match document larger than this (encoded as BSON) is
+    /// stored as a summary instead, see `summary_only`. MongoDB rejects documents over 16MB.
+    pub max_document_bytes: usize,
+    /// LEAGUE_PAGE_LOOKAHEAD: number of league-entry pages requested concurrently when
+    /// scanning a division. 1 is strictly sequential; larger values may request up to
+    /// `n - 1` pages past the end.
+    pub league_page_lookahead: usize,
+    /// CRAWL_SEED: seed for all randomness (e.g. retry jitter), so runs are reproducible.
+    /// Each region task derives its own seed from this one. Unset means entropy-seeded.
+    pub crawl_seed: Option<u64>,
+    /// PERSIST_LADDER_RANKINGS: upsert the rankings seen while scanning paginated league
+    /// pages into the league cache, so most participant lookups skip the API.
+    pub persist_ladder_rankings: bool,
+    /// LP_GAIN_TRACKING: when persisting ladder rankings, compare each player with their
+    /// previous cached ranking and record the games played and elo gained in between in
+    /// `lp-changes-4-1`, see `lp_change`. Needs `persist_ladder_rankings`, so covers the
+    /// paginated tiers only.
+    pub lp_gain_tracking: bool,
+    /// LP_CHANGES_RETENTION_DAYS: how long `lp_gain_tracking` keeps each recorded change.
+    /// 30 days by default.
+    pub lp_changes_retention: Duration,
+    /// RANK_DISTRIBUTIONS: from each scanned tier and division, record the LP distribution
+    /// and top percent cutoffs in `rank-distributions-4-1`, see `rank_distribution`.
+    pub rank_distributions: bool,
+    /// APEX_LP_CUTOFFS: `<grandmaster LP>,<challenger LP>`. Label apex `_avgEloText` by
+    /// the average LP against these cutoffs, rather than by counting players per apex tier.
+    pub apex_lp_cutoffs: Option<ApexCutoffs>,
+    /// LIVE_APEX_CUTOFFS: label apex `_avgEloText` against each platform's current cutoffs,
+    /// the lowest LP in its grandmaster and challenger leagues as of the last ladder scan.
+    /// Until both are scanned, or if the scan leaves them out, APEX_LP_CUTOFFS applies.
+    pub live_apex_cutoffs: bool,
+    /// ELO_ROUNDING: `truncate`, `half-up` or `nearest` (halves to even), how `_avgElo`
+    /// and the `_avgEloText` average are rounded. Defaults to `nearest`.
+    pub elo_rounding: EloRounding,
+    /// OMIT_APEX_DIVISION: label apex averages without a division, e.g. `CHALLENGER 535LP`
+    /// rather than `CHALLENGER I 535LP`. Off by default, keeping the labels already stored.
+    pub omit_apex_division: bool,
+    /// DIRECT_BSON: serialize the raw match straight to BSON instead of via a
+    /// `serde_json::Value`, saving a traversal and copy of every match. Falls back to the
+    /// JSON path if the direct conversion fails. On by default.
+    pub direct_bson: bool,
+    /// PARTIAL_ENRICHMENT: when a participant's summoner lookup fails for
+    /// a non-transient reason, store them as an unranked placeholder and flag the match
+    /// `_partialEnrichment`, instead of dropping the whole match. On by default.
+    pub partial_enrichment: bool,
+    /// PARTICIPANT_RETRIES, PARTICIPANT_RETRY_BACKOFF_MS: times a participant's summoner or
+    /// league lookup is retried on a transient API error (429 or 5xx) before it counts as
+    /// failed, waiting the backoff and doubling it after each attempt. Separate from the
+    /// cycle retry budget.
+    pub participant_retries: u32,
+    pub participant_retry_backoff: Duration,
+    /// MAX_RETRY_AFTER_MS: longest `Retry-After` a retry loop waits out after a 429.
+    /// A longer requested wait gives up on the call instead, like a non-retryable error.
+    /// See `error::retry_wait`.
+    pub max_retry_after: Duration,
+    /// SCAN_TIERS: tiers and divisions crawled by the ranked tasks, e.g. `APEX` for
+    /// challenger/grandmaster/master only, or `APEX,DIAMOND I,DIAMOND II`. See `ScanConfig`.
+    pub scan: ScanConfig,
+    /// DB_ERROR_POLICY: `fail-fast` (default) gives up on the current match or summoner
+    /// at the first MongoDB error; `retry-then-skip` first retries transient errors
+    /// (network, server selection, retryable labels) a few times with backoff.
+    /// The driver's own retryable reads/writes run underneath either policy. A retried
+    /// insert whose first attempt did reach the server then counts as already stored.
+    pub db_error_policy: DbErrorPolicy,
+    /// ENRICHMENT_LEVEL: `minimal`, `standard` or `full`, what each `_aggregatedPlayerInfo`
+    /// entry holds, see `participant_info::EnrichmentLevel`. `standard` by default.
+    pub enrichment_level: EnrichmentLevel,
+    /// STORE_COMPANIONS: add each participant's Little Legend (`species`, `contentId`)
+    /// to their `_aggregatedPlayerInfo` entry, when the match has it, whatever
+    /// `enrichment_level`. Always stored at `full`.
+    pub store_companions: bool,
+    /// INTERN_TRAITS: store the trait names of `_aggregatedPlayerInfo` entries (at `full`
+    /// `enrichment_level`) as `nameId`, an integer id from the `string-table-4-1`
+    /// collection, instead of the name itself. See `interning::decode_traits` for reading
+    /// them back.
+    pub intern_traits: bool,
+    /// RANK_OBSERVED_AT: add `_rankObservedAt` to each `_aggregatedPlayerInfo` entry, when
+    /// the league entry its rank comes from was fetched. Ranks are current ones, not the
+    /// ones at match time, so for backfilled matches this can be long after `_matchTimestamp`.
+    pub rank_observed_at: bool,
+    /// PER_REGION_COLLECTIONS: give each region its own matches, summoner and league
+    /// collections, e.g. `matches-4-1-EUW`, so retention and backups can differ per region.
+    /// Each collection needs its own `_documentExpire` TTL index, see `ttl_check`.
+    pub per_region_collections: bool,
+    /// SUMMONER_CACHE_MAX_AGE_SECS: refetch a cached summoner older than this, even if
+    /// it hasn't expired yet. Unset means any unexpired entry is used.
+    pub summoner_cache_max_age: Option<Duration>,
+    /// LEAGUE_CACHE_MAX_AGE_SECS: as `summoner_cache_max_age`, for cached league entries
+    pub league_cache_max_age: Option<Duration>,
+    /// META_STATS: keep running placement counts (games, wins, top 4s and each placement)
+    /// per TFT set and player tier in the `meta-stats-4-1` collection, updated as matches are stored.
+    pub meta_stats: bool,
+    /// CATCH_UP_GAP_SECS: if a region task last finished a cycle longer ago than this
+    /// (e.g. the crawler was down), its next cycle fetches `catch_up_match_count`
+    /// match ids per player instead of the usual 10.
+    pub catch_up_gap: Duration,
+    /// MAX_MATCH_AGE_HOURS: skip matches played longer ago than this, storing a dummy
+    /// instead. A fetched match shows its age; listed matches numbered at or below one
+    /// found too old are skipped without fetching, see `match_age::TooOld`. Must be
+    /// positive, an age too long to represent is as good as unlimited. Unlimited when
+    /// unset.
+    pub max_match_age: Option<chrono::Duration>,
+    /// CATCH_UP_MATCH_COUNT: match ids per player fetched by a catch-up cycle
+    pub catch_up_match_count: i32,
+    /// OLDEST_MATCHES_FIRST: work through each player's match list oldest first rather
+    /// than in Riot's newest-first order, so a crash or the new-match cap leaves the
+    /// newest matches unprocessed instead of a gap behind them.
+    pub oldest_matches_first: bool,
+    /// MIN_RANKED_TO_STORE: matches with fewer ranked participants than this are recorded
+    /// as a dummy document only, instead of being stored in full. Unrelated to how
+    /// `_avgElo` is averaged, which only ever needs one ranked participant.
+    pub min_ranked_to_store: usize,
+    /// STORE_PID_HASHES: add `_pidHash`, a 16 hex char hash of the puuid (see
+    /// `participant_info::pid_hash`), to each `_aggregatedPlayerInfo` entry as a short join key.
+    pub store_pid_hashes: bool,
+    /// STORE_PLACEMENTS: add `_placements`, the participants in finishing order with their
+    /// rank (see `participant_info::placements`), for queries on results without going
+    /// through `_aggregatedPlayerInfo`. Identified by `pidHash` under STORE_PID_HASHES.
+    pub store_placements: bool,
+    /// META_FINGERPRINT: add `_metaFingerprint`, the sorted (primary trait, carry unit)
+    /// pairs of the lobby (see `participant_info::meta_fingerprint`), and index it by
+    /// patch. Only at ENRICHMENT_LEVEL=full, which stores the traits it comes from.
+    pub meta_fingerprint: bool,
+    /// TOP_LOBBIES_INDEX: create the `{_avgElo: -1, _matchTimestamp: -1, _id: 1}` index
+    /// the database server's top-lobbies queries sort by (see
+    /// `match_doc::top_lobbies_index_command`) on every match collection. Off by default,
+    /// leaving the index to whoever deploys the server.
+    pub top_lobbies_index: bool,
+    /// CYCLE_RETRY_BUDGET: retries a region task may make in one cycle, across all retry
+    /// loops. Once spent, failing operations are skipped until the next cycle.
+    pub cycle_retry_budget: u32,
+    /// FLAT_PARTICIPATIONS: also write each stored match as one row per participant, with
+    /// the match-level fields (avg elo, patch, timestamps) repeated on each row, to the
+    /// `flat-participations-4-1` collection. The match document itself is unchanged.
+    pub flat_participations: bool,
+    /// INTEROP_MATCHES: also write each stored match in the flat shape of
+    /// `interop::InteropMatch` to `interop-matches-4-1`, for tooling outside the crawler.
+    pub interop_matches: bool,
+    /// DAILY_ROLLUP: keep one `daily-rollup-4-1` document per region, queue and UTC day,
+    /// with the matches stored, their average lobby elo and elo band counts, and the cycles'
+    /// match errors, see `daily_rollup`. Updated in place, so it outlives the matches' TTL.
+    pub daily_rollup: bool,
+    /// SET_CHECK: `off`, `warn` or `refuse`. At startup, compare the set of each platform's
+    /// latest top match with the set in the collection suffix (`matches-4-1` is set 4), to
+    /// catch a suffix not updated after a set rotation. `warn` (the default) logs an error
+    /// and crawls anyway, `refuse` exits instead.
+    pub set_check: SetCheck,
+    /// NOT_FOUND_GRACE_SECS: how long a listed match may keep returning 404 from
+    /// `get_match`, retried each cycle, before it is negatively cached. Protects matches
+    /// that are too new to be indexed yet.
+    pub not_found_grace: Duration,
+    /// RECENT_MATCH_WINDOW_MINS: a match whose fetch fails is retried on later cycles
+    /// instead of negatively cached while it's estimated to be younger than this. The age
+    /// is bounded by the newest match the task has fetched with an id no greater. Zero
+    /// disables it.
+    pub recent_match_window: Duration,
+    /// MAX_CONCURRENT_SUMMONER_CALLS, MAX_CONCURRENT_MATCH_LIST_CALLS,
+    /// MAX_CONCURRENT_MATCH_CALLS, MAX_CONCURRENT_LEAGUE_CALLS: upper bound on calls in
+    /// flight to each Riot endpoint across all region tasks, to tune against the
+    /// per-method rate limits. Must be positive. Uncapped when unset.
+    pub max_concurrent_summoner_calls: Option<usize>,
+    pub max_concurrent_match_list_calls: Option<usize>,
+    pub max_concurrent_match_calls: Option<usize>,
+    pub max_concurrent_league_calls: Option<usize>,
+    /// RATE_LIMIT_PREFLIGHT: gate each Riot call on a token bucket per method and per
+    /// routing value, so traffic stays under the limits rather than relying on riven
+    /// backing off after 429s. Off by default.
+    pub rate_limit_preflight: bool,
+    /// RATE_LIMIT_SUMMONER, RATE_LIMIT_MATCH_LIST, RATE_LIMIT_MATCH, RATE_LIMIT_LEAGUE:
+    /// method limits for the pre-flight as `<count>/<secs>`, in `Endpoint::ALL` order.
+    /// Default to Riot's documented limits, see `RateLimit::documented`.
+    pub method_rate_limits: [RateLimit; 4],
+    /// RATE_LIMIT_APP: application limit for the pre-flight as `<count>/<secs>`.
+    /// Defaults to `key_tier`'s.
+    pub app_rate_limit: RateLimit,
+    /// RATE_LIMIT_HEADROOM: fraction of each limit the pre-flight lets through, above 0 and
+    /// at most 1. 0.9 by default.
+    pub rate_limit_headroom: f64,
+    /// RAW_FIELDS: which parts of the raw match to store, between the full blob (unset) and
+    /// `summary_only`. `keep:<paths>` or `drop:<paths>` with comma separated dotted paths,
+    /// e.g. `drop:info.participants.units.items`, see `match_doc::RawFieldFilter`.
+    pub raw_fields: Option<RawFieldFilter>,
+    /// AUDIT_LOG_RETENTION_DAYS: append an event to `ingest-audit-4-1` for every decision
+    /// `process_match_id` makes about a new match (stored, dummy written, ...), kept this
+    /// long. Answers why a match isn't stored. Matches already stored are not logged.
+    /// Disabled when unset.
+    pub audit_log_retention: Option<Duration>,
+    /// RAW_RESPONSES_RETENTION_HOURS: debug mode keeping the exact body match-v1 returned
+    /// for each newly fetched match in `raw-responses-4-1`, keyed by match id, for this
+    /// long, including bodies that fail to parse. Failed calls have no body to keep. About
+    /// doubles match storage, so only meant for chasing parsing issues. Disabled when unset.
+    pub raw_responses_retention: Option<Duration>,
+    /// MATCH_CLUSTER_SLOTS: match-v1 calls in flight per major region cluster (EUROPE,
+    /// ASIA, AMERICAS), shared fairly between the platforms routed to it, see
+    /// `fair_share::FairShare`. Must be positive. Unlimited when unset.
+    pub match_cluster_slots: Option<usize>,
+    /// MATCH_FETCH_WORKERS: fetch matches through a queue per major region cluster, drained
+    /// by this many workers per cluster, instead of each platform task calling match-v1
+    /// itself. Bounds each cluster's match-v1 concurrency independently of how many
+    /// platforms route to it, see `cluster_queue::ClusterQueue`. Must be positive. Direct
+    /// calls when unset.
+    pub match_fetch_workers: Option<usize>,
+    /// VALIDATE_IDS: check the length and charset of summoner ids and puuids before using
+    /// them, see `ids`. A top player with a malformed id is skipped. A malformed participant
+    /// puuid gets a placeholder entry, see `ParticipantInfo::placeholder`. On by default.
+    pub validate_ids: bool,
+    /// CLUSTER_WEIGHTS: relative share of each platform within its cluster, e.g.
+    /// `EUW=3,EUNE=1`. Unlisted platforms have weight 1.
+    pub cluster_weights: RegionWeights,
+    /// UPSET_SCORE: store `_upsetScore`, how far the lobby's placements strayed from what
+    /// the players' ranks predicted, see `numeric_league_util::upset_score`.
+    pub upset_score: bool,
+    /// HIGH_VARIANCE_SPREAD: store `_highVariance`, whether the numeric elos of the
+    /// lobby's ranked players span more than this (see `participant_info::elo_spread`),
+    /// to filter out matchmaking outliers. Not stored for lobbies with fewer than two
+    /// ranked players. Off when unset.
+    pub high_variance_spread: Option<i32>,
+    /// CYCLE_HISTORY_RETENTION_DAYS: append each completed cycle's duration, summoners
+    /// processed and new matches to `cycle-history-4-1`, kept this long, for charting
+    /// cycle times per region. Disabled when unset.
+    pub cycle_history_retention: Option<Duration>,
+    /// MAX_SUMMONER_CALLS_PER_CYCLE: summoner-v1 `get_by_puuid` calls a region task may make
+    /// per cycle on summoner cache misses. Past it, matches needing another lookup are left
+    /// unstored and picked up again next cycle, spreading a cold start over several cycles.
+    /// Once it's reached, new matches aren't fetched at all until the next cycle.
+    /// Unlimited when unset.
+    pub max_summoner_calls_per_cycle: Option<u32>,
+    /// MAX_NEW_MATCHES_PER_CYCLE: new matches a region task may store per cycle. Once it's
+    /// reached the remaining players and matches are left for the next cycle. Unlimited
+    /// when unset.
+    pub max_new_matches_per_cycle: Option<u32>,
+    /// MIGRATE_STORED_MATCHES: when a stored match is seen again and its `_schemaVersion`
+    /// is older than `match_doc::SCHEMA_VERSION`, recompute its derived fields from the
+    /// stored raw match and update them in place. Participants keep their stored ranks,
+    /// so no Riot API calls are made.
+    pub migrate_stored_matches: bool,
+    /// RERANK_UNRANKED_PER_CYCLE: after each completed ranked cycle, look up again the
+    /// current rank of participants stored as unranked (e.g. early in a set, before
+    /// placements) in up to this many of the region's stored matches (in every collection
+    /// `Main::match_lookup_collections` covers), updating their entries and
+    /// `_avgElo` where they have since placed. Each pass continues after the last match
+    /// the previous one checked, see `Main::rerank_unranked`. Participants whose lookup
+    /// fails stay unranked until a later pass. Off when unset.
+    pub rerank_unranked_per_cycle: Option<u32>,
+    /// PARTITION_MATCHES_MONTHLY: store matches in one collection per month of
+    /// `_matchTimestamp`, e.g. `matches-4-1-2024-01`, so old months can be dropped whole.
+    /// Dummies of failed fetches go to the current month. See `collections::recent_partitions`.
+    /// The crawler creates each partition's `_documentExpire` TTL index itself.
+    pub partition_matches_monthly: bool,
+    /// EMPTY_PLAYER_LIST_BACKOFF_SECS: wait before the next cycle when a cycle found no
+    /// players to crawl, instead of going straight back to the ladder endpoints.
+    pub empty_player_list_backoff: Duration,
+    /// PARTICIPANT_PERFORMANCE: add `ranked` and the match performance (placement, level,
+    /// last round, gold left, damage) to every `_aggregatedPlayerInfo` entry, ranked or not,
+    /// whatever `enrichment_level`. Unranked players still don't count towards `_avgElo`.
+    pub participant_performance: bool,
+    /// MAX_CYCLE_SECS: hard cap on a cycle's wall-clock time. Past it the summoners still
+    /// in flight are cancelled and the next cycle starts straight away, without moving the
+    /// crawl cursor. Unlimited when unset.
+    pub max_cycle_duration: Option<Duration>,
+    /// MIN_CYCLE_INTERVAL_SECS: least time between the starts of consecutive cycles of a
+    /// region task, bounding how often its ladder is rescanned. A cycle ending sooner,
+    /// e.g. skipped or timed out, is followed by a sleep for the remainder. When unset,
+    /// 5 minutes for ranked and 10 minutes for hyperroll, see `Main::min_cycle_interval`.
+    pub min_cycle_interval: Option<Duration>,
+    /// FAILURE_RATIO_THRESHOLD: pause a region task for FAILURE_RATIO_COOLDOWN_SECS when
+    /// more than this fraction (e.g. 0.3) of its summoner, match list and match calls
+    /// failed transiently over the last FAILURE_RATIO_WINDOW_SECS. Checked at the start of
+    /// each cycle, once the window holds at least FAILURE_RATIO_MIN_CALLS calls. Disabled
+    /// when unset.
+    pub failure_ratio_threshold: Option<f64>,
+    pub failure_ratio_window: Duration,
+    pub failure_ratio_cooldown: Duration,
+    pub failure_ratio_min_calls: usize,
+}
+
+impl Config {
+    pub fn from_env() -> Config {
+        let default = Config::default();
+        let key_tier = env_parse("RGAPI_KEY_TIER", default.key_tier);
+        Config {
+            store_iso_timestamps: env_flag("STORE_ISO_TIMESTAMPS", default.store_iso_timestamps),
+            max_concurrent_writes: env_count(
+                "MAX_CONCURRENT_WRITES",
+                default.max_concurrent_writes,
+            ),
+            tag_discovered_via: env_flag("TAG_DISCOVERED_VIA", default.tag_discovered_via),
+            key_tier,
+            summoner_pacing: env_millis("SUMMONER_PACING_MS", key_tier.default_summoner_pacing()),
+            region_pacing: env_parse("REGION_PACING_MS", default.region_pacing),
+            bootstrap_slowdown: env_opt("BOOTSTRAP_SLOWDOWN"),
+            bootstrap_min_hit_rate: env_parse(
+                "BOOTSTRAP_MIN_HIT_RATE",
+                default.bootstrap_min_hit_rate,
+            ),
+            bootstrap_max_cycles: env_parse("BOOTSTRAP_MAX_CYCLES", default.bootstrap_max_cycles),
+            summary_only: env_flag("SUMMARY_ONLY", default.summary_only),
+            typed_match: env_parse("TYPED_MATCH", default.typed_match),
+            max_document_bytes: env_parse("MAX_DOCUMENT_BYTES", default.max_document_bytes),
+            league_page_lookahead: env_parse(
+                "LEAGUE_PAGE_LOOKAHEAD",
+                default.league_page_lookahead,
+            ),
+            crawl_seed: env_opt("CRAWL_SEED"),
+            persist_ladder_rankings: env_flag(
+                "PERSIST_LADDER_RANKINGS",
+                default.persist_ladder_rankings,
+            ),
+            lp_gain_tracking: env_flag("LP_GAIN_TRACKING", default.lp_gain_tracking),
+            lp_changes_retention: env_opt("LP_CHANGES_RETENTION_DAYS")
+                .map(|days: u64| Duration::from_secs(days.saturating_mul(24 * 60 * 60)))
+                .unwrap_or(default.lp_changes_retention),
+            rank_distributions: env_flag("RANK_DISTRIBUTIONS", default.rank_distributions),
+            apex_lp_cutoffs: env_opt("APEX_LP_CUTOFFS"),
+            live_apex_cutoffs: env_flag("LIVE_APEX_CUTOFFS", default.live_apex_cutoffs),
+            elo_rounding: env_parse("ELO_ROUNDING", default.elo_rounding),
+            omit_apex_division: env_flag("OMIT_APEX_DIVISION", default.omit_apex_division),
+            direct_bson: env_flag("DIRECT_BSON", default.direct_bson),
+            partial_enrichment: env_flag("PARTIAL_ENRICHMENT", default.partial_enrichment),
+            participant_retries: env_parse("PARTICIPANT_RETRIES", default.participant_retries),
+            participant_retry_backoff: env_millis(
+                "PARTICIPANT_RETRY_BACKOFF_MS",
+                default.participant_retry_backoff,
+            ),
+            max_retry_after: env_millis("MAX_RETRY_AFTER_MS", default.max_retry_after),
+            scan: env_parse("SCAN_TIERS", default.scan),
+            db_error_policy: env_parse("DB_ERROR_POLICY", default.db_error_policy),
+            enrichment_level: env_parse("ENRICHMENT_LEVEL", default.enrichment_level),
+            store_companions: env_flag("STORE_COMPANIONS", default.store_companions),
+            intern_traits: env_flag("INTERN_TRAITS", default.intern_traits),
+            rank_observed_at: env_flag("RANK_OBSERVED_AT", default.rank_observed_at),
+            per_region_collections: env_flag(
+                "PER_REGION_COLLECTIONS",
+                default.per_region_collections,
+            ),
+            summoner_cache_max_age: env_opt("SUMMONER_CACHE_MAX_AGE_SECS").map(Duration::from_secs),
+            league_cache_max_age: env_opt("LEAGUE_CACHE_MAX_AGE_SECS").map(Duration::from_secs),
+            meta_stats: env_flag("META_STATS", default.meta_stats),
+            max_match_age: env_hours("MAX_MATCH_AGE_HOURS"),
+            catch_up_gap: Duration::from_secs(env_parse(
+                "CATCH_UP_GAP_SECS",
+                default.catch_up_gap.as_secs(),
+            )),
+            catch_up_match_count: env_parse("CATCH_UP_MATCH_COUNT", default.catch_up_match_count),
+            oldest_matches_first: env_flag("OLDEST_MATCHES_FIRST", default.oldest_matches_first),
+            min_ranked_to_store: env_parse("MIN_RANKED_TO_STORE", default.min_ranked_to_store),
+            store_pid_hashes: env_flag("STORE_PID_HASHES", default.store_pid_hashes),
+            store_placements: env_flag("STORE_PLACEMENTS", default.store_placements),
+            meta_fingerprint: env_flag("META_FINGERPRINT", default.meta_fingerprint),
+            top_lobbies_index: env_flag("TOP_LOBBIES_INDEX", default.top_lobbies_index),
+            cycle_retry_budget: env_parse("CYCLE_RETRY_BUDGET", default.cycle_retry_budget),
+            flat_participations: env_flag("FLAT_PARTICIPATIONS", default.flat_participations),
+            interop_matches: env_flag("INTEROP_MATCHES", default.interop_matches),
+            daily_rollup: env_flag("DAILY_ROLLUP", default.daily_rollup),
+            set_check: env_parse("SET_CHECK", default.set_check),
+            not_found_grace: Duration::from_secs(env_parse(
+                "NOT_FOUND_GRACE_SECS",
+                default.not_found_grace.as_secs(),
+            )),
+            recent_match_window: Duration::from_secs(
+                60 * env_parse(
+                    "RECENT_MATCH_WINDOW_MINS",
+                    default.recent_match_window.as_secs() / 60,
+                ),
+            ),
+            max_concurrent_summoner_calls: env_count_opt("MAX_CONCURRENT_SUMMONER_CALLS"),
+            max_concurrent_match_list_calls: env_count_opt("MAX_CONCURRENT_MATCH_LIST_CALLS"),
+            max_concurrent_match_calls: env_count_opt("MAX_CONCURRENT_MATCH_CALLS"),
+            max_concurrent_league_calls: env_count_opt("MAX_CONCURRENT_LEAGUE_CALLS"),
+            rate_limit_preflight: env_flag("RATE_LIMIT_PREFLIGHT", default.rate_limit_preflight),
+            method_rate_limits: [
+                env_parse("RATE_LIMIT_SUMMONER", default.method_rate_limits[0]),
+                env_parse("RATE_LIMIT_MATCH_LIST", default.method_rate_limits[1]),
+                env_parse("RATE_LIMIT_MATCH", default.method_rate_limits[2]),
+                env_parse("RATE_LIMIT_LEAGUE", default.method_rate_limits[3]),
+            ],
+            app_rate_limit: env_parse("RATE_LIMIT_APP", RateLimit::application(key_tier)),
+            rate_limit_headroom: env_fraction("RATE_LIMIT_HEADROOM", default.rate_limit_headroom),
+            raw_fields: env_opt("RAW_FIELDS"),
+            audit_log_retention: env_opt("AUDIT_LOG_RETENTION_DAYS")
+                .map(|days: u64| Duration::from_secs(days.saturating_mul(24 * 60 * 60))),
+            raw_responses_retention: env_opt("RAW_RESPONSES_RETENTION_HOURS")
+                .map(|hours: u64| Duration::from_secs(hours.saturating_mul(60 * 60))),
+            match_cluster_slots: env_count_opt("MATCH_CLUSTER_SLOTS"),
+            match_fetch_workers: env_count_opt("MATCH_FETCH_WORKERS"),
+            validate_ids: env_flag("VALIDATE_IDS", default.validate_ids),
+            cluster_weights: env_parse("CLUSTER_WEIGHTS", default.cluster_weights),
+            upset_score: env_flag("UPSET_SCORE", default.upset_score),
+            high_variance_spread: env_opt("HIGH_VARIANCE_SPREAD"),
+            cycle_history_retention: env_opt("CYCLE_HISTORY_RETENTION_DAYS")
+                .map(|days: u64| Duration::from_secs(days.saturating_mul(24 * 60 * 60))),
+            max_summoner_calls_per_cycle: env_opt("MAX_SUMMONER_CALLS_PER_CYCLE"),
+            max_new_matches_per_cycle: env_opt("MAX_NEW_MATCHES_PER_CYCLE"),
+            migrate_stored_matches: env_flag(
+                "MIGRATE_STORED_MATCHES",
+                default.migrate_stored_matches,
+            ),
+            rerank_unranked_per_cycle: env_opt("RERANK_UNRANKED_PER_CYCLE"),
+            partition_matches_monthly: env_flag(
+                "PARTITION_MATCHES_MONTHLY",
+                default.partition_matches_monthly,
+            ),
+            empty_player_list_backoff: Duration::from_secs(env_parse(
+                "EMPTY_PLAYER_LIST_BACKOFF_SECS",
+                default.empty_player_list_backoff.as_secs(),
+            )),
+            participant_performance: env_flag(
+                "PARTICIPANT_PERFORMANCE",
+                default.participant_performance,
+            ),
+            max_cycle_duration: env_opt("MAX_CYCLE_SECS").map(Duration::from_secs),
+            min_cycle_interval: env_opt("MIN_CYCLE_INTERVAL_SECS").map(Duration::from_secs),
+            failure_ratio_threshold: env_opt("FAILURE_RATIO_THRESHOLD"),
+            failure_ratio_window: Duration::from_secs(env_parse(
+                "FAILURE_RATIO_WINDOW_SECS",
+                default.failure_ratio_window.as_secs(),
+            )),
+            failure_ratio_cooldown: Duration::from_secs(env_parse(
+                "FAILURE_RATIO_COOLDOWN_SECS",
+                default.failure_ratio_cooldown.as_secs(),
+            )),
+            failure_ratio_min_calls: env_parse(
+                "FAILURE_RATIO_MIN_CALLS",
+                default.failure_ratio_min_calls,
+            ),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            store_iso_timestamps: false,
+            max_concurrent_writes: 32,
+            tag_discovered_via: false,
+            key_tier: KeyTier::Production,
+            summoner_pacing: KeyTier::Production.default_summoner_pacing(),
+            region_pacing: RegionPacing::default(),
+            bootstrap_slowdown: None,
+            bootstrap_min_hit_rate: 0.5,
+            bootstrap_max_cycles: 3,
+            summary_only: false,
+            typed_match: TypedMatchMode::Off,
+            max_document_bytes: 15 * 1024 * 1024,
+            league_page_lookahead: 3,
+            crawl_seed: None,
+            persist_ladder_rankings: false,
+            lp_gain_tracking: false,
+            lp_changes_retention: Duration::from_secs(30 * 24 * 60 * 60),
+            rank_distributions: false,
+            apex_lp_cutoffs: None,
+            live_apex_cutoffs: false,
+            elo_rounding: EloRounding::default(),
+            omit_apex_division: false,
+            direct_bson: true,
+            partial_enrichment: true,
+            participant_retries: 2,
+            participant_retry_backoff: Duration::from_millis(500),
+            max_retry_after: Duration::from_secs(60),
+            scan: ScanConfig::default(),
+            db_error_policy: DbErrorPolicy::FailFast,
+            enrichment_level: EnrichmentLevel::Standard,
+            store_companions: false,
+            intern_traits: false,
+            rank_observed_at: false,
+            per_region_collections: false,
+            summoner_cache_max_age: None,
+            league_cache_max_age: None,
+            max_match_age: None,
+            meta_stats: false,
+            catch_up_gap: Duration::from_secs(60 * 60),
+            catch_up_match_count: 50,
+            oldest_matches_first: false,
+            min_ranked_to_store: 0,
+            store_pid_hashes: false,
+            store_placements: false,
+            meta_fingerprint: false,
+            top_lobbies_index: false,
+            cycle_retry_budget: 200,
+            flat_participations: false,
+            interop_matches: false,
+            daily_rollup: false,
+            set_check: SetCheck::Warn,
+            not_found_grace: Duration::from_secs(15 * 60),
+            recent_match_window: Duration::from_secs(30 * 60),
+            max_concurrent_summoner_calls: None,
+            max_concurrent_match_list_calls: None,
+            max_concurrent_match_calls: None,
+            max_concurrent_league_calls: None,
+            rate_limit_preflight: false,
+            method_rate_limits: [
+                RateLimit::documented(Endpoint::Summoner),
+                RateLimit::documented(Endpoint::MatchList),
+                RateLimit::documented(Endpoint::Match),
+                RateLimit::documented(Endpoint::League),
+            ],
+            app_rate_limit: RateLimit::application(KeyTier::Production),
+            rate_limit_headroom: 0.9,
+            raw_fields: None,
+            audit_log_retention: None,
+            raw_responses_retention: None,
+            match_cluster_slots: None,
+            match_fetch_workers: None,
+            validate_ids: true,
+            cluster_weights: RegionWeights::default(),
+            upset_score: false,
+            high_variance_spread: None,
+            cycle_history_retention: None,
+            max_summoner_calls_per_cycle: None,
+            max_new_matches_per_cycle: None,
+            migrate_stored_matches: false,
+            rerank_unranked_per_cycle: None,
+            partition_matches_monthly: false,
+            empty_player_list_backoff: Duration::from_secs(15 * 60),
+            participant_performance: false,
+            max_cycle_duration: None,
+            min_cycle_interval: None,
+            failure_ratio_threshold: None,
+            failure_ratio_window: Duration::from_secs(10 * 60),
+            failure_ratio_cooldown: Duration::from_secs(5 * 60),
+            failure_ratio_min_calls: 20,
+        }
+    }
+}
+
+/// Riot API key type, which determines the application rate limit
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum KeyTier {
+    /// 20 requests / 1s, 100 requests / 2min
+    Development,
+    /// 500 requests / 10s, 30000 requests / 10min
+    Production,
+}
+
+// Rough number of Riot API calls spent on one summoner: summoner-v1 and the
+// match list, plus get_match and participant summoner/league lookups for the
+// new matches found. Cache-warm steady state, not a cold start.
+const ESTIMATED_CALLS_PER_SUMMONER: f64 = 16.0;
+
+// The ranked and hyperroll tasks of a platform share its application limit
+const TASKS_PER_PLATFORM: f64 = 2.0;
+
+impl KeyTier {
+    /// Sustained requests per second under the application rate limit (per platform)
+    pub fn sustained_rate(self) -> f64 {
+        match self {
+            KeyTier::Development => 100.0 / 120.0,
+            KeyTier::Production => 30000.0 / 600.0,
+        }
+    }
+
+    /// Delay between launching summoners. A production key keeps the 2000ms the crawler
+    /// has always used; a development key gets one keeping each task within its share of
+    /// the sustained rate. riven still enforces the real limits; this only keeps the
+    /// crawl from running into them constantly.
+    pub fn default_summoner_pacing(self) -> Duration {
+        match self {
+            KeyTier::Production => Duration::from_millis(2000),
+            KeyTier::Development => {
+                let per_task_rate = self.sustained_rate() / TASKS_PER_PLATFORM;
+                Duration::from_millis(
+                    (1000.0 * ESTIMATED_CALLS_PER_SUMMONER / per_task_rate) as u64,
+                )
+            }
+        }
+    }
+}
+
+impl FromStr for KeyTier {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<KeyTier, String> {
+        match s.to_lowercase().as_str() {
+            "dev" | "development" => Ok(KeyTier::Development),
+            "prod" | "production" => Ok(KeyTier::Production),
+            _ => Err(format!("Unknown key tier: {}", s)),
+        }
+    }
+}
+
+fn parse_flag(value: &str) -> bool {
+    matches!(value.to_lowercase().as_str(), "1" | "true" | "yes" | "on")
+}
+
+fn env_flag(name: &str, default: bool) -> bool {
+    match std::env::var(name) {
+        Ok(value) => parse_flag(&value),
+        Err(_) => default,
+    }
+}
+
+fn env_parse<T: FromStr>(name: &str, default: T) -> T
+where
+    T::Err: std::fmt::Display,
+{
+    env_opt(name).unwrap_or(default)
+}
+
+fn env_opt<T: FromStr>(name: &str) -> Option<T>
+where
+    T::Err: std::fmt::Display,
+{
+    std::env::var(name)
+        .ok()
+        .map(|value| parse_env(name, &value).unwrap_or_else(|e| panic!("{}", e)))
+}
+
+// The parse error is kept, as it says what is wrong (e.g. "Unknown region XX")
+fn parse_env<T: FromStr>(name: &str, value: &str) -> Result<T, String>
+where
+    T::Err: std::fmt::Display,
+{
+    value.parse().map_err(|e: T::Err| {
+        format!(
+            "Invalid value for environment variable {}: {}: {}",
+            name, value, e
+        )
+    })
+}
+
+// A number of slots or permits. 0 is rejected, as nothing could ever acquire one.
+fn env_count(name: &str, default: usize) -> usize {
+    env_count_opt(name).unwrap_or(default)
+}
+
+fn env_count_opt(name: &str) -> Option<usize> {
+    env_opt(name).map(NonZeroUsize::get)
+}
+
+// A fraction above 0 and at most 1
+fn env_fraction(name: &str, default: f64) -> f64 {
+    let value = env_parse(name, default);
+    check_fraction(value)
+        .unwrap_or_else(|e| panic!("Invalid value for environment variable {}: {}", name, e))
+}
+
+fn check_fraction(value: f64) -> Result<f64, String> {
+    if value > 0.0 && value <= 1.0 {
+        Ok(value)
+    } else {
+        Err(format!("{} is not above 0 and at most 1", value))
+    }
+}
+
+fn env_hours(name: &str) -> Option<chrono::Duration> {
+    let hours = env_opt(name)?;
+    let hours = check_hours(hours)
+        .unwrap_or_else(|e| panic!("Invalid value for environment variable {}: {}", name, e));
+    Some(hours)
+}
+
+// Saturating, so a huge number of hours can't overflow
+fn check_hours(hours: u64) -> Result<chrono::Duration, String> {
+    if hours == 0 {
+        return Err("0 hours is not positive".to_string());
+    }
+    let secs = Duration::from_secs(hours.saturating_mul(60 * 60));
+    Ok(chrono::Duration::from_std(secs).unwrap_or_else(|_| chrono::Duration::max_value()))
+}
+
+fn env_millis(name: &str, default: Duration) -> Duration {
+    Duration::from_millis(env_parse(name, default.as_millis() as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_flag() {
+        assert!(parse_flag("1"));
+        assert!(parse_flag("true"));
+        assert!(parse_flag("TRUE"));
+        assert!(!parse_flag("0"));
+        assert!(!parse_flag("false"));
+        assert!(!parse_flag(""));
+    }
+
+    #[test]
+    fn test_parse_env() {
+        assert_eq!(parse_env::<u32>("PARTICIPANT_RETRIES", "3"), Ok(3));
+        assert_eq!(
+            parse_env::<KeyTier>("RGAPI_KEY_TIER", "personal"),
+            Err(
+                "Invalid value for environment variable RGAPI_KEY_TIER: personal: Unknown key tier: personal"
+                    .to_string()
+            )
+        );
+        // What `env_count` parses, so a count of 0 is refused
+        assert_eq!(
+            parse_env::<NonZeroUsize>("MAX_CONCURRENT_WRITES", "8").map(NonZeroUsize::get),
+            Ok(8)
+        );
+        assert!(parse_env::<NonZeroUsize>("MAX_CONCURRENT_WRITES", "0").is_err());
+    }
+
+    #[test]
+    fn test_check_fraction() {
+        assert_eq!(check_fraction(0.9), Ok(0.9));
+        assert_eq!(check_fraction(1.0), Ok(1.0));
+        assert!(check_fraction(0.0).is_err());
+        assert!(check_fraction(-0.5).is_err());
+        assert!(check_fraction(1.5).is_err());
+        assert!(check_fraction(f64::NAN).is_err());
+    }
+
+    #[test]
+    fn test_check_hours() {
+        assert_eq!(check_hours(48), Ok(chrono::Duration::hours(48)));
+        assert_eq!(check_hours(u64::MAX), Ok(chrono::Duration::max_value()));
+        assert!(check_hours(0).is_err());
+    }
+
+    #[test]
+    fn test_key_tier_pacing() {
+        assert_eq!("dev".parse(), Ok(KeyTier::Development));
+        assert_eq!("PRODUCTION".parse(), Ok(KeyTier::Production));
+        assert!("personal".parse::<KeyTier>().is_err());
+
+        // A development key can't sustain the pacing a production key can
+        assert_eq!(
+            KeyTier::Production.default_summoner_pacing(),
+            Duration::from_millis(2000)
+        );
+        assert_eq!(
+            KeyTier::Development.default_summoner_pacing(),
+            Duration::from_millis(38400)
+        );
+    }
+}
@@ -0,0 +1,48 @@
+use chrono::{DateTime, Duration, Utc};
+
+/// Match ids requested per player in a normal cycle
+pub const STEADY_MATCH_COUNT: i32 = 10;
+
+/// How many recent match ids to request per player this cycle. After a gap longer than
+/// `catch_up_gap` since the task last finished a cycle (e.g. downtime), players may have
+/// played more than `STEADY_MATCH_COUNT` games, so look further back once.
+pub fn match_fetch_depth(
+    last_cycle: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+    catch_up_gap: Duration,
+    catch_up_count: i32,
+) -> i32 {
+    match last_cycle {
+        Some(last_cycle) if now - last_cycle > catch_up_gap => {
+            catch_up_count.max(STEADY_MATCH_COUNT)
+        }
+        _ => STEADY_MATCH_COUNT,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_match_fetch_depth() {
+        let now = Utc.ymd(2021, 3, 1).and_hms(12, 0, 0);
+        let gap = Duration::hours(1);
+        // First run, nothing to catch up on
+        assert_eq!(match_fetch_depth(None, now, gap, 50), STEADY_MATCH_COUNT);
+        assert_eq!(
+            match_fetch_depth(Some(now - Duration::minutes(20)), now, gap, 50),
+            STEADY_MATCH_COUNT
+        );
+        assert_eq!(
+            match_fetch_depth(Some(now - Duration::hours(6)), now, gap, 50),
+            50
+        );
+        // Never shallower than steady state
+        assert_eq!(
+            match_fetch_depth(Some(now - Duration::hours(6)), now, gap, 5),
+            STEADY_MATCH_COUNT
+        );
+    }
+}
@@ -0,0 +1,43 @@
+use mongodb::bson::{doc, Document};
+use std::time::Duration;
+
+use crate::metrics::MetricsSnapshot;
+
+/// Cycle history entry for one completed cycle of a region task. Timestamps are
+/// added by the caller.
+pub fn entry(queue: &str, region: &str, duration: Duration, metrics: &MetricsSnapshot) -> Document {
+    doc! {
+        "queue": queue,
+        "region": region,
+        "durationSecs": duration.as_secs_f64(),
+        "summonersProcessed": metrics.summoners_processed as i64,
+        "newMatches": metrics.new_matches as i64,
+        "matchCalls": metrics.match_calls as i64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry() {
+        let metrics = MetricsSnapshot {
+            summoners_processed: 200,
+            new_matches: 35,
+            match_calls: 240,
+            ..MetricsSnapshot::default()
+        };
+        assert_eq!(
+            entry("Ranked", "EUW1", Duration::from_millis(90_500), &metrics),
+            doc! {
+                "queue": "Ranked",
+                "region": "EUW1",
+                "durationSecs": 90.5,
+                "summonersProcessed": 200i64,
+                "newMatches": 35i64,
+                "matchCalls": 240i64,
+            }
+        );
+    }
+}
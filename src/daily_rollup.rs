@@ -0,0 +1,121 @@
+use chrono::{DateTime, Utc};
+use mongodb::bson::{doc, Document};
+
+use crate::metrics::MetricsSnapshot;
+use crate::numeric_league_util::numeric_to_league;
+
+/// Day a rollup update counts towards, the UTC date it was made
+pub fn day(now: DateTime<Utc>) -> String {
+    now.format("%Y-%m-%d").to_string()
+}
+
+/// Band a lobby is counted in: the tier of its average elo, or UNRANKED
+pub fn elo_band(avg_elo: i32) -> String {
+    if avg_elo == i32::MIN {
+        return "UNRANKED".to_string();
+    }
+    numeric_to_league(avg_elo).0
+}
+
+/// Counters to add for one stored match. The day's average lobby elo is
+/// `eloSum / rankedMatches`, kept as a sum so concurrent updates can't lose it.
+pub fn match_inc(avg_elo: i32) -> Document {
+    let mut inc = doc! {"matches": 1i64};
+    if avg_elo != i32::MIN {
+        inc.insert("rankedMatches", 1i64);
+        inc.insert("eloSum", avg_elo as i64);
+    }
+    inc.insert(format!("eloBands.{}", elo_band(avg_elo)), 1i64);
+    inc
+}
+
+/// Error counters to add for one cycle, None if there were none
+pub fn cycle_inc(metrics: &MetricsSnapshot) -> Option<Document> {
+    if metrics.match_errors == 0 && metrics.invalid_ids == 0 {
+        return None;
+    }
+    Some(doc! {
+        "matchErrors": metrics.match_errors as i64,
+        "invalidIds": metrics.invalid_ids as i64,
+    })
+}
+
+/// `update` command adding `inc` to the region and queue's rollup for `day`. A single
+/// `$inc` upsert, so the region's tasks can update the same document concurrently.
+pub fn update_command(
+    collection_name: &str,
+    region: &str,
+    queue: &str,
+    day: &str,
+    inc: Document,
+) -> Document {
+    doc! {
+        "update": collection_name,
+        "updates": [{
+            "q": {"_id": format!("{}-{}-{}", region, queue, day)},
+            "u": {
+                "$inc": inc,
+                "$setOnInsert": {"region": region, "queue": queue, "date": day},
+            },
+            "upsert": true,
+        }],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_day() {
+        assert_eq!(day(Utc.ymd(2021, 3, 9).and_hms(23, 59, 59)), "2021-03-09");
+    }
+
+    #[test]
+    fn test_match_inc() {
+        assert_eq!(
+            match_inc(2250),
+            doc! {"matches": 1i64, "rankedMatches": 1i64, "eloSum": 2250i64, "eloBands.DIAMOND": 1i64}
+        );
+        assert_eq!(
+            match_inc(i32::MIN),
+            doc! {"matches": 1i64, "eloBands.UNRANKED": 1i64}
+        );
+    }
+
+    #[test]
+    fn test_cycle_inc() {
+        assert_eq!(cycle_inc(&MetricsSnapshot::default()), None);
+        let metrics = MetricsSnapshot {
+            match_errors: 3,
+            ..MetricsSnapshot::default()
+        };
+        assert_eq!(
+            cycle_inc(&metrics),
+            Some(doc! {"matchErrors": 3i64, "invalidIds": 0i64})
+        );
+    }
+
+    #[test]
+    fn test_update_command() {
+        let command = update_command("rollup", "EUW1", "Ranked", "2021-03-09", match_inc(2250));
+        let update = command.get_array("updates").unwrap()[0]
+            .as_document()
+            .unwrap();
+        assert_eq!(
+            update.get_document("q").unwrap(),
+            &doc! {"_id": "EUW1-Ranked-2021-03-09"}
+        );
+        // Hyperroll counts separately from ranked
+        let command = update_command("rollup", "EUW1", "Hyperroll", "2021-03-09", match_inc(2250));
+        let update = command.get_array("updates").unwrap()[0]
+            .as_document()
+            .unwrap();
+        assert_eq!(
+            update.get_document("q").unwrap(),
+            &doc! {"_id": "EUW1-Hyperroll-2021-03-09"}
+        );
+        assert!(update.get_bool("upsert").unwrap());
+    }
+}
@@ -0,0 +1,179 @@
+use log::{info, warn};
+use mongodb::bson::doc;
+use std::future::Future;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::time::sleep;
+
+use crate::error::{self, CrawlerError};
+
+// Attempts after the first under `RetryThenSkip`
+const DB_RETRIES: u32 = 3;
+
+// Pings before giving up on reaching MongoDB at startup, about 3 minutes in total
+const STARTUP_PING_ATTEMPTS: u32 = 10;
+
+/// How MongoDB call failures are handled, see `Config::db_error_policy`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DbErrorPolicy {
+    /// Return the error straight away, abandoning the match or summoner being processed
+    FailFast,
+    /// Retry transient errors a few times with backoff, then abandon the item
+    RetryThenSkip,
+}
+
+impl FromStr for DbErrorPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<DbErrorPolicy, String> {
+        match s.to_lowercase().as_str() {
+            "fail-fast" => Ok(DbErrorPolicy::FailFast),
+            "retry-then-skip" => Ok(DbErrorPolicy::RetryThenSkip),
+            _ => Err(format!("Unknown DB error policy: {}", s)),
+        }
+    }
+}
+
+// 0.5s, 1s, 2s, ...
+fn retry_backoff(attempt: u32) -> Duration {
+    Duration::from_millis(500 << (attempt - 1))
+}
+
+// 2s, 4s, 8s, 16s, then 30s
+fn startup_retry_delay(attempt: u32) -> Duration {
+    Duration::from_secs((1u64 << attempt.min(5)).min(30))
+}
+
+/// Wait for MongoDB to answer a ping, so a crawler started alongside its database
+/// doesn't fail before the database is up
+pub async fn wait_until_reachable(db: &mongodb::Database) -> anyhow::Result<()> {
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        match db.run_command(doc! {"ping": 1}, None).await {
+            Ok(_) => {
+                info!("Connected to MongoDB");
+                return Ok(());
+            }
+            Err(e) if attempt < STARTUP_PING_ATTEMPTS => {
+                let delay = startup_retry_delay(attempt);
+                warn!(
+                    "MongoDB unreachable ({}/{}), retrying in {:?}: {}",
+                    attempt, STARTUP_PING_ATTEMPTS, delay, e
+                );
+                sleep(delay).await;
+            }
+            Err(e) => {
+                return Err(anyhow::Error::new(e).context(format!(
+                    "MongoDB unreachable after {} attempts",
+                    STARTUP_PING_ATTEMPTS
+                )))
+            }
+        }
+    }
+}
+
+/// Run a MongoDB call under `policy`. `call` is invoked again for each retry, as long
+/// as `may_retry` allows it.
+pub async fn with_policy<T, F, Fut>(
+    policy: DbErrorPolicy,
+    context: &'static str,
+    may_retry: impl Fn() -> bool,
+    mut call: F,
+) -> error::Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = mongodb::error::Result<T>>,
+{
+    let mut attempt = 0;
+    loop {
+        let e = match call().await {
+            Ok(x) => return Ok(x),
+            Err(e) => CrawlerError::db(context, e),
+        };
+        attempt += 1;
+        if policy == DbErrorPolicy::FailFast
+            || !error::is_retryable(&e)
+            || attempt > DB_RETRIES
+            || !may_retry()
+        {
+            return Err(e);
+        }
+        warn!("{}, retrying ({}/{})", e, attempt, DB_RETRIES);
+        sleep(retry_backoff(attempt)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::error::ErrorKind;
+    use std::cell::Cell;
+
+    fn io_error() -> mongodb::error::Error {
+        mongodb::error::Error::from(std::io::Error::new(
+            std::io::ErrorKind::ConnectionReset,
+            "reset",
+        ))
+    }
+
+    #[test]
+    fn test_parse_policy() {
+        assert_eq!("fail-fast".parse(), Ok(DbErrorPolicy::FailFast));
+        assert_eq!("Retry-Then-Skip".parse(), Ok(DbErrorPolicy::RetryThenSkip));
+        assert!("retry".parse::<DbErrorPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_retry_backoff() {
+        assert_eq!(retry_backoff(1), Duration::from_millis(500));
+        assert_eq!(retry_backoff(3), Duration::from_millis(2000));
+    }
+
+    #[test]
+    fn test_startup_retry_delay() {
+        let delays: Vec<u64> = (1..=STARTUP_PING_ATTEMPTS)
+            .map(|attempt| startup_retry_delay(attempt).as_secs())
+            .collect();
+        assert_eq!(delays, vec![2, 4, 8, 16, 30, 30, 30, 30, 30, 30]);
+    }
+
+    #[test]
+    fn test_no_retry() {
+        // Fail-fast doesn't retry even transient errors
+        let calls = Cell::new(0);
+        let ret: error::Result<()> = futures::executor::block_on(with_policy(
+            DbErrorPolicy::FailFast,
+            "find_one",
+            || true,
+            || {
+                calls.set(calls.get() + 1);
+                async { Err(io_error()) }
+            },
+        ));
+        assert!(ret.unwrap_err().is_transient());
+        assert_eq!(calls.get(), 1);
+
+        // Retry-then-skip doesn't retry errors that won't go away
+        let calls = Cell::new(0);
+        let ret: error::Result<()> = futures::executor::block_on(with_policy(
+            DbErrorPolicy::RetryThenSkip,
+            "find_one",
+            || true,
+            || {
+                calls.set(calls.get() + 1);
+                async { Err(ErrorKind::SessionsNotSupported.into()) }
+            },
+        ));
+        assert!(!ret.unwrap_err().is_transient());
+        assert_eq!(calls.get(), 1);
+
+        let ret = futures::executor::block_on(with_policy(
+            DbErrorPolicy::RetryThenSkip,
+            "find_one",
+            || true,
+            || async { Ok(5) },
+        ));
+        assert_eq!(ret.unwrap(), 5);
+    }
+}
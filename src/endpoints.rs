@@ -0,0 +1,134 @@
+use crate::rate_limit::RateLimiter;
+use riven::consts::Region;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+/// Riot endpoints the crawler calls, each with its own method rate limit
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Endpoint {
+    Summoner,
+    MatchList,
+    Match,
+    League,
+}
+
+impl Endpoint {
+    pub const ALL: [Endpoint; 4] = [
+        Endpoint::Summoner,
+        Endpoint::MatchList,
+        Endpoint::Match,
+        Endpoint::League,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Endpoint::Summoner => "summoner",
+            Endpoint::MatchList => "match list",
+            Endpoint::Match => "match",
+            Endpoint::League => "league",
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Concurrency caps on each endpoint, shared by all region tasks, and a gauge of the
+/// calls currently in flight to each. Optionally also a rate limit pre-flight.
+pub struct EndpointLimits {
+    semaphores: Vec<Option<Semaphore>>,
+    in_flight: Vec<AtomicU64>,
+    rate_limiter: Option<RateLimiter>,
+}
+
+/// Held for the duration of one call
+pub struct EndpointPermit<'a> {
+    _permit: Option<SemaphorePermit<'a>>,
+    in_flight: &'a AtomicU64,
+}
+
+impl Drop for EndpointPermit<'_> {
+    fn drop(&mut self) {
+        self.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl EndpointLimits {
+    /// `caps` in `Endpoint::ALL` order, `None` for no cap
+    pub fn new(caps: [Option<usize>; 4], rate_limiter: Option<RateLimiter>) -> EndpointLimits {
+        EndpointLimits {
+            semaphores: caps.iter().map(|cap| cap.map(Semaphore::new)).collect(),
+            in_flight: caps.iter().map(|_| AtomicU64::new(0)).collect(),
+            rate_limiter,
+        }
+    }
+
+    /// Wait for a free slot on `endpoint`, then for the call to fit under the rate
+    /// limits of `route`, the platform or cluster it is routed to
+    pub async fn acquire(&self, endpoint: Endpoint, route: Region) -> EndpointPermit<'_> {
+        let permit = match &self.semaphores[endpoint.index()] {
+            Some(semaphore) => Some(semaphore.acquire().await.expect("Semaphore closed")),
+            None => None,
+        };
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.acquire(endpoint, route).await;
+        }
+        let in_flight = &self.in_flight[endpoint.index()];
+        in_flight.fetch_add(1, Ordering::Relaxed);
+        EndpointPermit {
+            _permit: permit,
+            in_flight,
+        }
+    }
+
+    pub fn in_flight(&self, endpoint: Endpoint) -> u64 {
+        self.in_flight[endpoint.index()].load(Ordering::Relaxed)
+    }
+
+    /// Current in-flight counts, for logging
+    pub fn in_flight_str(&self) -> String {
+        Endpoint::ALL
+            .iter()
+            .map(|endpoint| format!("{} {}", endpoint.name(), self.in_flight(*endpoint)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Rate limit bucket fill levels of `route` and the calls that waited on them, for
+    /// logging. `None` without the pre-flight.
+    pub fn rate_limit_str(&self, route: Region) -> Option<String> {
+        self.rate_limiter.as_ref().map(|rate_limiter| {
+            format!(
+                "{}; {} waited",
+                rate_limiter.fill_str(route),
+                rate_limiter.waits()
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_in_flight() {
+        let limits = EndpointLimits::new([None, None, Some(1), None], None);
+        let first = limits.acquire(Endpoint::Match, Region::EUW).await;
+        let _other = limits.acquire(Endpoint::Summoner, Region::EUW).await;
+        assert_eq!(limits.in_flight(Endpoint::Match), 1);
+        assert_eq!(
+            limits.in_flight_str(),
+            "summoner 1, match list 0, match 1, league 0"
+        );
+
+        // Capped at one match call
+        let second = limits.acquire(Endpoint::Match, Region::EUW);
+        tokio::pin!(second);
+        assert!(futures::poll!(second.as_mut()).is_pending());
+        drop(first);
+        let _second = second.await;
+        assert_eq!(limits.in_flight(Endpoint::Match), 1);
+    }
+}
@@ -0,0 +1,347 @@
+use mongodb::error::{ErrorKind, WriteFailure};
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use riven::RiotApiError;
+use std::time::Duration;
+
+/// Errors from the crawl, classified so callers can tell what is worth retrying.
+/// Top level code still deals in `anyhow::Error`; a `CrawlerError` converts into one
+/// and can be recovered from it with `downcast_ref`.
+#[derive(Debug)]
+pub enum CrawlerError {
+    /// Rate limited (429), server error (5xx) or no response at all. Retrying later may work.
+    ApiTransient(&'static str, RiotApiError),
+    /// The Riot API says the requested resource doesn't exist (404)
+    ApiNotFound(&'static str, RiotApiError),
+    /// Any other Riot API error (400, 401, 403...). Retrying won't help.
+    ApiFatal(&'static str, RiotApiError),
+    /// Connection or server selection problem talking to MongoDB
+    DbTransient(&'static str, mongodb::error::Error),
+    /// MongoDB rejected the operation
+    DbFatal(&'static str, mongodb::error::Error),
+    /// Data couldn't be converted between the Riot models, JSON and BSON
+    Serialization(String),
+    /// Not attempted this cycle because a per-cycle call budget ran out
+    Deferred(&'static str),
+    /// A background task doing the work failed, e.g. a match fetch worker panicked
+    Internal(&'static str),
+}
+
+pub type Result<T> = std::result::Result<T, CrawlerError>;
+
+impl CrawlerError {
+    /// Classify a Riot API error by its response status
+    pub fn api(context: &'static str, e: RiotApiError) -> CrawlerError {
+        if is_transient_api(&e) {
+            return CrawlerError::ApiTransient(context, e);
+        }
+        match e.status_code().map(|status| status.as_u16()) {
+            Some(404) => CrawlerError::ApiNotFound(context, e),
+            _ => CrawlerError::ApiFatal(context, e),
+        }
+    }
+
+    /// Classify a MongoDB error by its kind and labels
+    pub fn db(context: &'static str, e: mongodb::error::Error) -> CrawlerError {
+        let transient = matches!(
+            e.kind,
+            ErrorKind::Io(_)
+                | ErrorKind::ConnectionPoolClearedError { .. }
+                | ErrorKind::ServerSelectionError { .. }
+                | ErrorKind::WaitQueueTimeoutError { .. }
+                | ErrorKind::DnsResolve(_)
+                | ErrorKind::NoDnsResults(_)
+        ) || e.contains_label("RetryableWriteError")
+            || e.contains_label("TransientTransactionError");
+        if transient {
+            CrawlerError::DbTransient(context, e)
+        } else {
+            CrawlerError::DbFatal(context, e)
+        }
+    }
+
+    pub fn serialization(msg: impl std::fmt::Display) -> CrawlerError {
+        CrawlerError::Serialization(msg.to_string())
+    }
+
+    /// Whether the same operation might succeed if tried again later
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            CrawlerError::ApiTransient(..)
+                | CrawlerError::DbTransient(..)
+                | CrawlerError::Deferred(..)
+        )
+    }
+
+    /// Whether an insert failed because a document with the same `_id` already exists
+    pub fn is_duplicate_key(&self) -> bool {
+        match self {
+            CrawlerError::DbTransient(_, e) | CrawlerError::DbFatal(_, e) => {
+                is_duplicate_key(&e.kind)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Rate limited (429), server error (5xx) or no response at all
+pub fn is_transient_api(e: &RiotApiError) -> bool {
+    is_retryable_status(e.status_code().map(|status| status.as_u16()))
+}
+
+/// Whether a response with this status, or no response at all, is worth retrying
+pub fn is_retryable_status(status: Option<u16>) -> bool {
+    matches!(status, None | Some(429) | Some(500..=599))
+}
+
+/// Whether a retry loop should try the failed call again: rate limits (429), server
+/// errors (5xx), timeouts and transient DB errors. A 404 is never retried and other API
+/// errors (403...) fail fast. Deferred calls wait for the next cycle instead, as the
+/// budget that deferred them won't refill before then.
+pub fn is_retryable(err: &CrawlerError) -> bool {
+    matches!(
+        err,
+        CrawlerError::ApiTransient(..) | CrawlerError::DbTransient(..)
+    )
+}
+
+/// How long the Riot API asked us to wait before retrying, from a 429's `Retry-After`
+/// header. Other retryable errors back off by the retry site's own delay.
+pub fn retry_after(err: &CrawlerError) -> Option<Duration> {
+    match err {
+        CrawlerError::ApiTransient(_, e) => requested_wait(
+            e.status_code().map(|status| status.as_u16()),
+            e.response().map(reqwest::Response::headers),
+        ),
+        _ => None,
+    }
+}
+
+fn requested_wait(status: Option<u16>, headers: Option<&HeaderMap>) -> Option<Duration> {
+    if status != Some(429) {
+        return None;
+    }
+    parse_retry_after(headers?.get(RETRY_AFTER)?.to_str().ok()?)
+}
+
+/// How long a retry site should wait before trying again after `err`, or `None` to give
+/// up: not retryable, or the API asked for a longer wait than `max_retry_after`, see
+/// `Config::max_retry_after`. `backoff` is the site's own delay.
+pub fn retry_wait(
+    err: &CrawlerError,
+    backoff: Duration,
+    max_retry_after: Duration,
+) -> Option<Duration> {
+    if !is_retryable(err) {
+        return None;
+    }
+    wait_before_retry(retry_after(err), backoff, max_retry_after)
+}
+
+fn wait_before_retry(
+    requested: Option<Duration>,
+    backoff: Duration,
+    max_retry_after: Duration,
+) -> Option<Duration> {
+    match requested {
+        Some(wait) if wait > max_retry_after => None,
+        Some(wait) => Some(wait),
+        None => Some(backoff),
+    }
+}
+
+/// `Retry-After` in seconds, the only form the Riot API sends
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    value.trim().parse().ok().map(Duration::from_secs)
+}
+
+const DUPLICATE_KEY: i32 = 11000;
+
+fn is_duplicate_key(kind: &ErrorKind) -> bool {
+    match kind {
+        ErrorKind::WriteError(WriteFailure::WriteError(e)) => e.code == DUPLICATE_KEY,
+        ErrorKind::BulkWriteError(e) => e
+            .write_errors
+            .iter()
+            .flatten()
+            .any(|e| e.code == DUPLICATE_KEY),
+        ErrorKind::CommandError(e) => e.code == DUPLICATE_KEY,
+        _ => false,
+    }
+}
+
+impl std::fmt::Display for CrawlerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CrawlerError::ApiTransient(context, e) => {
+                write!(f, "{} (transient API error): {}", context, e)
+            }
+            CrawlerError::ApiNotFound(context, e) => write!(f, "{} (not found): {}", context, e),
+            CrawlerError::ApiFatal(context, e) => write!(f, "{} (API error): {}", context, e),
+            CrawlerError::DbTransient(context, e) => {
+                write!(f, "{} (transient DB error): {}", context, e)
+            }
+            CrawlerError::DbFatal(context, e) => write!(f, "{} (DB error): {}", context, e),
+            CrawlerError::Serialization(msg) => write!(f, "Serialization error: {}", msg),
+            CrawlerError::Deferred(context) => write!(f, "{} (deferred to a later cycle)", context),
+            CrawlerError::Internal(msg) => write!(f, "Internal error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for CrawlerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CrawlerError::ApiTransient(_, e)
+            | CrawlerError::ApiNotFound(_, e)
+            | CrawlerError::ApiFatal(_, e) => Some(e),
+            CrawlerError::DbTransient(_, e) | CrawlerError::DbFatal(_, e) => Some(e),
+            CrawlerError::Serialization(_)
+            | CrawlerError::Deferred(_)
+            | CrawlerError::Internal(_) => None,
+        }
+    }
+}
+
+impl From<serde_json::Error> for CrawlerError {
+    fn from(e: serde_json::Error) -> CrawlerError {
+        CrawlerError::serialization(e)
+    }
+}
+
+impl From<mongodb::bson::extjson::de::Error> for CrawlerError {
+    fn from(e: mongodb::bson::extjson::de::Error) -> CrawlerError {
+        CrawlerError::serialization(e)
+    }
+}
+
+impl From<mongodb::bson::document::ValueAccessError> for CrawlerError {
+    fn from(e: mongodb::bson::document::ValueAccessError) -> CrawlerError {
+        CrawlerError::serialization(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_db_classification() {
+        let io = mongodb::error::Error::from(std::io::Error::new(
+            std::io::ErrorKind::ConnectionReset,
+            "reset",
+        ));
+        assert!(CrawlerError::db("find_one", io).is_transient());
+
+        let unsupported = mongodb::error::Error::from(ErrorKind::SessionsNotSupported);
+        assert!(!CrawlerError::db("find_one", unsupported).is_transient());
+    }
+
+    #[test]
+    fn test_retryable_status() {
+        // 429, 5xx and timeouts (no response) retry, 404 never does, 403 etc. fail fast
+        for status in &[None, Some(429), Some(500), Some(503), Some(504)] {
+            assert!(is_retryable_status(*status), "{:?}", status);
+        }
+        for status in &[Some(404), Some(403), Some(401), Some(400)] {
+            assert!(!is_retryable_status(*status), "{:?}", status);
+        }
+    }
+
+    #[test]
+    fn test_is_retryable() {
+        let io = mongodb::error::Error::from(std::io::Error::new(
+            std::io::ErrorKind::TimedOut,
+            "timed out",
+        ));
+        let e = CrawlerError::db("find_one", io);
+        assert!(is_retryable(&e));
+        assert_eq!(retry_after(&e), None);
+        let backoff = Duration::from_millis(500);
+        assert_eq!(
+            retry_wait(&e, backoff, Duration::from_secs(60)),
+            Some(backoff)
+        );
+
+        let unsupported = mongodb::error::Error::from(ErrorKind::SessionsNotSupported);
+        assert!(!is_retryable(&CrawlerError::db("find_one", unsupported)));
+        assert!(!is_retryable(&CrawlerError::serialization(
+            "BSON is not a doc"
+        )));
+        // Transient, but retrying within the cycle won't get past the budget
+        let deferred = CrawlerError::Deferred("get_by_puuid");
+        assert!(!is_retryable(&deferred));
+        assert_eq!(retry_after(&deferred), None);
+        assert_eq!(
+            retry_wait(&deferred, Duration::from_secs(1), Duration::from_secs(60)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_requested_wait() {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, "7".parse().unwrap());
+        // Only a 429 asks for a wait; a 503 carrying the header backs off as usual
+        assert_eq!(
+            requested_wait(Some(429), Some(&headers)),
+            Some(Duration::from_secs(7))
+        );
+        assert_eq!(requested_wait(Some(503), Some(&headers)), None);
+        assert_eq!(requested_wait(Some(429), Some(&HeaderMap::new())), None);
+        assert_eq!(requested_wait(Some(429), None), None);
+        assert_eq!(requested_wait(None, None), None);
+    }
+
+    #[test]
+    fn test_wait_before_retry() {
+        let backoff = Duration::from_millis(500);
+        let max = Duration::from_secs(60);
+        // 429s wait as asked, up to the cap, beyond which the call gives up
+        let asked = Some(Duration::from_secs(10));
+        assert_eq!(wait_before_retry(asked, backoff, max), asked);
+        assert_eq!(wait_before_retry(Some(max), backoff, max), Some(max));
+        let too_long = Some(Duration::from_secs(61));
+        assert_eq!(wait_before_retry(too_long, backoff, max), None);
+        // 5xx and timeouts back off
+        assert_eq!(wait_before_retry(None, backoff, max), Some(backoff));
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after("7"), Some(Duration::from_secs(7)));
+        assert_eq!(parse_retry_after(" 0 "), Some(Duration::from_secs(0)));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), None);
+        assert_eq!(parse_retry_after("-1"), None);
+    }
+
+    #[test]
+    fn test_duplicate_key() {
+        let command_error = |code: i32| {
+            let e: mongodb::error::CommandError = mongodb::bson::from_document(
+                mongodb::bson::doc! {"code": code, "codeName": "", "errmsg": "", "errorLabels": []},
+            )
+            .unwrap();
+            mongodb::error::Error::from(ErrorKind::CommandError(e))
+        };
+        assert!(CrawlerError::db("insert_one", command_error(11000)).is_duplicate_key());
+        assert!(!CrawlerError::db("insert_one", command_error(26)).is_duplicate_key());
+        let unsupported = mongodb::error::Error::from(ErrorKind::SessionsNotSupported);
+        assert!(!CrawlerError::db("insert_one", unsupported).is_duplicate_key());
+    }
+
+    #[test]
+    fn test_serialization_is_not_transient() {
+        let e = CrawlerError::serialization("BSON is not a doc");
+        assert!(!e.is_transient());
+        assert_eq!(e.to_string(), "Serialization error: BSON is not a doc");
+    }
+
+    #[test]
+    fn test_deferred_is_transient() {
+        // So partial enrichment leaves the match for a later cycle instead of storing a placeholder
+        let e = CrawlerError::Deferred("get_by_puuid");
+        assert!(e.is_transient());
+        assert_eq!(e.to_string(), "get_by_puuid (deferred to a later cycle)");
+    }
+}
@@ -0,0 +1,77 @@
+use chrono::{DateTime, Duration, Utc, MAX_DATETIME};
+
+/// Expiry of a stored match: not until the game date was 4 days ago,
+/// and additionally not within the next 24 hours.
+pub fn match_expiry(now: DateTime<Utc>, match_timestamp: DateTime<Utc>) -> DateTime<Utc> {
+    std::cmp::max(
+        now + Duration::hours(24),
+        match_timestamp + Duration::days(4),
+    )
+}
+
+/// Expiry of a document kept for an operator configured `retention`. One too long to
+/// represent never expires, rather than panicking.
+pub fn retention_expiry(now: DateTime<Utc>, retention: std::time::Duration) -> DateTime<Utc> {
+    Duration::from_std(retention)
+        .ok()
+        .and_then(|retention| now.checked_add_signed(retention))
+        .unwrap_or(MAX_DATETIME)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn now() -> DateTime<Utc> {
+        Utc.ymd(2021, 3, 1).and_hms(12, 0, 0)
+    }
+
+    #[test]
+    fn test_match_expiry_new_match() {
+        // A match that just finished is kept for 4 days
+        let match_timestamp = now() - Duration::minutes(30);
+        assert_eq!(
+            match_expiry(now(), match_timestamp),
+            match_timestamp + Duration::days(4)
+        );
+    }
+
+    #[test]
+    fn test_match_expiry_old_match() {
+        // A 10 day old match is still kept for 24 hours after we store it
+        let match_timestamp = now() - Duration::days(10);
+        assert_eq!(
+            match_expiry(now(), match_timestamp),
+            now() + Duration::hours(24)
+        );
+    }
+
+    #[test]
+    fn test_match_expiry_boundary() {
+        // Exactly 3 days old: both rules give the same instant
+        let match_timestamp = now() - Duration::days(3);
+        assert_eq!(
+            match_expiry(now(), match_timestamp),
+            now() + Duration::hours(24)
+        );
+        assert_eq!(
+            match_expiry(now(), match_timestamp),
+            match_timestamp + Duration::days(4)
+        );
+
+        // One second either side picks the other branch
+        let newer = match_timestamp + Duration::seconds(1);
+        assert_eq!(match_expiry(now(), newer), newer + Duration::days(4));
+        let older = match_timestamp - Duration::seconds(1);
+        assert_eq!(match_expiry(now(), older), now() + Duration::hours(24));
+    }
+
+    #[test]
+    fn test_retention_expiry() {
+        let day = std::time::Duration::from_secs(24 * 60 * 60);
+        assert_eq!(retention_expiry(now(), day), now() + Duration::days(1));
+        let forever = std::time::Duration::from_secs(u64::MAX);
+        assert_eq!(retention_expiry(now(), forever), MAX_DATETIME);
+    }
+}
@@ -0,0 +1,73 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Outcomes of a region task's Riot API calls over a sliding window, for
+/// `Config::failure_ratio_threshold`
+pub struct FailureWindow {
+    window: Duration,
+    // (time, failed), oldest first
+    outcomes: VecDeque<(Instant, bool)>,
+}
+
+impl FailureWindow {
+    pub fn new(window: Duration) -> FailureWindow {
+        FailureWindow {
+            window,
+            outcomes: VecDeque::new(),
+        }
+    }
+
+    pub fn record(&mut self, now: Instant, failed: bool) {
+        self.prune(now);
+        self.outcomes.push_back((now, failed));
+    }
+
+    /// Fraction of calls in the window that failed and the number of calls, None if
+    /// there were fewer than `min_calls` to judge by
+    pub fn failure_ratio(&mut self, now: Instant, min_calls: usize) -> Option<(f64, usize)> {
+        self.prune(now);
+        let total = self.outcomes.len();
+        if total == 0 || total < min_calls {
+            return None;
+        }
+        let failed = self.outcomes.iter().filter(|(_, failed)| *failed).count();
+        Some((failed as f64 / total as f64, total))
+    }
+
+    /// Forget everything, e.g. after pausing so the old failures don't trip it again
+    pub fn clear(&mut self) {
+        self.outcomes.clear();
+    }
+
+    fn prune(&mut self, now: Instant) {
+        while let Some((time, _)) = self.outcomes.front() {
+            if now.duration_since(*time) <= self.window {
+                break;
+            }
+            self.outcomes.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_failure_window() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut window = FailureWindow::new(Duration::from_secs(60));
+        for i in 0..10 {
+            window.record(at(i), i % 5 == 0);
+        }
+        assert_eq!(window.failure_ratio(at(10), 20), None);
+        assert_eq!(window.failure_ratio(at(10), 10), Some((0.2, 10)));
+        // The first five calls, one failed, have left the window
+        assert_eq!(window.failure_ratio(at(65), 1), Some((0.2, 5)));
+        window.record(at(65), true);
+        assert_eq!(window.failure_ratio(at(65), 1), Some((2.0 / 6.0, 6)));
+        window.clear();
+        assert_eq!(window.failure_ratio(at(65), 1), None);
+    }
+}
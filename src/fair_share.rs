@@ -0,0 +1,247 @@
+use riven::consts::Region;
+use std::collections::{HashMap, VecDeque};
+use std::str::FromStr;
+use std::sync::Mutex;
+use tokio::sync::oneshot;
+
+/// Relative share of a cluster's match calls for each platform, `EUW=3,EUNE=1`.
+/// Platforms not listed have weight 1.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RegionWeights(HashMap<Region, u32>);
+
+impl RegionWeights {
+    pub fn weight(&self, region: Region) -> u32 {
+        self.0.get(&region).copied().unwrap_or(1)
+    }
+}
+
+impl FromStr for RegionWeights {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<RegionWeights, String> {
+        let mut weights = HashMap::new();
+        for entry in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (region, weight) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("Expected REGION=weight, got {}", entry))?;
+            let region: Region = region
+                .trim()
+                .parse()
+                .map_err(|_| format!("Unknown region {}", region))?;
+            let weight: u32 = weight
+                .trim()
+                .parse()
+                .map_err(|_| format!("Invalid weight {}", weight))?;
+            if weight == 0 {
+                return Err(format!("Weight of {:?} must be positive", region));
+            }
+            weights.insert(region, weight);
+        }
+        Ok(RegionWeights(weights))
+    }
+}
+
+/// Among the platforms with callers waiting, the one furthest below its share:
+/// the lowest calls in flight per unit of weight. Ties go to the first listed.
+pub fn next_region(
+    in_flight: &HashMap<Region, usize>,
+    waiting: &[Region],
+    weights: &RegionWeights,
+) -> Option<Region> {
+    let load = |region: &Region| {
+        in_flight.get(region).copied().unwrap_or(0) as f64 / f64::from(weights.weight(*region))
+    };
+    waiting
+        .iter()
+        .copied()
+        .fold(None, |best: Option<Region>, region| match best {
+            Some(best) if load(&best) <= load(&region) => Some(best),
+            _ => Some(region),
+        })
+}
+
+/// Match call slots of one major-region cluster, shared between the platforms routed to
+/// it. A free slot goes straight to any caller; once all are taken, each freed slot goes
+/// to the waiting platform furthest below its weighted share, so a busy platform can't
+/// starve a quieter one on the same cluster.
+pub struct FairShare {
+    capacity: usize,
+    weights: RegionWeights,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    in_flight: HashMap<Region, usize>,
+    // Per platform, in the order platforms first had to wait
+    waiting: Vec<(Region, VecDeque<oneshot::Sender<()>>)>,
+}
+
+/// Held for the duration of one call
+pub struct FairSharePermit<'a> {
+    share: &'a FairShare,
+    region: Region,
+}
+
+impl Drop for FairSharePermit<'_> {
+    fn drop(&mut self) {
+        self.share.release(self.region);
+    }
+}
+
+/// A caller waiting in `FairShare::acquire`. If the caller is cancelled after `release`
+/// handed it a slot but before it took it, the slot is released again instead of lost.
+struct Handover<'a> {
+    share: &'a FairShare,
+    region: Region,
+    // None once the slot is taken
+    rx: Option<oneshot::Receiver<()>>,
+}
+
+impl Drop for Handover<'_> {
+    fn drop(&mut self) {
+        if let Some(rx) = &mut self.rx {
+            // No slot can be handed over after this
+            rx.close();
+            if rx.try_recv().is_ok() {
+                self.share.release(self.region);
+            }
+        }
+    }
+}
+
+impl FairShare {
+    pub fn new(capacity: usize, weights: RegionWeights) -> FairShare {
+        FairShare {
+            capacity,
+            weights,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    pub async fn acquire(&self, region: Region) -> FairSharePermit<'_> {
+        let rx = {
+            let mut state = self.state.lock().unwrap();
+            let total: usize = state.in_flight.values().sum();
+            if total < self.capacity && state.waiting.is_empty() {
+                *state.in_flight.entry(region).or_insert(0) += 1;
+                None
+            } else {
+                let (tx, rx) = oneshot::channel();
+                match state.waiting.iter_mut().find(|(r, _)| *r == region) {
+                    Some((_, queue)) => queue.push_back(tx),
+                    None => state.waiting.push((region, VecDeque::from(vec![tx]))),
+                }
+                Some(rx)
+            }
+        };
+        if let Some(rx) = rx {
+            let mut handover = Handover {
+                share: self,
+                region,
+                rx: Some(rx),
+            };
+            // The releasing caller hands its slot over, already counted for us
+            if let Some(rx) = &mut handover.rx {
+                rx.await.expect("Fair share dropped");
+            }
+            handover.rx = None;
+        }
+        FairSharePermit {
+            share: self,
+            region,
+        }
+    }
+
+    fn release(&self, region: Region) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(n) = state.in_flight.get_mut(&region) {
+            *n -= 1;
+        }
+        loop {
+            let waiting: Vec<Region> = state.waiting.iter().map(|(r, _)| *r).collect();
+            let next = match next_region(&state.in_flight, &waiting, &self.weights) {
+                Some(next) => next,
+                None => return,
+            };
+            let index = waiting.iter().position(|r| *r == next).unwrap();
+            let tx = state.waiting[index].1.pop_front().unwrap();
+            if state.waiting[index].1.is_empty() {
+                state.waiting.remove(index);
+            }
+            // A closed receiver was cancelled, offer the slot to the next caller
+            if tx.send(()).is_ok() {
+                *state.in_flight.entry(next).or_insert(0) += 1;
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_region_weights() {
+        let weights: RegionWeights = "EUW=3, EUNE=1".parse().unwrap();
+        assert_eq!(weights.weight(Region::EUW), 3);
+        assert_eq!(weights.weight(Region::EUNE), 1);
+        assert_eq!(weights.weight(Region::NA), 1);
+        assert!("EUW".parse::<RegionWeights>().is_err());
+        assert!("EUW=0".parse::<RegionWeights>().is_err());
+        assert!("XX=1".parse::<RegionWeights>().is_err());
+    }
+
+    #[test]
+    fn test_next_region() {
+        let weights: RegionWeights = "EUW=3".parse().unwrap();
+        let in_flight: HashMap<Region, usize> = vec![(Region::EUW, 4), (Region::EUNE, 1)]
+            .into_iter()
+            .collect();
+        // EUW has 4/3 per weight, EUNE 1/1
+        assert_eq!(
+            next_region(&in_flight, &[Region::EUW, Region::EUNE], &weights),
+            Some(Region::EUNE)
+        );
+        let in_flight: HashMap<Region, usize> = vec![(Region::EUW, 2), (Region::EUNE, 1)]
+            .into_iter()
+            .collect();
+        assert_eq!(
+            next_region(&in_flight, &[Region::EUNE, Region::EUW], &weights),
+            Some(Region::EUW)
+        );
+        assert_eq!(next_region(&in_flight, &[], &weights), None);
+    }
+
+    #[tokio::test]
+    async fn test_fair_share_hands_over_slots() {
+        let share = FairShare::new(2, RegionWeights::default());
+        let first = share.acquire(Region::EUW).await;
+        let _second = share.acquire(Region::EUW).await;
+
+        // Full, so both wait; the freed slot goes to EUNE, which has none
+        let euw = share.acquire(Region::EUW);
+        let eune = share.acquire(Region::EUNE);
+        tokio::pin!(euw, eune);
+        assert!(futures::poll!(euw.as_mut()).is_pending());
+        assert!(futures::poll!(eune.as_mut()).is_pending());
+        drop(first);
+        assert!(futures::poll!(euw.as_mut()).is_pending());
+        assert!(futures::poll!(eune.as_mut()).is_ready());
+    }
+
+    #[tokio::test]
+    async fn test_fair_share_cancelled_handover() {
+        let share = FairShare::new(1, RegionWeights::default());
+        let first = share.acquire(Region::EUW).await;
+        let mut waiting = Box::pin(share.acquire(Region::EUNE));
+        assert!(futures::poll!(waiting.as_mut()).is_pending());
+        // The slot is handed to the waiter, which is cancelled before taking it
+        drop(first);
+        drop(waiting);
+        let next = share.acquire(Region::EUW);
+        tokio::pin!(next);
+        assert!(futures::poll!(next.as_mut()).is_ready());
+    }
+}
@@ -0,0 +1,127 @@
+use mongodb::bson::{Bson, Document};
+use riven::models::tft_match_v1::Match;
+
+/// One row per participant for columnar analytics: the match-level fields in
+/// `match_fields` (avg elo, patch, timestamps...) repeated on every row, alongside the
+/// participant's results and their `_aggregatedPlayerInfo` entry (matched by puuid).
+pub fn flat_rows(game: &Match, player_data: &[Bson], match_fields: &Document) -> Vec<Document> {
+    game.info
+        .participants
+        .iter()
+        .map(|participant| {
+            let mut row = match_fields.clone();
+            row.insert(
+                "_id",
+                format!("{}_{}", game.metadata.match_id, participant.puuid),
+            );
+            row.insert("matchId", game.metadata.match_id.clone());
+            row.insert("puuid", participant.puuid.clone());
+            row.insert("placement", participant.placement);
+            row.insert("level", participant.level);
+            row.insert("lastRound", participant.last_round);
+            row.insert("goldLeft", participant.gold_left);
+            row.insert("playersEliminated", participant.players_eliminated);
+            row.insert("totalDamageToPlayers", participant.total_damage_to_players);
+            row.insert("timeEliminated", f64::from(participant.time_eliminated));
+            // Active traits and units as flat lists of ids
+            let traits: Vec<Bson> = participant
+                .traits
+                .iter()
+                .filter(|t| t.tier_current > 0)
+                .map(|t| Bson::String(format!("{}:{}", t.name, t.tier_current)))
+                .collect();
+            row.insert("traits", traits);
+            let units: Vec<Bson> = participant
+                .units
+                .iter()
+                .map(|u| Bson::String(u.character_id.clone()))
+                .collect();
+            row.insert("units", units);
+
+            let info = player_data
+                .iter()
+                .filter_map(Bson::as_document)
+                .find(|info| info.get_str("puuid") == Ok(participant.puuid.as_str()));
+            if let Some(info) = info {
+                for key in &["summonerId", "tftTier", "tftRank", "tftLeaguePoints"] {
+                    if let Some(value) = info.get(key) {
+                        row.insert(key.to_string(), value.clone());
+                    }
+                }
+            }
+            row
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::bson::doc;
+
+    #[test]
+    fn test_flat_rows() {
+        let participant = |puuid: &str, placement: i32| {
+            serde_json::json!({
+                "companion": {"skin_ID": 1, "content_ID": "c", "species": "s"},
+                "gold_left": 3,
+                "last_round": 30,
+                "level": 8,
+                "placement": placement,
+                "players_eliminated": 1,
+                "puuid": puuid,
+                "time_eliminated": 1800.5,
+                "total_damage_to_players": 100,
+                "traits": [
+                    {"name": "Set5_Forgotten", "num_units": 6, "tier_current": 3, "tier_total": 3},
+                    {"name": "Set5_Knight", "num_units": 1, "tier_current": 0, "tier_total": 3},
+                ],
+                "units": [
+                    {"items": [], "character_id": "TFT5_Viego", "name": "", "rarity": 4, "tier": 2},
+                ],
+            })
+        };
+        let game: Match = serde_json::from_value(serde_json::json!({
+            "metadata": {
+                "data_version": "5",
+                "match_id": "EUW1_1",
+                "participants": ["a", "b"],
+            },
+            "info": {
+                "game_datetime": 1600000000000i64,
+                "game_length": 2000.0,
+                "game_version": "Version 11.1",
+                "participants": [participant("a", 2), participant("b", 1)],
+                "queue_id": 1100,
+                "tft_set_number": 5,
+            },
+        }))
+        .unwrap();
+        let player_data: Vec<Bson> = vec![
+            doc! {"puuid": "b", "summonerId": "sb", "tftTier": "MASTER", "tftRank": "I", "tftLeaguePoints": 50}
+                .into(),
+            doc! {"puuid": "a", "summonerId": "sa", "tftTier": "unranked", "tftRank": "unranked", "tftLeaguePoints": i32::MIN}
+                .into(),
+        ];
+        let match_fields = doc! {"_avgElo": 2450, "_patch": "11.1"};
+
+        let rows = flat_rows(&game, &player_data, &match_fields);
+        assert_eq!(rows.len(), 2);
+        let row = &rows[1];
+        assert_eq!(row.get_str("_id").unwrap(), "EUW1_1_b");
+        assert_eq!(row.get_i32("_avgElo").unwrap(), 2450);
+        assert_eq!(row.get_str("_patch").unwrap(), "11.1");
+        assert_eq!(row.get_i32("placement").unwrap(), 1);
+        assert_eq!(row.get_str("summonerId").unwrap(), "sb");
+        assert_eq!(row.get_str("tftTier").unwrap(), "MASTER");
+        assert_eq!(
+            row.get_array("traits").unwrap(),
+            &vec![Bson::String("Set5_Forgotten:3".to_string())]
+        );
+        assert_eq!(
+            row.get_array("units").unwrap(),
+            &vec![Bson::String("TFT5_Viego".to_string())]
+        );
+        assert_eq!(rows[0].get_str("summonerId").unwrap(), "sa");
+    }
+}
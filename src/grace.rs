@@ -0,0 +1,67 @@
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+
+/// Matches that were listed in a match history but not found by `get_match`, keyed by
+/// match id with the time of the first miss. A match a few minutes old may not be
+/// indexed yet, so it is only negatively cached once it has kept missing for the
+/// grace period, and is retried on later cycles until then.
+#[derive(Default)]
+pub struct NotFoundGrace {
+    first_missed: HashMap<String, DateTime<Utc>>,
+}
+
+impl NotFoundGrace {
+    /// Record a miss for `id`, returning whether it should now be negatively cached
+    pub fn expired(&mut self, id: &str, now: DateTime<Utc>, grace: Duration) -> bool {
+        // Drop matches that stopped being listed, long after they would have expired
+        let keep_for = grace
+            .checked_add(&grace)
+            .unwrap_or_else(Duration::max_value);
+        self.first_missed
+            .retain(|_, first_missed| now - *first_missed <= keep_for);
+        let first_missed = *self.first_missed.entry(id.to_string()).or_insert(now);
+        if now - first_missed >= grace {
+            self.first_missed.remove(id);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_not_found_grace() {
+        let now = Utc.ymd(2021, 3, 1).and_hms(12, 0, 0);
+        let grace = Duration::minutes(15);
+        let mut misses = NotFoundGrace::default();
+        assert!(!misses.expired("EUW1_1", now, grace));
+        assert!(!misses.expired("EUW1_1", now + Duration::minutes(10), grace));
+        assert!(!misses.expired("EUW1_2", now + Duration::minutes(10), grace));
+        assert!(misses.expired("EUW1_1", now + Duration::minutes(15), grace));
+        // Forgotten once cached
+        assert!(!misses.expired("EUW1_1", now + Duration::minutes(16), grace));
+        // Pruned after twice the grace period unseen, so it starts over
+        assert!(!misses.expired("EUW1_2", now + Duration::minutes(41), grace));
+    }
+
+    #[test]
+    fn test_no_grace() {
+        let now = Utc.ymd(2021, 3, 1).and_hms(12, 0, 0);
+        let mut misses = NotFoundGrace::default();
+        assert!(misses.expired("EUW1_1", now, Duration::zero()));
+    }
+
+    #[test]
+    fn test_saturated_grace() {
+        // A grace period too long to represent never expires, and doesn't overflow
+        let now = Utc.ymd(2021, 3, 1).and_hms(12, 0, 0);
+        let mut misses = NotFoundGrace::default();
+        assert!(!misses.expired("EUW1_1", now, Duration::max_value()));
+        assert!(!misses.expired("EUW1_1", now + Duration::days(365), Duration::max_value()));
+    }
+}
@@ -0,0 +1,229 @@
+use chrono::Utc;
+use futures::future::BoxFuture;
+use log::error;
+use mongodb::bson::{Bson, Document};
+use riven::models::tft_match_v1::Match;
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::error::Result;
+use crate::interop::InteropMatch;
+use crate::{
+    daily_rollup, flat, meta_stats, CrawlerError, Main, DAILY_ROLLUP_COLLECTION_NAME,
+    INTEROP_COLLECTION_NAME, META_STATS_COLLECTION_NAME,
+};
+
+/// A match `process_match_id` has just stored
+pub struct StoredMatch<'a> {
+    pub id: &'a str,
+    pub game: &'a Match,
+    /// The document as inserted
+    pub doc: &'a Document,
+}
+
+/// Post-processing run after a match is stored, in registration order. The match is
+/// already stored, so a failing hook is logged and the rest still run.
+pub trait MatchHook: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn after_insert<'a>(
+        &'a self,
+        main: &'a Main,
+        stored: &'a StoredMatch<'a>,
+    ) -> BoxFuture<'a, Result<()>>;
+}
+
+/// Hooks enabled by the configuration. Register further hooks here.
+pub fn configured(config: &Config) -> Vec<Arc<dyn MatchHook>> {
+    let mut hooks: Vec<Arc<dyn MatchHook>> = vec![];
+    if config.meta_stats {
+        hooks.push(Arc::new(MetaStats));
+    }
+    if config.flat_participations {
+        hooks.push(Arc::new(FlatParticipations));
+    }
+    if config.interop_matches {
+        hooks.push(Arc::new(InteropMatches));
+    }
+    if config.daily_rollup {
+        hooks.push(Arc::new(DailyRollup));
+    }
+    hooks
+}
+
+/// Run every hook for a stored match
+pub async fn run_all(main: &Main, hooks: &[Arc<dyn MatchHook>], stored: &StoredMatch<'_>) {
+    for hook in hooks {
+        if let Err(e) = hook.after_insert(main, stored).await {
+            error!("Hook {} failed for match {}: {}", hook.name(), stored.id, e);
+        }
+    }
+}
+
+fn player_data(doc: &Document) -> &[Bson] {
+    doc.get_array("_aggregatedPlayerInfo")
+        .map(Vec::as_slice)
+        .unwrap_or(&[])
+}
+
+/// Counts the match in `META_STATS_COLLECTION_NAME`, see `Config::meta_stats`
+struct MetaStats;
+
+impl MatchHook for MetaStats {
+    fn name(&self) -> &'static str {
+        "meta-stats"
+    }
+
+    fn after_insert<'a>(
+        &'a self,
+        main: &'a Main,
+        stored: &'a StoredMatch<'a>,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let placements = meta_stats::bracket_placements(
+                &stored.game.info.participants,
+                player_data(stored.doc),
+            );
+            let command = meta_stats::update_command(
+                &main.suffixed(META_STATS_COLLECTION_NAME),
+                stored.game.info.tft_set_number,
+                &placements,
+            );
+            main.db_call("Error updating meta stats", || {
+                main.db.run_command(command.clone(), None)
+            })
+            .await?;
+            Ok(())
+        })
+    }
+}
+
+// Match-level fields repeated on every flat row
+const FLAT_MATCH_FIELDS: &[&str] = &[
+    "_region",
+    "_tftSet",
+    "_patch",
+    "_matchTimestamp",
+    "_matchTimestampIso",
+    "_documentExpire",
+    "_documentExpireIso",
+    "_avgElo",
+    "_avgEloText",
+];
+
+/// Writes one row per participant, see `Config::flat_participations`
+struct FlatParticipations;
+
+impl MatchHook for FlatParticipations {
+    fn name(&self) -> &'static str {
+        "flat-participations"
+    }
+
+    fn after_insert<'a>(
+        &'a self,
+        main: &'a Main,
+        stored: &'a StoredMatch<'a>,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let mut match_fields = Document::new();
+            for key in FLAT_MATCH_FIELDS {
+                if let Some(value) = stored.doc.get(key) {
+                    match_fields.insert(*key, value.clone());
+                }
+            }
+            let rows = flat::flat_rows(stored.game, player_data(stored.doc), &match_fields);
+            let collection = main
+                .db
+                .collection::<Document>(&main.flat_participations_collection);
+            let _permit = main.write_permit().await;
+            main.db_call("Error inserting flat participations", || {
+                collection.insert_many(rows.clone(), None)
+            })
+            .await?;
+            Ok(())
+        })
+    }
+}
+
+/// Writes the match as an `InteropMatch`, see `Config::interop_matches`
+struct InteropMatches;
+
+impl MatchHook for InteropMatches {
+    fn name(&self) -> &'static str {
+        "interop-matches"
+    }
+
+    fn after_insert<'a>(
+        &'a self,
+        main: &'a Main,
+        stored: &'a StoredMatch<'a>,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let doc = InteropMatch::new(stored.game, stored.doc)
+                .to_document()
+                .map_err(CrawlerError::serialization)?;
+            let collection = main
+                .db
+                .collection::<Document>(&main.suffixed(INTEROP_COLLECTION_NAME));
+            let _permit = main.write_permit().await;
+            main.db_call("Error inserting interop match", || {
+                collection.insert_one(doc.clone(), None)
+            })
+            .await?;
+            Ok(())
+        })
+    }
+}
+
+/// Counts the match in today's rollup, see `Config::daily_rollup`
+struct DailyRollup;
+
+impl MatchHook for DailyRollup {
+    fn name(&self) -> &'static str {
+        "daily-rollup"
+    }
+
+    fn after_insert<'a>(
+        &'a self,
+        main: &'a Main,
+        stored: &'a StoredMatch<'a>,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let avg_elo = stored.doc.get_i32("_avgElo").unwrap_or(i32::MIN);
+            let command = daily_rollup::update_command(
+                &main.suffixed(DAILY_ROLLUP_COLLECTION_NAME),
+                main.region.as_ref(),
+                &format!("{:?}", main.queue_type),
+                &daily_rollup::day(Utc::now()),
+                daily_rollup::match_inc(avg_elo),
+            );
+            let _permit = main.write_permit().await;
+            main.db_call("Error updating daily rollup", || {
+                main.db.run_command(command.clone(), None)
+            })
+            .await?;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_configured() {
+        let names = |config: &Config| -> Vec<&'static str> {
+            configured(config).iter().map(|hook| hook.name()).collect()
+        };
+        let mut config = Config::default();
+        assert!(names(&config).is_empty());
+        config.meta_stats = true;
+        config.flat_participations = true;
+        assert_eq!(names(&config), vec!["meta-stats", "flat-participations"]);
+        config.interop_matches = true;
+        assert_eq!(names(&config).last(), Some(&"interop-matches"));
+        config.daily_rollup = true;
+        assert_eq!(names(&config).last(), Some(&"daily-rollup"));
+    }
+}
@@ -0,0 +1,43 @@
+/// Riot's encrypted ids are URL-safe base64: letters, digits, `-` and `_`
+fn is_id_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'-' || c == b'_'
+}
+
+/// An encrypted puuid, always 78 characters
+pub fn is_valid_puuid(puuid: &str) -> bool {
+    puuid.len() == 78 && puuid.bytes().all(is_id_char)
+}
+
+/// An encrypted summoner id, at most 63 characters
+pub fn is_valid_summoner_id(summoner_id: &str) -> bool {
+    (1..=63).contains(&summoner_id.len()) && summoner_id.bytes().all(is_id_char)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PUUID: &str =
+        "eXb3E8Qd_lXjQbDzqDn2yFvAEhBOIjBsc6OaLT-lA8fbSQU4Ui0bSWQgJb5rlEOBOv8vRhcuE61sqw";
+
+    #[test]
+    fn test_is_valid_puuid() {
+        assert!(is_valid_puuid(PUUID));
+        assert!(!is_valid_puuid(""));
+        assert!(!is_valid_puuid(&PUUID[1..]));
+        assert!(!is_valid_puuid(&PUUID.replace('_', "/")));
+        assert!(!is_valid_puuid(&format!("{} ", &PUUID[1..])));
+    }
+
+    #[test]
+    fn test_is_valid_summoner_id() {
+        assert!(is_valid_summoner_id(
+            "u6u8Cq5SkmY8jAeThpHxrRCXVt9SZ0lcWOMqvhD_7zCIaaA"
+        ));
+        assert!(is_valid_summoner_id(&"a".repeat(63)));
+        assert!(!is_valid_summoner_id(&"a".repeat(64)));
+        assert!(!is_valid_summoner_id(""));
+        assert!(!is_valid_summoner_id("unknown id"));
+        assert!(!is_valid_summoner_id("null\n"));
+    }
+}
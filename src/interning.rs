@@ -0,0 +1,198 @@
+use futures::stream::StreamExt;
+use mongodb::bson::{doc, Bson, Document};
+use mongodb::options::{FindOneAndUpdateOptions, ReturnDocument};
+use mongodb::Database;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Mutex;
+
+use crate::error::{CrawlerError, Result};
+
+/// `_id` of the string table's counter document, holding the last id handed out
+const COUNTER_ID: &str = "__lastId";
+
+/// Short integer ids for strings repeated across stored matches, see
+/// `Config::intern_traits`. The string table holds one `{_id: <string>, id: <int>}`
+/// document per string. Ids come from a counter document incremented atomically, so
+/// concurrent tasks never hand out the same id twice; if two tasks intern the same new
+/// string at once, the first insert wins and the other task uses its id.
+pub struct StringTable {
+    collection_name: String,
+    // Strings already known to this process
+    ids: Mutex<HashMap<String, i32>>,
+}
+
+impl StringTable {
+    pub fn new(collection_name: &str) -> StringTable {
+        StringTable {
+            collection_name: collection_name.to_string(),
+            ids: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The id of each of `strings`, adding those not seen before to the table
+    pub async fn ids(&self, db: &Database, strings: &[String]) -> Result<HashMap<String, i32>> {
+        let mut ids = HashMap::new();
+        for string in strings {
+            let known = self.ids.lock().unwrap().get(string).copied();
+            let id = match known {
+                Some(id) => id,
+                None => {
+                    let id = self.intern(db, string).await?;
+                    self.ids.lock().unwrap().insert(string.clone(), id);
+                    id
+                }
+            };
+            ids.insert(string.clone(), id);
+        }
+        Ok(ids)
+    }
+
+    async fn intern(&self, db: &Database, string: &str) -> Result<i32> {
+        let collection = db.collection::<Document>(&self.collection_name);
+        if let Some(id) = self.stored_id(db, string).await? {
+            return Ok(id);
+        }
+        let counter = collection
+            .find_one_and_update(
+                doc! {"_id": COUNTER_ID},
+                doc! {"$inc": {"id": 1}},
+                FindOneAndUpdateOptions::builder()
+                    .upsert(true)
+                    .return_document(ReturnDocument::After)
+                    .build(),
+            )
+            .await
+            .map_err(|e| CrawlerError::db("Error allocating string id", e))?
+            .ok_or_else(|| CrawlerError::serialization("String table counter missing"))?;
+        let id = counter.get_i32("id")?;
+        match collection
+            .insert_one(doc! {"_id": string, "id": id}, None)
+            .await
+        {
+            Ok(_) => Ok(id),
+            Err(e) => {
+                let e = CrawlerError::db("Error adding to string table", e);
+                if !e.is_duplicate_key() {
+                    return Err(e);
+                }
+                // Interned by another task in the meantime, the allocated id goes unused
+                self.stored_id(db, string).await?.ok_or_else(|| {
+                    CrawlerError::serialization(format!("{} left the string table", string))
+                })
+            }
+        }
+    }
+
+    async fn stored_id(&self, db: &Database, string: &str) -> Result<Option<i32>> {
+        let stored = db
+            .collection::<Document>(&self.collection_name)
+            .find_one(doc! {"_id": string}, None)
+            .await
+            .map_err(|e| CrawlerError::db("Error reading string table", e))?;
+        match stored {
+            Some(stored) => Ok(Some(stored.get_i32("id")?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// The whole string table keyed by id, for `decode_traits`
+pub async fn load_names(db: &Database, collection_name: &str) -> Result<HashMap<i32, String>> {
+    let mut cursor = db
+        .collection::<Document>(collection_name)
+        .find(doc! {"_id": {"$ne": COUNTER_ID}}, None)
+        .await
+        .map_err(|e| CrawlerError::db("Error reading string table", e))?;
+    let mut names = HashMap::new();
+    while let Some(entry) = cursor.next().await {
+        let entry = entry.map_err(|e| CrawlerError::db("Error reading string table", e))?;
+        names.insert(entry.get_i32("id")?, entry.get_str("_id")?.to_string());
+    }
+    Ok(names)
+}
+
+fn traits_mut(player_data: &mut [Bson]) -> impl Iterator<Item = &mut Document> {
+    player_data
+        .iter_mut()
+        .filter_map(Bson::as_document_mut)
+        .filter_map(|entry| entry.get_array_mut("traits").ok())
+        .flat_map(|traits| traits.iter_mut().filter_map(Bson::as_document_mut))
+}
+
+/// Distinct trait names in the `_aggregatedPlayerInfo` entries, see `add_traits`
+pub fn trait_names(player_data: &[Bson]) -> Vec<String> {
+    let names: BTreeSet<&str> = player_data
+        .iter()
+        .filter_map(Bson::as_document)
+        .filter_map(|entry| entry.get_array("traits").ok())
+        .flatten()
+        .filter_map(Bson::as_document)
+        .filter_map(|t| t.get_str("name").ok())
+        .collect();
+    names.into_iter().map(str::to_string).collect()
+}
+
+/// Replace each trait's `name` with its `nameId`. `ids` must hold every name.
+pub fn intern_traits(player_data: &mut [Bson], ids: &HashMap<String, i32>) {
+    for t in traits_mut(player_data) {
+        if let Some(Bson::String(name)) = t.remove("name") {
+            t.insert("nameId", ids[&name]);
+        }
+    }
+}
+
+/// Undo `intern_traits`, given the table from `load_names`. Ids missing from the table
+/// are left as they are.
+pub fn decode_traits(player_data: &mut [Bson], names: &HashMap<i32, String>) {
+    for t in traits_mut(player_data) {
+        let name = match t.get_i32("nameId").ok().and_then(|id| names.get(&id)) {
+            Some(name) => name.clone(),
+            None => continue,
+        };
+        t.remove("nameId");
+        t.insert("name", name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intern_traits() {
+        let original: Vec<Bson> = vec![
+            doc! {"puuid": "a", "traits": [
+                {"name": "Set4_Cultist", "tierCurrent": 2},
+                {"name": "Set4_Mystic", "tierCurrent": 1},
+            ]}
+            .into(),
+            doc! {"puuid": "b", "traits": [{"name": "Set4_Cultist", "tierCurrent": 1}]}.into(),
+            // Below `EnrichmentLevel::Full`
+            doc! {"puuid": "c"}.into(),
+        ];
+        let names = trait_names(&original);
+        assert_eq!(names, vec!["Set4_Cultist", "Set4_Mystic"]);
+
+        let ids: HashMap<String, i32> = names.into_iter().zip(1..).collect();
+        let mut player_data = original.clone();
+        intern_traits(&mut player_data, &ids);
+        assert_eq!(
+            player_data[1],
+            Bson::from(doc! {"puuid": "b", "traits": [{"tierCurrent": 1, "nameId": 1}]})
+        );
+        assert!(trait_names(&player_data).is_empty());
+
+        let table: HashMap<i32, String> = ids.into_iter().map(|(name, id)| (id, name)).collect();
+        decode_traits(&mut player_data, &table);
+        assert_eq!(trait_names(&player_data), trait_names(&original));
+        let cultist = player_data[0]
+            .as_document()
+            .unwrap()
+            .get_array("traits")
+            .unwrap()[0]
+            .as_document()
+            .unwrap();
+        assert_eq!(cultist.get_str("name"), Ok("Set4_Cultist"));
+        assert_eq!(cultist.get_i32("tierCurrent"), Ok(2));
+    }
+}
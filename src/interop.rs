@@ -0,0 +1,236 @@
+use mongodb::bson::{Bson, Document};
+use riven::models::tft_match_v1::Match;
+use serde::{Deserialize, Serialize};
+
+use crate::numeric_league_util::try_league_to_numeric;
+
+/// A stored match in a flat, self-describing shape for outside tooling, written to
+/// `interop-matches-4-1` with `_id` the match id. Mapping from the stored match:
+///
+/// | field          | from                                                       |
+/// |----------------|------------------------------------------------------------|
+/// | `matchId`      | `metadata.match_id`                                        |
+/// | `region`       | `_region`, the platform e.g. `EUW1`                        |
+/// | `patch`        | `_patch`, e.g. `11.1`                                      |
+/// | `set`          | `info.tft_set_number`                                      |
+/// | `gameDatetime` | `info.game_datetime`, epoch milliseconds                   |
+/// | `gameLength`   | `info.game_length`, seconds                                |
+/// | `avgElo`       | `_avgElo`, null for an unranked lobby                      |
+/// | `avgRank`      | `_avgEloText`                                              |
+/// | `players`      | one per participant in finishing order, see `InteropPlayer` |
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InteropMatch {
+    pub match_id: String,
+    pub region: String,
+    pub patch: Option<String>,
+    pub set: i32,
+    pub game_datetime: i64,
+    pub game_length: f32,
+    pub avg_elo: Option<i32>,
+    pub avg_rank: String,
+    pub players: Vec<InteropPlayer>,
+}
+
+/// A participant's result and board. `tier`, `division`, `leaguePoints` and `elo`
+/// (`league_to_numeric`) come from their `_aggregatedPlayerInfo` entry and are null
+/// when they're unranked or weren't looked up. Traits are the active ones only.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InteropPlayer {
+    pub puuid: String,
+    pub placement: i32,
+    pub level: i32,
+    pub gold_left: i32,
+    pub tier: Option<String>,
+    pub division: Option<String>,
+    pub league_points: Option<i32>,
+    pub elo: Option<i32>,
+    pub traits: Vec<InteropTrait>,
+    pub units: Vec<InteropUnit>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InteropTrait {
+    pub name: String,
+    pub tier: i32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InteropUnit {
+    pub character_id: String,
+    pub star_level: i32,
+    pub items: Vec<i32>,
+}
+
+impl InteropMatch {
+    /// From a match and the document `process_match_id` stored for it
+    pub fn new(game: &Match, doc: &Document) -> InteropMatch {
+        let player_data = doc
+            .get_array("_aggregatedPlayerInfo")
+            .map(Vec::as_slice)
+            .unwrap_or(&[]);
+        let mut players: Vec<InteropPlayer> = game
+            .info
+            .participants
+            .iter()
+            .map(|participant| {
+                let entry = player_data
+                    .iter()
+                    .filter_map(Bson::as_document)
+                    .find(|entry| entry.get_str("puuid") == Ok(participant.puuid.as_str()));
+                let rank = entry.and_then(|entry| {
+                    let tier = entry.get_str("tftTier").ok()?;
+                    let division = entry.get_str("tftRank").ok()?;
+                    let league_points = entry.get_i32("tftLeaguePoints").ok()?;
+                    let elo = try_league_to_numeric(tier, division, league_points)?;
+                    Some((tier, division, league_points, elo))
+                });
+                InteropPlayer {
+                    puuid: participant.puuid.clone(),
+                    placement: participant.placement,
+                    level: participant.level,
+                    gold_left: participant.gold_left,
+                    tier: rank.map(|rank| rank.0.to_string()),
+                    division: rank.map(|rank| rank.1.to_string()),
+                    league_points: rank.map(|rank| rank.2),
+                    elo: rank.map(|rank| rank.3),
+                    traits: participant
+                        .traits
+                        .iter()
+                        .filter(|t| t.tier_current > 0)
+                        .map(|t| InteropTrait {
+                            name: t.name.clone(),
+                            tier: t.tier_current,
+                        })
+                        .collect(),
+                    units: participant
+                        .units
+                        .iter()
+                        .map(|u| InteropUnit {
+                            character_id: u.character_id.clone(),
+                            star_level: u.tier,
+                            items: u.items.clone(),
+                        })
+                        .collect(),
+                }
+            })
+            .collect();
+        players.sort_by_key(|player| player.placement);
+        InteropMatch {
+            match_id: game.metadata.match_id.clone(),
+            region: doc.get_str("_region").unwrap_or_default().to_string(),
+            patch: doc.get_str("_patch").ok().map(str::to_string),
+            set: game.info.tft_set_number,
+            game_datetime: game.info.game_datetime,
+            game_length: game.info.game_length,
+            avg_elo: doc.get_i32("_avgElo").ok().filter(|&elo| elo != i32::MIN),
+            avg_rank: doc.get_str("_avgEloText").unwrap_or("UNRANKED").to_string(),
+            players,
+        }
+    }
+
+    /// The document to store, keyed by match id
+    pub fn to_document(&self) -> Result<Document, String> {
+        let mut doc = mongodb::bson::to_document(self).map_err(|e| e.to_string())?;
+        doc.insert("_id", &self.match_id);
+        Ok(doc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::bson::{doc, from_document};
+
+    fn game() -> Match {
+        let participant = |puuid: &str, placement: i32| {
+            serde_json::json!({
+                "companion": {"skin_ID": 1, "content_ID": "c", "species": "s"},
+                "gold_left": 3,
+                "last_round": 30,
+                "level": 8,
+                "placement": placement,
+                "players_eliminated": 2,
+                "puuid": puuid,
+                "time_eliminated": 1800.0,
+                "total_damage_to_players": 100,
+                "traits": [
+                    {"name": "Set4_Cultist", "num_units": 6, "tier_current": 2},
+                    {"name": "Set4_Mystic", "num_units": 1, "tier_current": 0},
+                ],
+                "units": [{
+                    "items": [16, 44],
+                    "character_id": "TFT4_Aatrox",
+                    "name": "",
+                    "rarity": 4,
+                    "tier": 2,
+                }],
+            })
+        };
+        serde_json::from_value(serde_json::json!({
+            "metadata": {"data_version": "5", "match_id": "EUW1_1", "participants": ["a", "b"]},
+            "info": {
+                "game_datetime": 1600000000000i64,
+                "game_length": 2000.5,
+                "game_version": "Version 11.1",
+                "participants": [participant("a", 2), participant("b", 1)],
+                "queue_id": 1100,
+                "tft_set_number": 4,
+            },
+        }))
+        .unwrap()
+    }
+
+    fn stored() -> Document {
+        doc! {
+            "_id": "EUW1_1",
+            "_region": "EUW1",
+            "_patch": "11.1",
+            "_avgElo": 2250,
+            "_avgEloText": "DIAMOND II 50LP",
+            "_aggregatedPlayerInfo": [
+                {"puuid": "a", "tftTier": "DIAMOND", "tftRank": "II", "tftLeaguePoints": 50},
+                {"puuid": "b", "tftTier": "unranked", "tftRank": "unranked", "tftLeaguePoints": i32::MIN},
+            ],
+        }
+    }
+
+    #[test]
+    fn test_interop_mapping() {
+        let interop = InteropMatch::new(&game(), &stored());
+        assert_eq!(interop.region, "EUW1");
+        assert_eq!(interop.patch.as_deref(), Some("11.1"));
+        assert_eq!(interop.avg_elo, Some(2250));
+        // Finishing order
+        let placements: Vec<(&str, i32)> = interop
+            .players
+            .iter()
+            .map(|p| (p.puuid.as_str(), p.placement))
+            .collect();
+        assert_eq!(placements, vec![("b", 1), ("a", 2)]);
+        assert_eq!(interop.players[1].elo, Some(2250));
+        assert_eq!(interop.players[1].division.as_deref(), Some("II"));
+        assert_eq!(interop.players[0].elo, None);
+        assert_eq!(interop.players[0].tier, None);
+        assert_eq!(interop.players[1].traits.len(), 1);
+        assert_eq!(interop.players[1].units[0].star_level, 2);
+
+        let mut unranked = stored();
+        unranked.insert("_avgElo", i32::MIN);
+        assert_eq!(InteropMatch::new(&game(), &unranked).avg_elo, None);
+    }
+
+    #[test]
+    fn test_interop_round_trip() {
+        let interop = InteropMatch::new(&game(), &stored());
+        let doc = interop.to_document().unwrap();
+        assert_eq!(doc.get_str("_id"), Ok("EUW1_1"));
+        assert_eq!(doc.get_i32("avgElo"), Ok(2250));
+        assert_eq!(doc.get_str("region"), Ok("EUW1"));
+        assert_eq!(doc.get_str("patch"), Ok("11.1"));
+        assert_eq!(from_document::<InteropMatch>(doc).unwrap(), interop);
+    }
+}
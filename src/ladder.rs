@@ -0,0 +1,54 @@
+use mongodb::bson::{doc, Bson, Document};
+
+/// Number of rankings sent per `update` command
+pub const UPSERT_BATCH_SIZE: usize = 500;
+
+/// Build `update` commands that upsert the given league cache documents (keyed by
+/// `_id`), at most `batch_size` per command
+pub fn upsert_commands(
+    collection_name: &str,
+    docs: &[Document],
+    batch_size: usize,
+) -> Vec<Document> {
+    docs.chunks(batch_size.max(1))
+        .map(|batch| {
+            let updates: Vec<Bson> = batch
+                .iter()
+                .map(|doc| {
+                    let id = doc.get("_id").cloned().unwrap_or(Bson::Null);
+                    Bson::Document(doc! {"q": {"_id": id}, "u": doc.clone(), "upsert": true})
+                })
+                .collect();
+            doc! {"update": collection_name, "updates": updates, "ordered": false}
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_upsert_commands() {
+        let docs: Vec<Document> = (0..1201)
+            .map(|i| doc! {"_id": format!("summoner-{}", i), "leaguePoints": i})
+            .collect();
+        let commands = upsert_commands("league", &docs, UPSERT_BATCH_SIZE);
+        let sizes: Vec<usize> = commands
+            .iter()
+            .map(|c| c.get_array("updates").unwrap().len())
+            .collect();
+        assert_eq!(sizes, vec![500, 500, 201]);
+
+        let first = commands[0].get_array("updates").unwrap()[0]
+            .as_document()
+            .unwrap();
+        assert_eq!(
+            first.get_document("q").unwrap(),
+            &doc! {"_id": "summoner-0"}
+        );
+        assert!(first.get_bool("upsert").unwrap());
+        assert_eq!(commands[2].get_str("update").unwrap(), "league");
+        assert!(upsert_commands("league", &[], UPSERT_BATCH_SIZE).is_empty());
+    }
+}
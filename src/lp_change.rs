@@ -0,0 +1,127 @@
+use crate::numeric_league_util::try_league_to_numeric;
+use mongodb::bson::{doc, Document};
+
+/// Typical LP a ranked game moves a player by, either way. Only used to estimate games
+/// when the ladder's own game count can't be compared.
+pub const LP_PER_GAME: i32 = 35;
+
+/// A player's place on one ladder snapshot
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Standing {
+    /// `league_to_numeric` of the tier, division and LP
+    pub elo: i32,
+    /// Ranked games played this set, wins plus losses
+    pub games: i32,
+}
+
+impl Standing {
+    /// From a league cache document, None for an unranked player
+    pub fn from_league_doc(doc: &Document) -> Option<Standing> {
+        let tier = doc.get_str("tier").ok()?;
+        let rank = doc.get_str("rank").unwrap_or("I");
+        let league_points = doc.get_i32("leaguePoints").ok()?;
+        Some(Standing {
+            elo: try_league_to_numeric(tier, rank, league_points)?,
+            games: doc.get_i32("wins").unwrap_or(0) + doc.get_i32("losses").unwrap_or(0),
+        })
+    }
+}
+
+/// Games played between two snapshots: the change in the ladder's game count, or when
+/// that went backwards (e.g. a new set reset it) an estimate from the LP moved. Being
+/// on the numeric scale, promotions and demotions across divisions and tiers count only
+/// the LP actually gained or lost.
+pub fn games_between(previous: Standing, current: Standing) -> i32 {
+    let games = current.games - previous.games;
+    if games >= 0 {
+        return games;
+    }
+    let elo_delta = (current.elo - previous.elo).abs();
+    (elo_delta + LP_PER_GAME - 1) / LP_PER_GAME
+}
+
+/// The change document for a player between two snapshots, None when they played no
+/// games in between. Timestamps are added by the caller.
+pub fn lp_change(
+    region: &str,
+    summoner_id: &str,
+    previous: Standing,
+    current: Standing,
+) -> Option<Document> {
+    let games = games_between(previous, current);
+    if games == 0 {
+        return None;
+    }
+    let elo_delta = current.elo - previous.elo;
+    Some(doc! {
+        "summonerId": summoner_id,
+        "region": region,
+        "previousElo": previous.elo,
+        "elo": current.elo,
+        "eloDelta": elo_delta,
+        "gamesPlayed": games,
+        "eloPerGame": elo_delta as f64 / games as f64,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn standing(tier: &str, rank: &str, lp: i32, wins: i32, losses: i32) -> Standing {
+        Standing::from_league_doc(&doc! {
+            "tier": tier, "rank": rank, "leaguePoints": lp, "wins": wins, "losses": losses,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_standing() {
+        assert_eq!(
+            standing("DIAMOND", "II", 50, 10, 40),
+            Standing {
+                elo: 2250,
+                games: 50
+            }
+        );
+        assert_eq!(standing("MASTER", "I", 120, 0, 0).elo, 2520);
+        assert_eq!(
+            Standing::from_league_doc(&doc! {"_status": "unranked"}),
+            None
+        );
+        assert_eq!(
+            Standing::from_league_doc(&doc! {"tier": "EMERALD", "rank": "I", "leaguePoints": 0}),
+            None
+        );
+    }
+
+    #[test]
+    fn test_promotion() {
+        // Diamond I 90LP to Master 30LP over two games is 40 elo, not -60LP
+        let previous = standing("DIAMOND", "I", 90, 10, 40);
+        let current = standing("MASTER", "I", 30, 11, 41);
+        let change = lp_change("EUW1", "s", previous, current).unwrap();
+        assert_eq!(change.get_i32("eloDelta"), Ok(40));
+        assert_eq!(change.get_i32("gamesPlayed"), Ok(2));
+        assert_eq!(change.get_f64("eloPerGame"), Ok(20.0));
+
+        // Demoted from Diamond IV to Platinum I
+        let previous = standing("DIAMOND", "IV", 10, 10, 40);
+        let current = standing("PLATINUM", "I", 75, 10, 41);
+        let change = lp_change("EUW1", "s", previous, current).unwrap();
+        assert_eq!(change.get_i32("eloDelta"), Ok(-35));
+    }
+
+    #[test]
+    fn test_games_between() {
+        let previous = standing("GOLD", "I", 20, 30, 70);
+        // No games, nothing to store even if LP moved (e.g. decay)
+        assert_eq!(
+            lp_change("EUW1", "s", previous, standing("GOLD", "I", 0, 30, 70)),
+            None
+        );
+        // Game count reset, estimated from the 100LP moved
+        let reset = standing("GOLD", "II", 20, 0, 0);
+        assert_eq!(games_between(previous, reset), 3);
+    }
+}
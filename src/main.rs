@@ -1,29 +1,168 @@
+mod api_keys;
+mod audit;
+mod bootstrap;
+mod cache;
+mod cluster_queue;
+mod collections;
+mod commands;
+mod config;
+mod cursor;
+mod cycle_history;
+mod daily_rollup;
+mod db_policy;
+mod endpoints;
+mod error;
+mod expiry;
+mod failure_window;
+mod fair_share;
+mod flat;
+mod grace;
+mod hooks;
+mod ids;
+mod interning;
+mod interop;
+mod ladder;
+mod lp_change;
+mod match_age;
+mod match_cap;
+mod match_doc;
+mod meta_stats;
+mod metrics;
 mod numeric_league_util;
+mod pagination;
+mod participant_info;
+mod patch;
+mod pause;
+mod platform_status;
+mod rank_distribution;
+mod rate_limit;
+mod redact;
+mod region_pacing;
+mod region_util;
+mod rerank;
+mod retry_budget;
+mod scan;
+mod seed;
+mod set_check;
+mod task_exit;
+#[cfg(feature = "otel")]
+mod telemetry;
+mod ttl_check;
+mod typed_match;
 
 use chrono::offset::TimeZone;
 use chrono::offset::Utc;
-use chrono::Duration;
+use chrono::{DateTime, Duration};
+use cluster_queue::ClusterQueue;
 use futures::stream::FuturesUnordered;
 use futures::stream::StreamExt;
-use log::{debug, error, info, trace};
+use log::{debug, error, info, trace, warn};
 use mongodb::bson::document::Document;
 use mongodb::bson::{doc, Bson};
-use mongodb::options::{ClientOptions, CountOptions, FindOneOptions};
+use mongodb::options::{ClientOptions, CountOptions, FindOneOptions, FindOptions, ReplaceOptions};
 use mongodb::Client;
 use riven::consts::Region;
-use riven::models::tft_league_v1::LeagueList;
-use riven::{RiotApi, RiotApiConfig};
-use std::collections::VecDeque;
+use riven::models::tft_league_v1::{LeagueEntry, LeagueList};
+use riven::models::tft_match_v1::Match;
+use riven::models::tft_summoner_v1::Summoner;
+use riven::{ResponseInfo, RiotApi, RiotApiConfig};
+use std::collections::HashMap;
 use std::convert::TryInto;
 use std::iter::Iterator;
-use std::sync::Arc;
-use tokio::time::sleep;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::sync::{Semaphore, SemaphorePermit};
+use tokio::time::{sleep, timeout_at};
 
-use numeric_league_util::{league_to_numeric, team_avg_rank_str};
+use audit::Decision;
+use bootstrap::Bootstrap;
+use collections::collection_name;
+use config::Config;
+use endpoints::{Endpoint, EndpointLimits};
+use error::CrawlerError;
+use expiry::{match_expiry, retention_expiry};
+use failure_window::FailureWindow;
+use fair_share::{FairShare, FairSharePermit};
+use hooks::{MatchHook, StoredMatch};
+use interning::StringTable;
+use match_age::{NewestMatch, TooOld};
+use match_cap::MatchCap;
+use match_doc::{
+    derived_fields, document_size, lobby_index_command, match_document, match_fields,
+    meta_fingerprint_index_command, raw_match, strip_raw_fields, top_lobbies_index_command,
+    ttl_index_command, unranked_index_command, SCHEMA_VERSION,
+};
+use metrics::{incr, MatchCounts, Metrics, MetricsSnapshot};
+use numeric_league_util::{ApexCutoffs, LiveApexCutoffs};
+use pagination::fetch_all_pages;
+use participant_info::{
+    add_performance, add_pid_hashes, add_rank_observed_at, add_traits, aggregate_participants,
+    collect_indexed, companion_info, elo_spread, lobby_upset_score, meta_fingerprint, placements,
+    Enrichment, EnrichmentLevel, ParticipantInfo,
+};
+use platform_status::{blocking_status, StatusCache};
+use rand::rngs::StdRng;
+use rand::Rng;
+use rate_limit::RateLimiter;
+use retry_budget::RetryBudget;
+use tracing::{info_span, Instrument};
+use typed_match::{TypedMatch, TypedMatchMode};
 
 const MATCHES_COLLECTION_NAME: &str = "matches-4-1";
 const SUMMONERS_COLLECTION_NAME: &str = "summoner-4-1";
 const LEAGUES_COLLECTION_NAME: &str = "league-4-1";
+const META_STATS_COLLECTION_NAME: &str = "meta-stats-4-1";
+const FLAT_PARTICIPATIONS_COLLECTION_NAME: &str = "flat-participations-4-1";
+// Stored matches for outside tooling, see `Config::interop_matches`
+const INTEROP_COLLECTION_NAME: &str = "interop-matches-4-1";
+// Ingestion decisions, see `Config::audit_log_retention`
+const AUDIT_COLLECTION_NAME: &str = "ingest-audit-4-1";
+// Exact match-v1 bodies, see `Config::raw_responses_retention`
+const RAW_RESPONSES_COLLECTION_NAME: &str = "raw-responses-4-1";
+// One entry per completed cycle, see `Config::cycle_history_retention`
+const CYCLE_HISTORY_COLLECTION_NAME: &str = "cycle-history-4-1";
+const RANK_DISTRIBUTIONS_COLLECTION_NAME: &str = "rank-distributions-4-1";
+// Games and elo between ladder snapshots, see `Config::lp_gain_tracking`
+const LP_CHANGES_COLLECTION_NAME: &str = "lp-changes-4-1";
+// Per region, queue and day ingestion counts, see `Config::daily_rollup`
+const DAILY_ROLLUP_COLLECTION_NAME: &str = "daily-rollup-4-1";
+// Interned strings, see `Config::intern_traits`
+const STRING_TABLE_COLLECTION_NAME: &str = "string-table-4-1";
+// When each region task last finished a cycle, see `cursor::match_fetch_depth`
+const CRAWL_STATE_COLLECTION_NAME: &str = "crawl-state-4-1";
+
+// Summoners processed at once by a region task
+const SUMMONER_CONCURRENCY: usize = 10;
+
+// How long a tft-status-v1 result is reused before checking again
+const PLATFORM_STATUS_CACHE_DURATION: std::time::Duration = std::time::Duration::from_secs(60);
+// Delay before the next attempt when a cycle is skipped due to maintenance
+const PLATFORM_UNAVAILABLE_DELAY: std::time::Duration = std::time::Duration::from_secs(120);
+
+// How often to verify expired documents are being removed
+const TTL_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+// How often a paused task checks whether it has been resumed
+const PAUSE_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
+// Waiting longer than this for a write slot is logged and counted
+const SLOW_WRITE_PERMIT: std::time::Duration = std::time::Duration::from_secs(5);
+
+// Result of a match-v1 `get_match` call, see `get_match_response`
+type FetchedMatch = Result<ResponseInfo, riven::RiotApiError>;
+
+// match-v1 `get_match` as riven makes it, with the same rate limit bucket and retries,
+// but returning the response unparsed so its body can be kept, see
+// `Main::read_match_response`
+fn get_match_response<'a>(
+    api: &'a RiotApi,
+    cluster: Region,
+    id: &str,
+) -> impl std::future::Future<Output = FetchedMatch> + 'a {
+    let path = format!("/tft/match/v1/matches/{}", id);
+    api.get_raw_response("tft-match-v1.getMatch", cluster.into(), path, None)
+}
 
 #[derive(Copy, Clone, Debug)]
 enum TftQueue {
@@ -32,355 +171,1807 @@ enum TftQueue {
 }
 
 #[tokio::main]
-async fn main() -> () {
+async fn main() {
     env_logger::init();
 
-    let api = {
-        let api_key = std::env::var("RGAPI_KEY").expect("Missing environment variable: RGAPI_KEY");
-        let api_config = RiotApiConfig::with_key(api_key).preconfig_throughput();
-        Arc::new(RiotApi::with_config(api_config))
-    };
+    let config = Arc::new(Config::from_env());
+    if let Err(e) = config.scan.validate() {
+        eprintln!("Error: SCAN_TIERS: {}", e);
+        std::process::exit(1);
+    }
+    info!(
+        "Key tier {:?}, summoner pacing {:?}",
+        config.key_tier, config.summoner_pacing
+    );
+    let region_pacing: Vec<String> = region_util::SUPPORTED_REGIONS
+        .iter()
+        .map(|region| {
+            let pacing = config.region_pacing.pacing(*region, config.summoner_pacing);
+            format!("{:?} {:?}", region, pacing)
+        })
+        .collect();
+    info!("Summoner pacing by region: {}", region_pacing.join(", "));
+    if let Some(seed) = config.crawl_seed {
+        info!("Crawl seed {}", seed);
+    }
+    if let Some(raw_fields) = &config.raw_fields {
+        for path in raw_fields.unknown_paths() {
+            warn!("RAW_FIELDS path {} is not part of the match schema", path);
+        }
+    }
 
-    let db = {
-        let db_connection_string = std::env::var("DB_CONNECTION_STRING")
-            .expect("Missing environment variable: DB_CONNECTION_STRING");
-        let mut client_options = ClientOptions::parse(&db_connection_string)
-            .await
-            .expect("Unable to parse DB options");
-        client_options.app_name = Some("tft_stat".to_string());
-        let client = Client::with_options(client_options).expect("Unable to construct DB client");
-        Arc::new(client.database("tft"))
-    };
+    #[cfg(feature = "otel")]
+    match telemetry::init() {
+        Ok(true) => info!("Exporting traces over OTLP"),
+        Ok(false) => (),
+        Err(e) => {
+            eprintln!("Error: OTEL_EXPORTER_OTLP_ENDPOINT: {}", e);
+            std::process::exit(1);
+        }
+    }
 
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let ret = run(config, &args).await;
+    #[cfg(feature = "otel")]
+    telemetry::shutdown();
+    if let Err(e) = ret {
+        eprintln!("Error: {:#}", e);
+        std::process::exit(1);
+    }
+}
+
+async fn run(config: Arc<Config>, args: &[String]) -> anyhow::Result<()> {
+    let shared = Shared::connect(config).await?;
+    match args.first().map(String::as_str) {
+        None => crawl(shared).await,
+        Some("reprocess") => commands::reprocess(&shared, &args[1..]).await,
+        Some("selftest") => commands::selftest(&shared, &args[1..]).await,
+        Some("migrate") => commands::migrate(&shared, &args[1..]).await,
+        Some("diff") => commands::diff(&shared, &args[1..]).await,
+        Some(command) => Err(anyhow::anyhow!("Unknown command: {}", command)),
+    }
+}
+
+// Crawl all regions forever
+async fn crawl(shared: Shared) -> anyhow::Result<()> {
+    // Handles of tasks that should never end, with a name for the exit diagnostic
     let mut join_handles = vec![];
+    let mut task_names = vec![];
+
+    #[cfg(unix)]
+    tokio::spawn(pause::toggle_on_sigusr1(shared.paused.clone()));
+    #[cfg(unix)]
+    tokio::spawn(pause::drain_on_sigusr2(shared.draining.clone()));
 
-    for (queue_type, region, region_major) in &[
-        (TftQueue::Ranked, Region::EUW, Region::EUROPE),
-        (TftQueue::Ranked, Region::EUNE, Region::EUROPE),
-        (TftQueue::Ranked, Region::KR, Region::ASIA),
-        (TftQueue::Ranked, Region::JP, Region::ASIA),
-        (TftQueue::Ranked, Region::NA, Region::AMERICAS),
-        (TftQueue::Ranked, Region::BR, Region::AMERICAS),
-        (TftQueue::Ranked, Region::OCE, Region::AMERICAS),
-        (TftQueue::Hyperroll, Region::EUW, Region::EUROPE),
-        (TftQueue::Hyperroll, Region::EUNE, Region::EUROPE),
-        (TftQueue::Hyperroll, Region::KR, Region::ASIA),
-        (TftQueue::Hyperroll, Region::JP, Region::ASIA),
-        (TftQueue::Hyperroll, Region::NA, Region::AMERICAS),
-        (TftQueue::Hyperroll, Region::BR, Region::AMERICAS),
-        (TftQueue::Hyperroll, Region::OCE, Region::AMERICAS),
-    ] {
-        let api_clone = api.clone();
-        let db_clone = db.clone();
+    let mut mains = vec![];
+    for queue_type in &[TftQueue::Ranked, TftQueue::Hyperroll] {
+        for region in region_util::SUPPORTED_REGIONS {
+            let region_major = region_util::major_region(*region)
+                .unwrap_or_else(|| panic!("No major region for {:?}", region));
+            mains.push(Main::new(&shared, *queue_type, *region, region_major));
+        }
+    }
+
+    // The ranked task of each platform
+    let ranked = &mains[..region_util::SUPPORTED_REGIONS.len()];
+    set_check::check(ranked, MATCHES_COLLECTION_NAME, shared.config.set_check).await?;
+
+    let mut collection_names = vec![];
+    for main in &mains {
+        for name in main.collection_names() {
+            if !collection_names.contains(&name) {
+                collection_names.push(name);
+            }
+        }
+    }
+    let db = shared.db.clone();
+    join_handles.push(tokio::spawn(async move {
+        loop {
+            for collection_name in &collection_names {
+                ttl_check::check_ttl(&db, collection_name).await;
+            }
+            sleep(TTL_CHECK_INTERVAL).await;
+        }
+    }));
+    task_names.push("TTL check".to_string());
+
+    for main in mains {
+        task_names.push(format!("{:?} {}", main.queue_type, main.region));
         let hdl = tokio::spawn(async move {
-            Main {
-                queue_type: *queue_type,
-                region: *region,
-                region_major: *region_major,
-                api: api_clone,
-                db: db_clone,
-            }
-            .run()
-            .await;
+            main.run().await;
         });
         join_handles.push(hdl);
     }
-    let (_i, idx, _v) = futures::future::select_all(join_handles).await;
-    panic!("Handle {} returned.", idx);
+    let (result, idx, _remaining) = futures::future::select_all(join_handles).await;
+    let how = task_exit::describe(result);
+    error!("Task {} {}", task_names[idx], how);
+    panic!("Task {} {}", task_names[idx], how);
+}
+
+/// Connections and limits shared by every region task
+#[derive(Clone)]
+struct Shared {
+    // Client and key for each major region, see `api_keys::cluster_keys`
+    apis: Arc<HashMap<Region, (Arc<RiotApi>, String)>>,
+    db: Arc<mongodb::Database>,
+    db_read: Arc<mongodb::Database>,
+    config: Arc<Config>,
+    write_semaphore: Arc<Semaphore>,
+    endpoints: Arc<EndpointLimits>,
+    // Keyed by major region, see `Config::match_cluster_slots`
+    match_shares: Arc<HashMap<Region, FairShare>>,
+    // Keyed by major region, see `Config::match_fetch_workers`
+    match_queues: Arc<HashMap<Region, ClusterQueue<FetchedMatch>>>,
+    // Set while crawling is paused, toggled by SIGUSR1
+    paused: Arc<AtomicBool>,
+    // Set once crawling should wind down, by SIGUSR2
+    draining: Arc<AtomicBool>,
+    // Keyed by platform, see `Config::live_apex_cutoffs`
+    apex_cutoffs: Arc<Mutex<HashMap<Region, LiveApexCutoffs>>>,
+    // See `Config::intern_traits`
+    string_table: Arc<StringTable>,
+    // Appended to every collection name, to keep `selftest` off the real collections
+    collection_suffix: String,
+}
+
+impl Shared {
+    // `name` with the collection suffix, see `Shared::collection_suffix`
+    fn suffixed(&self, name: &str) -> String {
+        format!("{}{}", name, self.collection_suffix)
+    }
+
+    async fn connect(config: Arc<Config>) -> anyhow::Result<Shared> {
+        // Clusters sharing a key share a client, and so its rate limits
+        let mut apis = HashMap::new();
+        let mut clients: HashMap<String, Arc<RiotApi>> = HashMap::new();
+        for (cluster, api_key) in
+            api_keys::cluster_keys(|name| std::env::var(name).ok()).map_err(anyhow::Error::msg)?
+        {
+            let api = clients
+                .entry(api_key.clone())
+                .or_insert_with(|| {
+                    let api_config =
+                        RiotApiConfig::with_key(api_key.clone()).preconfig_throughput();
+                    Arc::new(RiotApi::with_config(api_config))
+                })
+                .clone();
+            apis.insert(cluster, (api, api_key));
+        }
+        if clients.len() > 1 {
+            info!("Using {} API keys across clusters", clients.len());
+        }
+
+        let db = {
+            let db_connection_string = std::env::var("DB_CONNECTION_STRING")
+                .expect("Missing environment variable: DB_CONNECTION_STRING");
+            connect_db(&db_connection_string).await?
+        };
+        // Optional read replica for the cache lookups, falls back to the primary
+        let db_read = match std::env::var("DB_READ_CONNECTION_STRING") {
+            Ok(db_read_connection_string) => connect_db(&db_read_connection_string).await?,
+            Err(_) => db.clone(),
+        };
+
+        let write_semaphore = Arc::new(Semaphore::new(config.max_concurrent_writes));
+        let rate_limiter = if config.rate_limit_preflight {
+            Some(RateLimiter::new(
+                config.method_rate_limits,
+                config.app_rate_limit,
+                config.rate_limit_headroom,
+            ))
+        } else {
+            None
+        };
+        let endpoints = Arc::new(EndpointLimits::new(
+            [
+                config.max_concurrent_summoner_calls,
+                config.max_concurrent_match_list_calls,
+                config.max_concurrent_match_calls,
+                config.max_concurrent_league_calls,
+            ],
+            rate_limiter,
+        ));
+        let mut match_shares = HashMap::new();
+        if let Some(slots) = config.match_cluster_slots {
+            for region in region_util::SUPPORTED_REGIONS {
+                if let Some(major) = region_util::major_region(*region) {
+                    match_shares
+                        .entry(major)
+                        .or_insert_with(|| FairShare::new(slots, config.cluster_weights.clone()));
+                }
+            }
+        }
+        let mut match_queues = HashMap::new();
+        if let Some(workers) = config.match_fetch_workers {
+            for (cluster, (api, _)) in &apis {
+                let (cluster, api, endpoints) = (*cluster, api.clone(), endpoints.clone());
+                let queue = ClusterQueue::spawn(workers, move |match_id: String| {
+                    let (api, endpoints) = (api.clone(), endpoints.clone());
+                    async move {
+                        let _permit = endpoints.acquire(Endpoint::Match, cluster).await;
+                        get_match_response(&api, cluster, &match_id).await
+                    }
+                });
+                match_queues.insert(cluster, queue);
+            }
+        }
+        Ok(Shared {
+            apis: Arc::new(apis),
+            db,
+            db_read,
+            config,
+            write_semaphore,
+            endpoints,
+            match_shares: Arc::new(match_shares),
+            match_queues: Arc::new(match_queues),
+            paused: Arc::new(AtomicBool::new(false)),
+            draining: Arc::new(AtomicBool::new(false)),
+            apex_cutoffs: Arc::new(Mutex::new(HashMap::new())),
+            string_table: Arc::new(StringTable::new(STRING_TABLE_COLLECTION_NAME)),
+            collection_suffix: String::new(),
+        })
+    }
+}
+
+async fn connect_db(connection_string: &str) -> anyhow::Result<Arc<mongodb::Database>> {
+    // Errors may quote the connection string, credentials and all
+    let redacted =
+        |e: mongodb::error::Error| redact::redact_message(&e.to_string(), connection_string);
+    let mut client_options = ClientOptions::parse(connection_string).await.map_err(|e| {
+        anyhow::anyhow!(
+            "Unable to parse DB options of {}: {}",
+            redact::redact_uri(connection_string),
+            redacted(e)
+        )
+    })?;
+    client_options.app_name = Some("tft_stat".to_string());
+    let client = Client::with_options(client_options)
+        .map_err(|e| anyhow::anyhow!("Unable to construct DB client: {}", redacted(e)))?;
+    let db = client.database("tft");
+    db_policy::wait_until_reachable(&db).await?;
+    Ok(Arc::new(db))
 }
 
 #[derive(Clone)]
 struct Main {
     api: Arc<RiotApi>,
+    // Key of `api`, for the endpoints called without riven
+    api_key: String,
     queue_type: TftQueue,
     region: Region,
     region_major: Region,
     db: Arc<mongodb::Database>,
+    // Used for read-only queries. Same as `db` unless a read replica is configured.
+    // Replica lag can let a just-stored document look missing, so an insert may
+    // occasionally collide with an existing _id.
+    db_read: Arc<mongodb::Database>,
+    metrics: Arc<Metrics>,
+    config: Arc<Config>,
+    // Shared by all region tasks, see `Config::max_concurrent_writes`
+    write_semaphore: Arc<Semaphore>,
+    // Shared by all region tasks, see `Config::max_concurrent_match_calls`
+    endpoints: Arc<EndpointLimits>,
+    // Shared by all region tasks, see `Config::match_cluster_slots`
+    match_shares: Arc<HashMap<Region, FairShare>>,
+    // Shared by all region tasks, see `Config::match_fetch_workers`
+    match_queues: Arc<HashMap<Region, ClusterQueue<FetchedMatch>>>,
+    platform_status: Arc<StatusCache>,
+    // Collection names, see `Config::per_region_collections`
+    matches_collection: String,
+    summoners_collection: String,
+    leagues_collection: String,
+    flat_participations_collection: String,
+    // Source of all randomness in this task, see `Config::crawl_seed`
+    rng: Arc<Mutex<StdRng>>,
+    // Matches not found yet, see `Config::not_found_grace`
+    not_found: Arc<Mutex<grace::NotFoundGrace>>,
+    // See `Config::bootstrap_slowdown`
+    bootstrap: Arc<Mutex<Bootstrap>>,
+    // Delay between launching each summoner, see `Config::region_pacing`
+    summoner_pacing: std::time::Duration,
+    newest_match: Arc<Mutex<NewestMatch>>,
+    // See `Config::max_match_age`
+    too_old: Arc<Mutex<TooOld>>,
+    // See `Config::failure_ratio_threshold`
+    api_outcomes: Arc<Mutex<FailureWindow>>,
+    paused: Arc<AtomicBool>,
+    draining: Arc<AtomicBool>,
+    // Shared by all tasks, see `Config::live_apex_cutoffs`
+    apex_cutoffs: Arc<Mutex<HashMap<Region, LiveApexCutoffs>>>,
+    // Shared by all tasks, see `Config::intern_traits`
+    string_table: Arc<StringTable>,
+    retry_budget: Arc<RetryBudget>,
+    // See `Config::max_summoner_calls_per_cycle`
+    summoner_call_budget: Arc<RetryBudget>,
+    match_cap: Arc<MatchCap>,
+    // Run after each match is stored, see `hooks::configured`
+    hooks: Vec<Arc<dyn MatchHook>>,
+    // See `Shared::collection_suffix`
+    collection_suffix: String,
 }
 
 impl Main {
+    fn new(shared: &Shared, queue_type: TftQueue, region: Region, region_major: Region) -> Main {
+        let (api, api_key) = shared
+            .apis
+            .get(&region_major)
+            .unwrap_or_else(|| panic!("{:?} is not a major region", region_major));
+        Main {
+            queue_type,
+            region,
+            region_major,
+            api: api.clone(),
+            api_key: api_key.clone(),
+            db: shared.db.clone(),
+            db_read: shared.db_read.clone(),
+            metrics: Arc::new(Metrics::default()),
+            config: shared.config.clone(),
+            write_semaphore: shared.write_semaphore.clone(),
+            endpoints: shared.endpoints.clone(),
+            match_shares: shared.match_shares.clone(),
+            match_queues: shared.match_queues.clone(),
+            platform_status: Arc::new(Mutex::new(None)),
+            matches_collection: shared.suffixed(&collection_name(
+                MATCHES_COLLECTION_NAME,
+                region,
+                shared.config.per_region_collections,
+            )),
+            summoners_collection: shared.suffixed(&collection_name(
+                SUMMONERS_COLLECTION_NAME,
+                region,
+                shared.config.per_region_collections,
+            )),
+            leagues_collection: shared.suffixed(&collection_name(
+                LEAGUES_COLLECTION_NAME,
+                region,
+                shared.config.per_region_collections,
+            )),
+            flat_participations_collection: shared.suffixed(&collection_name(
+                FLAT_PARTICIPATIONS_COLLECTION_NAME,
+                region,
+                shared.config.per_region_collections,
+            )),
+            rng: Arc::new(Mutex::new(seed::task_rng(
+                shared.config.crawl_seed,
+                &format!("{:?} {}", queue_type, region),
+            ))),
+            not_found: Arc::new(Mutex::new(grace::NotFoundGrace::default())),
+            bootstrap: Arc::new(Mutex::new(Bootstrap::default())),
+            summoner_pacing: shared
+                .config
+                .region_pacing
+                .pacing(region, shared.config.summoner_pacing),
+            newest_match: Arc::new(Mutex::new(NewestMatch::default())),
+            too_old: Arc::new(Mutex::new(TooOld::default())),
+            api_outcomes: Arc::new(Mutex::new(FailureWindow::new(
+                shared.config.failure_ratio_window,
+            ))),
+            paused: shared.paused.clone(),
+            draining: shared.draining.clone(),
+            apex_cutoffs: shared.apex_cutoffs.clone(),
+            string_table: shared.string_table.clone(),
+            retry_budget: Arc::new(RetryBudget::default()),
+            summoner_call_budget: Arc::new(RetryBudget::default()),
+            match_cap: Arc::new(MatchCap::default()),
+            hooks: hooks::configured(&shared.config),
+            collection_suffix: shared.collection_suffix.clone(),
+        }
+    }
+
+    // `name` with the collection suffix, see `Shared::collection_suffix`
+    fn suffixed(&self, name: &str) -> String {
+        format!("{}{}", name, self.collection_suffix)
+    }
+
+    // Collections for the TTL check. Monthly match partitions are left out, they are
+    // retained by dropping old months.
+    fn collection_names(&self) -> Vec<String> {
+        let mut names = vec![
+            self.summoners_collection.clone(),
+            self.leagues_collection.clone(),
+        ];
+        if !self.config.partition_matches_monthly {
+            names.insert(0, self.matches_collection.clone());
+        }
+        if self.config.flat_participations {
+            names.push(self.flat_participations_collection.clone());
+        }
+        if self.config.audit_log_retention.is_some() {
+            names.push(self.suffixed(AUDIT_COLLECTION_NAME));
+        }
+        if self.config.cycle_history_retention.is_some() {
+            names.push(self.suffixed(CYCLE_HISTORY_COLLECTION_NAME));
+        }
+        if self.config.raw_responses_retention.is_some() {
+            names.push(self.suffixed(RAW_RESPONSES_COLLECTION_NAME));
+        }
+        if self.config.lp_gain_tracking {
+            names.push(self.suffixed(LP_CHANGES_COLLECTION_NAME));
+        }
+        names
+    }
+
     // run forever
     async fn run(&self) {
+        self.start_bootstrap().await;
         loop {
+            if self.draining.load(Ordering::Relaxed) {
+                info!("[{:?} {}] Drained, idling.", self.queue_type, self.region);
+                return std::future::pending().await;
+            }
+            let cycle_start = Instant::now();
             self.do_cycle().await;
+            let elapsed = cycle_start.elapsed();
+            let remaining = self.min_cycle_interval().checked_sub(elapsed);
+            if let Some(remaining) = remaining.filter(|remaining| !remaining.is_zero()) {
+                info!(
+                    "[{:?} {}] Cycle took {:?}, waiting {:?} for the minimum cycle interval.",
+                    self.queue_type, self.region, elapsed, remaining
+                );
+                sleep(remaining).await;
+            }
         }
     }
 
     async fn do_cycle(&self) {
+        self.wait_while_paused().await;
         info!("[{:?} {}] Main begin.", self.queue_type, self.region);
-        let summoner_list = self.get_top_players().await;
+        if let Some(reason) = self.get_platform_status().await {
+            warn!(
+                "[{:?} {}] Skipping cycle, platform unavailable: {}",
+                self.queue_type, self.region, reason
+            );
+            sleep(PLATFORM_UNAVAILABLE_DELAY).await;
+            return;
+        }
+        if self.failing_too_often() {
+            sleep(self.config.failure_ratio_cooldown).await;
+            return;
+        }
+        self.ensure_match_indexes().await;
+        let metrics_start = self.metrics.snapshot();
+        let cycle_start = Instant::now();
+        self.retry_budget.reset(self.config.cycle_retry_budget);
+        self.summoner_call_budget
+            .reset(self.config.max_summoner_calls_per_cycle.unwrap_or(u32::MAX));
+        self.match_cap
+            .reset(self.config.max_new_matches_per_cycle.unwrap_or(u32::MAX));
+        let depth = cursor::match_fetch_depth(
+            self.read_cursor().await,
+            Utc::now(),
+            Duration::from_std(self.config.catch_up_gap).unwrap_or_else(|_| Duration::max_value()),
+            self.config.catch_up_match_count,
+        );
+        if depth != cursor::STEADY_MATCH_COUNT {
+            info!(
+                "[{:?} {}] Catching up after a gap, fetching {} matches per player.",
+                self.queue_type, self.region, depth
+            );
+        }
+        // Far enough out to never fire when unset
+        let deadline = tokio::time::Instant::from_std(cycle_start)
+            + self
+                .config
+                .max_cycle_duration
+                .unwrap_or(std::time::Duration::from_secs(30 * 365 * 24 * 60 * 60));
+        let summoner_list = match timeout_at(deadline, self.get_top_players()).await {
+            Ok(summoner_list) => summoner_list,
+            Err(_) => {
+                warn!(
+                    "[{:?} {}] Cycle timed out gathering players, starting over.",
+                    self.queue_type, self.region
+                );
+                return;
+            }
+        };
         info!(
             "[{:?} {}] Gathered summoner ids for {} players.",
             self.queue_type,
             self.region,
             summoner_list.len()
         );
+        if summoner_list.is_empty() {
+            // Nothing to crawl, e.g. every ladder call failed or the scanned tiers are
+            // empty. Not a completed cycle, so the cursor stays put.
+            warn!(
+                "[{:?} {}] No players found, backing off for {:?}.",
+                self.queue_type, self.region, self.config.empty_player_list_backoff
+            );
+            sleep(self.config.empty_player_list_backoff).await;
+            return;
+        }
 
-        let mut q: VecDeque<(usize, &String)> = summoner_list.iter().enumerate().collect();
+        let mut q = scan::cycle_queue(&summoner_list);
+        let (concurrency, pacing) = match self.config.bootstrap_slowdown {
+            Some(slowdown) if self.bootstrap.lock().unwrap().active() => {
+                bootstrap::throttled(SUMMONER_CONCURRENCY, self.summoner_pacing, slowdown)
+            }
+            _ => (SUMMONER_CONCURRENCY, self.summoner_pacing),
+        };
 
         let mut futures = FuturesUnordered::new();
-        loop {
-            if q.is_empty() && futures.is_empty() {
-                break;
-            }
-            while !q.is_empty() && futures.len() < 10 {
-                futures.push(
-                    q.pop_front()
-                        .map(|(index, id)| self.process_summoner_id(index, id))
-                        .unwrap(),
-                );
-                sleep(tokio::time::Duration::from_millis(2000)).await;
-            }
+        let crawl = async {
+            loop {
+                if q.is_empty() && futures.is_empty() {
+                    break;
+                }
+                if self.match_cap.reached() {
+                    // Capped, the remaining players wait for the next cycle
+                    q.clear();
+                }
+                if self.draining.load(Ordering::Relaxed) && !q.is_empty() {
+                    info!(
+                        "[{:?} {}] Draining, not starting the remaining {} summoners.",
+                        self.queue_type,
+                        self.region,
+                        q.len()
+                    );
+                    q.clear();
+                }
+                while !q.is_empty() && futures.len() < concurrency {
+                    self.wait_while_paused().await;
+                    futures.push(
+                        q.pop_front()
+                            .map(|(index, id)| {
+                                // The root span of the summoner's trace, see `telemetry`
+                                self.process_summoner_id(index, id, depth)
+                                    .instrument(info_span!(
+                                        "process_summoner_id",
+                                        region = %self.region,
+                                        summoner_id = %id
+                                    ))
+                            })
+                            .unwrap(),
+                    );
+                    sleep(pacing).await;
+                }
 
-            match futures.next().await {
-                Some(_ret) => (),
-                None => break,
+                match futures.next().await {
+                    Some(_ret) => (),
+                    None => break,
+                }
             }
+        };
+        if timeout_at(deadline, crawl).await.is_err() {
+            // Dropping the futures cancels the summoners still in flight
+            warn!(
+                "[{:?} {}] Cycle timed out with {} of {} summoners incomplete, starting over.",
+                self.queue_type,
+                self.region,
+                futures.len() + q.len(),
+                summoner_list.len()
+            );
+            return;
         }
 
+        if self.draining.load(Ordering::Relaxed) {
+            // Not a completed cycle, so the cursor stays put
+            return;
+        }
         info!("[{}] Main Done.", self.region);
-        let delay = match self.queue_type {
+        self.write_cursor(Utc::now()).await;
+        // From the ranked task only, as both queues' tasks store the same region's matches
+        let rerank_limit = match self.queue_type {
+            TftQueue::Ranked => self.config.rerank_unranked_per_cycle,
+            TftQueue::Hyperroll => None,
+        };
+        if let Some(limit) = rerank_limit {
+            if let Err(e) = self.rerank_unranked(limit).await {
+                error!("[{}] Error reranking stored matches: {}", self.region, e);
+            }
+        }
+        let cycle_metrics = self.metrics.snapshot() - metrics_start;
+        self.end_bootstrap_cycle(&cycle_metrics);
+        info!(
+            "[{:?} {}] Cycle: {}; {}; in flight: {}",
+            self.queue_type,
+            self.region,
+            cycle_metrics,
+            metrics::rate_str(
+                cycle_metrics.match_calls,
+                cycle_start.elapsed(),
+                "match calls"
+            ),
+            self.endpoints.in_flight_str()
+        );
+        // Summoner and league calls route to the platform, match calls to the cluster
+        for route in &[self.region, self.region_major] {
+            if let Some(rate_limits) = self.endpoints.rate_limit_str(*route) {
+                info!(
+                    "[{:?} {}] Rate limit buckets {}: {}",
+                    self.queue_type, self.region, route, rate_limits
+                );
+            }
+        }
+        self.record_cycle(cycle_start.elapsed(), &cycle_metrics)
+            .await;
+        self.record_daily_errors(&cycle_metrics).await;
+    }
+
+    // See `Config::min_cycle_interval`
+    fn min_cycle_interval(&self) -> std::time::Duration {
+        let default = match self.queue_type {
             TftQueue::Ranked => 300,    // 5 minutes
             TftQueue::Hyperroll => 600, // 10 minutes
         };
-        sleep(tokio::time::Duration::from_secs(delay)).await;
+        self.config
+            .min_cycle_interval
+            .unwrap_or_else(|| std::time::Duration::from_secs(default))
+    }
+
+    // Enter bootstrap mode if enabled and the caches are empty, see
+    // `Config::bootstrap_slowdown`. Errors are logged and leave it off.
+    async fn start_bootstrap(&self) {
+        let slowdown = match self.config.bootstrap_slowdown {
+            Some(slowdown) => slowdown,
+            None => return,
+        };
+        let mut cached = 0;
+        for name in &[&self.summoners_collection, &self.leagues_collection] {
+            let count = self
+                .db_read
+                .collection::<Document>(name)
+                .estimated_document_count(None)
+                .await;
+            match count {
+                Ok(count) => cached += count,
+                Err(e) => return error!("[{}] Error counting {}: {}", self.region, name, e),
+            }
+        }
+        if cached == 0 {
+            info!(
+                "[{:?} {}] Empty caches, bootstrapping {}x slower until a cycle's hit rate reaches {:.0}% or {} cycles.",
+                self.queue_type,
+                self.region,
+                slowdown,
+                100.0 * self.config.bootstrap_min_hit_rate,
+                self.config.bootstrap_max_cycles
+            );
+            *self.bootstrap.lock().unwrap() = Bootstrap::start();
+        }
+    }
+
+    // Count a completed cycle towards leaving bootstrap mode
+    fn end_bootstrap_cycle(&self, cycle_metrics: &MetricsSnapshot) {
+        let hits = cycle_metrics.summoner_cache_hits + cycle_metrics.league_cache_hits;
+        let misses = cycle_metrics.summoner_cache_misses + cycle_metrics.league_cache_misses;
+        let ended = self.bootstrap.lock().unwrap().end_cycle(
+            hits,
+            misses,
+            self.config.bootstrap_min_hit_rate,
+            self.config.bootstrap_max_cycles,
+        );
+        if ended {
+            info!(
+                "[{:?} {}] Bootstrap done at a {} cache hit rate, running at steady-state pacing.",
+                self.queue_type,
+                self.region,
+                metrics::hit_rate_str(hits, misses)
+            );
+        }
+    }
+
+    fn cursor_id(&self) -> String {
+        format!(
+            "{:?}-{}",
+            self.queue_type,
+            region_util::region_key(self.region).expect("unsupported region")
+        )
+    }
+
+    // When this task last finished a cycle. Errors are logged and treated as unknown.
+    async fn read_cursor(&self) -> Option<DateTime<Utc>> {
+        let state = self
+            .db_read
+            .collection::<Document>(&self.suffixed(CRAWL_STATE_COLLECTION_NAME))
+            .find_one(doc! {"_id": self.cursor_id()}, None)
+            .await;
+        match state {
+            Ok(state) => state.and_then(|state| state.get_datetime("lastCycle").ok().copied()),
+            Err(e) => {
+                error!("[{}] Error reading crawl cursor: {}", self.region, e);
+                None
+            }
+        }
+    }
+
+    async fn write_cursor(&self, last_cycle: DateTime<Utc>) {
+        let _permit = self.write_permit().await;
+        let ret = self
+            .db
+            .collection::<Document>(&self.suffixed(CRAWL_STATE_COLLECTION_NAME))
+            .replace_one(
+                doc! {"_id": self.cursor_id()},
+                doc! {"_id": self.cursor_id(), "lastCycle": last_cycle},
+                ReplaceOptions::builder().upsert(true).build(),
+            )
+            .await;
+        if let Err(e) = ret {
+            error!("[{}] Error writing crawl cursor: {}", self.region, e);
+        }
+    }
+
+    // Idle until crawling is resumed. Summoners already in flight are left to finish.
+    async fn wait_while_paused(&self) {
+        if !self.paused.load(Ordering::Relaxed) {
+            return;
+        }
+        info!("[{:?} {}] Paused.", self.queue_type, self.region);
+        while self.paused.load(Ordering::Relaxed) {
+            sleep(PAUSE_POLL_INTERVAL).await;
+        }
+        info!("[{:?} {}] Resumed.", self.queue_type, self.region);
     }
 
     /// Do all processing for a single summoner
     /// Propagates up errors from database and api calls (but not match fetching errors)
-    async fn process_summoner_id(&self, index: usize, id: &str) {
-        let player = self
-            .api
-            .tft_summoner_v1()
-            .get_by_summoner_id(self.region, id)
-            .await;
+    async fn process_summoner_id(&self, index: usize, id: &str, depth: i32) {
+        if self.config.validate_ids && !ids::is_valid_summoner_id(id) {
+            incr(&self.metrics.invalid_ids);
+            return warn!("[{}] Skipping invalid summoner id {:?}", self.region, id);
+        }
+        let player = async {
+            let _permit = self
+                .endpoints
+                .acquire(Endpoint::Summoner, self.region)
+                .await;
+            self.api
+                .tft_summoner_v1()
+                .get_by_summoner_id(self.region, id)
+                .await
+        }
+        .instrument(info_span!("summoner_lookup"))
+        .await;
+        self.record_api_outcome(&player);
         let player = match player {
             Ok(player) => player,
-            Err(e) => return error!("tft_summoner_v1 error: {}", e.to_string()),
+            Err(e) => return error!("tft_summoner_v1 error: {}", e),
         };
-        let player_match = self
-            .api
-            .tft_match_v1()
-            .get_match_ids_by_puuid(self.region_major, &player.puuid, Some(10))
-            .await;
+        if self.config.validate_ids && !ids::is_valid_puuid(&player.puuid) {
+            incr(&self.metrics.invalid_ids);
+            return warn!(
+                "[{}] Skipping summoner {} with invalid puuid {:?}",
+                self.region, id, player.puuid
+            );
+        }
+        let player_match = async {
+            let _share = self.match_slot().await;
+            let _permit = self
+                .endpoints
+                .acquire(Endpoint::MatchList, self.region_major)
+                .await;
+            self.api
+                .tft_match_v1()
+                .get_match_ids_by_puuid(self.region_major, &player.puuid, Some(depth))
+                .await
+        }
+        .instrument(info_span!("match_list"))
+        .await;
+        self.record_api_outcome(&player_match);
         let player_match = match player_match {
             Ok(player_match) => player_match,
-            Err(e) => return error!("tft_match_v1 error: {}", e.to_string()),
+            Err(e) => return error!("tft_match_v1 error: {}", e),
         };
+        incr(&self.metrics.summoners_processed);
+        if player_match.is_empty() {
+            // e.g. a new account, or one that hasn't played in a while. The summoner id
+            // was just resolved live, so it isn't stale.
+            incr(&self.metrics.summoners_without_matches);
+            debug!(
+                "{} {} {:#?} has no recent matches",
+                index, self.region, player.name
+            );
+            return;
+        }
 
-        let mut new: i32 = 0;
-        let mut repeat: i32 = 0;
-        let mut new_error: i32 = 0;
-        for x in &player_match {
-            match self.process_match_id(&x).await {
-                Err(e) => error!("{:#?}", e),
-                Ok(-1) => new_error += 1,
-                Ok(0) => repeat += 1,
-                Ok(1) => new += 1,
-                Ok(_) => unreachable!(),
+        let mut counts = MatchCounts::default();
+        let mut ordered: Vec<&String> = player_match.iter().collect();
+        if self.config.oldest_matches_first {
+            ordered.reverse();
+        }
+        for x in ordered {
+            if self.match_cap.reached() {
+                break;
+            }
+            if self.too_old.lock().unwrap().contains(x) {
+                match self.skip_too_old(x).await {
+                    Ok(()) => counts.add(Some(0)),
+                    Err(e) => {
+                        error!("Error skipping match {}: {}", x, e);
+                        counts.add(None);
+                    }
+                }
+                continue;
+            }
+            let processed = self
+                .process_match_id(x, Some(&player))
+                .instrument(info_span!("process_match_id", match_id = %x))
+                .await;
+            match processed {
+                Ok(ret) => {
+                    if ret == -1 {
+                        incr(&self.metrics.match_errors);
+                    }
+                    counts.add(Some(ret))
+                }
+                Err(e) => {
+                    error!("Error processing match {}: {}", x, e);
+                    incr(&self.metrics.match_errors);
+                    counts.add(None);
+                }
             }
         }
         debug!(
-            "{} {} {:#?} {} ({} New, {} Old, {} Error)",
+            "{} {} {:#?} {} ({})",
             index,
             self.region,
             player.name,
             player_match.len(),
-            new,
-            repeat,
-            new_error
+            counts
         );
     }
 
-    async fn process_match_id(&self, id: &str) -> anyhow::Result<i64> {
-        let matches = self.db.collection(MATCHES_COLLECTION_NAME);
+    /// Collections a match being looked up by id may be stored in
+    fn match_lookup_collections(&self, now: DateTime<Utc>) -> Vec<String> {
+        if self.config.partition_matches_monthly {
+            collections::recent_partitions(&self.matches_collection, now)
+        } else {
+            vec![self.matches_collection.clone()]
+        }
+    }
+
+    /// Collection a match played, or a dummy written, at `timestamp` is stored in
+    fn match_collection_for(&self, timestamp: DateTime<Utc>) -> mongodb::Collection<Document> {
+        if self.config.partition_matches_monthly {
+            let name = collections::partition_name(&self.matches_collection, timestamp);
+            self.db.collection(&name)
+        } else {
+            self.db.collection(&self.matches_collection)
+        }
+    }
+
+    // Index `_lobbyHash`, the top-lobbies sort, and `_metaFingerprint` if stored, on the
+    // match collections, and `_documentExpire` on monthly partitions. Run every cycle so
+    // new monthly partitions pick them up; failures are only logged.
+    async fn ensure_match_indexes(&self) {
+        for name in self.match_lookup_collections(Utc::now()) {
+            let mut commands = vec![lobby_index_command(&name)];
+            if self.config.partition_matches_monthly {
+                commands.push(ttl_index_command(&name));
+            }
+            if self.config.top_lobbies_index {
+                commands.push(top_lobbies_index_command(&name));
+            }
+            if self.config.meta_fingerprint {
+                commands.push(meta_fingerprint_index_command(&name));
+            }
+            if self.config.rerank_unranked_per_cycle.is_some() {
+                commands.push(unranked_index_command(&name));
+            }
+            for command in commands {
+                let _permit = self.write_permit().await;
+                let ret = self
+                    .db_call("Error creating match index", || {
+                        self.db.run_command(command.clone(), None)
+                    })
+                    .await;
+                if let Err(e) = ret {
+                    error!("{}", e);
+                }
+            }
+        }
+    }
+
+    // Which of `match_lookup_collections` has a document for the match, if any
+    async fn find_match_collection(&self, id: &str) -> error::Result<Option<String>> {
         let filter = doc! {"_id": id};
-        let count_options = CountOptions::default();
-        let num_doc = matches
-            .count_documents(filter, count_options)
-            .await
-            .map_err(|_| anyhow::Error::msg("Error counting documents"))?;
+        for name in self.match_lookup_collections(Utc::now()) {
+            let matches_read = self.db_read.collection::<Document>(&name);
+            let num_doc = self
+                .db_call("Error counting documents", || {
+                    matches_read.count_documents(filter.clone(), CountOptions::default())
+                })
+                .await?;
+            if num_doc != 0 {
+                return Ok(Some(name));
+            }
+        }
+        Ok(None)
+    }
 
-        if num_doc != 0 {
+    /// `seed` is the top player whose match history surfaced this match, if any.
+    /// Returns 1 if stored, 0 if already known, -1 on failure and 2 if it should be
+    /// retried on a later cycle.
+    async fn process_match_id(&self, id: &str, seed: Option<&Summoner>) -> error::Result<i64> {
+        if let Some(stored_in) = self.find_match_collection(id).await? {
+            if self.config.migrate_stored_matches {
+                self.migrate_stored_match(&stored_in, id).await?;
+            }
             return Ok(0);
         }
+        self.audit(id, Decision::Discovered, None).await;
+        if self.summoner_call_budget.exhausted() {
+            // Its participants would likely need lookups past the cap, so the match-v1
+            // call would be wasted. Left unmarked, so it's picked up next cycle.
+            incr(&self.metrics.deferred_matches);
+            self.audit(id, Decision::Deferred, None).await;
+            return Ok(2);
+        }
+        let slot = self.match_cap.reserve(|| {
+            info!(
+                "[{:?} {}] Reached {} new matches this cycle, skipping the rest.",
+                self.queue_type,
+                self.region,
+                self.config.max_new_matches_per_cycle.unwrap_or(u32::MAX)
+            )
+        });
+        let slot = match slot {
+            Some(slot) => slot,
+            // Left unmarked, so it's picked up next cycle
+            None => return Ok(2),
+        };
 
         let current_timestamp = Utc::now();
-        // Fetch details of the match
-        match self
-            .api
-            .tft_match_v1()
-            .get_match(self.region_major, id)
-            .await
-            .unwrap_or_else(|e| {
-                // let req_err = e.source_reqwest_error().to_string();
+        // Fetch details of the match. Failures carry whether they are worth retrying.
+        let fetched = async {
+            let _share = self.match_slot().await;
+            if let Some(queue) = self.match_queues.get(&self.region_major) {
+                return queue.fetch(id).await;
+            }
+            let _permit = self
+                .endpoints
+                .acquire(Endpoint::Match, self.region_major)
+                .await;
+            Ok(get_match_response(&self.api, self.region_major, id).await)
+        }
+        .instrument(info_span!("get_match"))
+        .await;
+        let fetched = match fetched {
+            // The match fetch worker running it panicked
+            Err(e) => {
                 error!("Error on GET_MATCH({},{}): {}", self.region_major, id, e);
-                None
-            }) {
-            Some(game) => {
+                Err((e.to_string(), error::is_retryable(&e)))
+            }
+            Ok(fetched) => {
+                self.record_api_outcome(&fetched);
+                match fetched {
+                    Ok(response) => self.read_match_response(id, response).await,
+                    Err(e) => {
+                        error!("Error on GET_MATCH({},{}): {}", self.region_major, id, e);
+                        let failure = e.to_string();
+                        let retryable = error::is_retryable(&CrawlerError::api("get_match", e));
+                        Err((failure, retryable))
+                    }
+                }
+            }
+        };
+        let game = match fetched {
+            // Converted before enrichment, so a match whose schema drifted from `TypedMatch`
+            // fails like an unparsable one, with a dummy, rather than on every cycle
+            Ok(Some((game, body))) => match self.config.typed_match {
+                TypedMatchMode::Off => Ok((game, None)),
+                _ => match TypedMatch::from_raw(&body) {
+                    Ok(typed) => Ok((game, Some(typed))),
+                    Err(e) => {
+                        error!("Match {} doesn't fit TypedMatch: {}", id, e);
+                        Err((format!("Typed match: {}", e), false))
+                    }
+                },
+            },
+            Ok(None) => {
+                let grace = chrono::Duration::from_std(self.config.not_found_grace)
+                    .unwrap_or_else(|_| Duration::max_value());
+                let expired = self
+                    .not_found
+                    .lock()
+                    .unwrap()
+                    .expired(id, current_timestamp, grace);
+                if !expired {
+                    // Possibly too new to be indexed, try again next cycle
+                    debug!("Match {} not found, within grace period", id);
+                    self.audit(id, Decision::NotFoundRetrying, None).await;
+                    return Ok(2);
+                }
+                // Not found past the grace period is as retried as it gets
+                Err(("not found".to_string(), false))
+            }
+            Err(failure) => Err(failure),
+        };
+        match game {
+            Ok((game, typed)) => {
+                let played = Utc.timestamp_millis(game.info.game_datetime);
+                self.newest_match.lock().unwrap().observe(id, played);
+                let cutoff = self
+                    .config
+                    .max_match_age
+                    .and_then(|max_age| current_timestamp.checked_sub_signed(max_age));
+                // No cutoff for a max age reaching before any representable date
+                if let Some(cutoff) = cutoff {
+                    if played < cutoff {
+                        self.too_old.lock().unwrap().observe(id, played, cutoff);
+                        self.skip_too_old(id).await?;
+                        return Ok(0);
+                    }
+                }
                 // Get information about the participants in this game
-                let (player_data, avg_elo, avg_elo_text) =
-                    self.get_extended_participant_info(&game).await?;
+                let enrichment = self
+                    .get_extended_participant_info(&game)
+                    .instrument(info_span!("participant_enrichment"))
+                    .await;
+                let enrichment = match enrichment {
+                    Ok(enrichment) => enrichment,
+                    Err(CrawlerError::Deferred(_)) => {
+                        incr(&self.metrics.deferred_matches);
+                        self.audit(id, Decision::Deferred, None).await;
+                        return Ok(2);
+                    }
+                    Err(e) => return Err(e),
+                };
 
                 let match_timestamp = Utc.timestamp_millis(game.info.game_datetime);
-                let mut bson: Bson = serde_json::to_value(game)?.try_into()?;
-                let doc = bson
-                    .as_document_mut()
-                    .ok_or_else(|| anyhow::Error::msg("BSON is not a doc"))?;
+                if enrichment.num_ranked < self.config.min_ranked_to_store {
+                    debug!(
+                        "Match {} has {} ranked participants, not storing",
+                        id, enrichment.num_ranked
+                    );
+                    incr(&self.metrics.unranked_matches_skipped);
+                    // Kept as long as a stored match would be, so it isn't fetched again
+                    let expire = match_expiry(current_timestamp, match_timestamp);
+                    let matches = self.match_collection_for(match_timestamp);
+                    self.insert_dummy(&matches, id, current_timestamp, expire)
+                        .await?;
+                    let reason = format!("{} ranked participants", enrichment.num_ranked);
+                    self.audit(id, Decision::FilteredByElo, Some(&reason)).await;
+                    return Ok(0);
+                }
+                let expire = match_expiry(current_timestamp, match_timestamp);
+                let derived = derived_fields(&game, enrichment);
+                let raw_blob =
+                    !self.config.summary_only && self.config.typed_match != TypedMatchMode::Instead;
+                let mut doc = if raw_blob {
+                    match_document(&game, self.config.direct_bson)
+                        .map_err(CrawlerError::serialization)?
+                } else {
+                    // Derived fields only, no raw match blob
+                    doc! {}
+                };
+                let doc = &mut doc;
                 doc.insert("_id", Bson::String(id.to_string()));
-                doc.insert("_documentCreated", Bson::DateTime(current_timestamp));
-                doc.insert("_matchTimestamp", Bson::DateTime(match_timestamp));
-                // Don't expire this document until the game date was 4 days ago
-                // Additionally don't expire within the next 24 hours
-                let expire = std::cmp::max(
-                    current_timestamp + Duration::hours(24),
-                    match_timestamp + Duration::days(4),
-                );
-                doc.insert("_documentExpire", Bson::DateTime(expire));
+                doc.insert("_region", self.region.to_string());
+                doc.extend(derived);
+                self.insert_datetime(doc, "_documentCreated", current_timestamp);
+                self.insert_datetime(doc, "_matchTimestamp", match_timestamp);
+                self.insert_datetime(doc, "_documentExpire", expire);
+                if let Some(typed) = typed {
+                    let typed = typed.to_document().map_err(CrawlerError::serialization)?;
+                    doc.insert("_typedMatch", typed);
+                }
+                if let (true, Some(seed)) = (self.config.tag_discovered_via, seed) {
+                    // First writer wins: a match surfaced by several players keeps the first
+                    doc.insert(
+                        "_discoveredVia",
+                        doc! {"summonerId": &seed.id, "puuid": &seed.puuid},
+                    );
+                }
 
-                doc.insert("_aggregatedPlayerInfo", player_data);
-                doc.insert("_avgElo", avg_elo);
-                doc.insert("_avgEloText", avg_elo_text);
+                if let (true, Some(raw_fields)) = (raw_blob, &self.config.raw_fields) {
+                    raw_fields.apply(doc);
+                }
+                if raw_blob {
+                    let size = document_size(doc);
+                    if size > self.config.max_document_bytes {
+                        warn!(
+                            "Match {} document is {} bytes (limit {}), storing summary only",
+                            id, size, self.config.max_document_bytes
+                        );
+                        strip_raw_fields(doc);
+                    }
+                }
 
-                matches
-                    .insert_one(doc.clone(), None)
-                    .await
-                    .map_err(|_| anyhow::Error::msg("Error inserting document"))?;
+                let matches = self.match_collection_for(match_timestamp);
+                if !self.insert_one(&matches, doc).await? {
+                    self.audit(id, Decision::SkippedDuplicate, None).await;
+                    return Ok(0);
+                }
+                self.audit(id, Decision::Stored, None).await;
+                incr(&self.metrics.new_matches);
+                slot.keep();
+                let stored = StoredMatch {
+                    id,
+                    game: &game,
+                    doc,
+                };
+                hooks::run_all(self, &self.hooks, &stored).await;
                 Ok(1)
             }
-            None => {
-                // Insert a dummy document, so we don't keep trying to fetch this game
-                let mut doc = doc! {};
-                doc.insert("_id", Bson::String(id.to_string()));
-                doc.insert("_documentCreated", Bson::DateTime(current_timestamp));
+            Err((failure, retryable)) => {
+                let played_after = self.newest_match.lock().unwrap().played_no_earlier_than(id);
+                let window = chrono::Duration::from_std(self.config.recent_match_window)
+                    .unwrap_or_else(|_| Duration::max_value());
+                // A 403 and the like fail fast, however recent the match
+                if retryable
+                    && played_after.is_some_and(|played| current_timestamp - played < window)
+                {
+                    // Likely not consistent across Riot's backends yet, don't cache the failure
+                    debug!("Match {} failed but is recent, retrying later", id);
+                    self.audit(id, Decision::TooRecent, Some(&failure)).await;
+                    return Ok(2);
+                }
                 // Expire document 24 hours after creation
-                doc.insert(
-                    "_documentExpire",
-                    Bson::DateTime(current_timestamp + Duration::hours(24)),
-                );
-                matches
-                    .insert_one(doc.clone(), None)
-                    .await
-                    .map_err(|_| anyhow::Error::msg("Error inserting document"))?;
+                let expire = current_timestamp + Duration::hours(24);
+                let matches = self.match_collection_for(current_timestamp);
+                if !self
+                    .insert_dummy(&matches, id, current_timestamp, expire)
+                    .await?
+                {
+                    self.audit(id, Decision::SkippedDuplicate, None).await;
+                    return Ok(0);
+                }
+                self.audit(id, Decision::DummyWritten, Some(&failure)).await;
                 Ok(-1)
             }
         }
     }
 
-    async fn get_extended_participant_info(
+    // Store a dummy for a match older than `Config::max_match_age`, so it isn't fetched
+    // again for a while. A match already stored is left alone.
+    async fn skip_too_old(&self, id: &str) -> error::Result<()> {
+        let now = Utc::now();
+        let matches = self.match_collection_for(now);
+        if self
+            .insert_dummy(&matches, id, now, now + Duration::hours(24))
+            .await?
+        {
+            incr(&self.metrics.too_old_matches);
+            self.audit(id, Decision::TooOld, None).await;
+        }
+        Ok(())
+    }
+
+    // Append this cycle to `CYCLE_HISTORY_COLLECTION_NAME` if enabled. Failures are only logged.
+    async fn record_cycle(&self, duration: std::time::Duration, cycle_metrics: &MetricsSnapshot) {
+        let retention = match self.config.cycle_history_retention {
+            Some(retention) => retention,
+            None => return,
+        };
+        let now = Utc::now();
+        let mut entry = cycle_history::entry(
+            &format!("{:?}", self.queue_type),
+            self.region.as_ref(),
+            duration,
+            cycle_metrics,
+        );
+        self.insert_datetime(&mut entry, "_documentCreated", now);
+        let expire = retention_expiry(now, retention);
+        self.insert_datetime(&mut entry, "_documentExpire", expire);
+        let collection = self
+            .db
+            .collection::<Document>(&self.suffixed(CYCLE_HISTORY_COLLECTION_NAME));
+        let _permit = self.write_permit().await;
+        let ret = self
+            .db_call("Error writing cycle history", || {
+                collection.insert_one(entry.clone(), None)
+            })
+            .await;
+        if let Err(e) = ret {
+            error!("{}", e);
+        }
+    }
+
+    // Add the cycle's errors to today's `DAILY_ROLLUP_COLLECTION_NAME` entry if enabled.
+    // Failures are only logged.
+    async fn record_daily_errors(&self, cycle_metrics: &MetricsSnapshot) {
+        if !self.config.daily_rollup {
+            return;
+        }
+        let inc = match daily_rollup::cycle_inc(cycle_metrics) {
+            Some(inc) => inc,
+            None => return,
+        };
+        let command = daily_rollup::update_command(
+            &self.suffixed(DAILY_ROLLUP_COLLECTION_NAME),
+            self.region.as_ref(),
+            &format!("{:?}", self.queue_type),
+            &daily_rollup::day(Utc::now()),
+            inc,
+        );
+        let _permit = self.write_permit().await;
+        let ret = self
+            .db_call("Error updating daily rollup", || {
+                self.db.run_command(command.clone(), None)
+            })
+            .await;
+        if let Err(e) = ret {
+            error!("{}", e);
+        }
+    }
+
+    // Read and parse a successful (or not found, None) match-v1 response, returning the
+    // match with its body. With `Config::raw_responses_retention`, the body is kept in
+    // `RAW_RESPONSES_COLLECTION_NAME` before parsing it, so bodies that fail to parse are
+    // kept too. Failures carry whether they are worth retrying, like riven's in
+    // `process_match_id`.
+    async fn read_match_response(
         &self,
-        game: &riven::models::tft_match_v1::Match,
-    ) -> anyhow::Result<(Vec<Bson>, i32, String)> {
-        let mut ret: Vec<Bson> = vec![];
-        let mut sum = 0;
-        let mut num_ranked = 0;
+        id: &str,
+        response: ResponseInfo,
+    ) -> Result<Option<(Match, String)>, (String, bool)> {
+        let status = response.response.status().as_u16();
+        let body = match response.response.text().await {
+            Ok(body) => body,
+            Err(e) => {
+                error!("Error reading match {}: {}", id, e);
+                // Cut off mid-response, as transient as no response at all
+                return Err((e.to_string(), true));
+            }
+        };
+        if let Some(retention) = self.config.raw_responses_retention {
+            self.store_raw_response(id, status, &body, retention).await;
+        }
+        if response.status_none {
+            return Ok(None);
+        }
+        match serde_json::from_str(&body) {
+            Ok(game) => Ok(Some((game, body))),
+            Err(e) => {
+                error!("Error parsing match {}: {}", id, e);
+                Err((e.to_string(), false))
+            }
+        }
+    }
+
+    // Keep a match-v1 response body for `retention`. Failures are only logged.
+    async fn store_raw_response(
+        &self,
+        id: &str,
+        status: u16,
+        body: &str,
+        retention: std::time::Duration,
+    ) {
+        let now = Utc::now();
+        let mut doc = doc! {
+            "_id": id,
+            "region": self.region.as_ref(),
+            "status": status as i32,
+            "body": body,
+        };
+        self.insert_datetime(&mut doc, "_documentCreated", now);
+        let expire = retention_expiry(now, retention);
+        self.insert_datetime(&mut doc, "_documentExpire", expire);
+        let collection = self
+            .db
+            .collection::<Document>(&self.suffixed(RAW_RESPONSES_COLLECTION_NAME));
+        let _permit = self.write_permit().await;
+        let ret = self
+            .db_call("Error writing raw response", || {
+                collection.replace_one(
+                    doc! {"_id": id},
+                    doc.clone(),
+                    ReplaceOptions::builder().upsert(true).build(),
+                )
+            })
+            .await;
+        if let Err(e) = ret {
+            error!("{}", e);
+        }
+    }
+
+    // Append to `AUDIT_COLLECTION_NAME` if enabled, see `Config::audit_log_retention`.
+    // Failures are only logged.
+    async fn audit(&self, id: &str, decision: Decision, reason: Option<&str>) {
+        let retention = match self.config.audit_log_retention {
+            Some(retention) => retention,
+            None => return,
+        };
+        let now = Utc::now();
+        let mut event = audit::event(id, self.region.as_ref(), decision, reason);
+        self.insert_datetime(&mut event, "_documentCreated", now);
+        let expire = retention_expiry(now, retention);
+        self.insert_datetime(&mut event, "_documentExpire", expire);
+        let collection = self
+            .db
+            .collection::<Document>(&self.suffixed(AUDIT_COLLECTION_NAME));
+        let _permit = self.write_permit().await;
+        let ret = self
+            .db_call("Error writing audit event", || {
+                collection.insert_one(event.clone(), None)
+            })
+            .await;
+        if let Err(e) = ret {
+            error!("{}", e);
+        }
+    }
+
+    // Recompute the derived fields of a stored match from its raw blob if they are from an
+    // older `SCHEMA_VERSION`. Dummies and summaries, with no raw match, are left alone.
+    // Participants keep the ranks stored at match time: `_avgElo` and `_avgEloText` are
+    // recomputed from `_aggregatedPlayerInfo`, with no API calls.
+    async fn migrate_stored_match(&self, collection: &str, id: &str) -> error::Result<()> {
+        let matches = self.db.collection::<Document>(collection);
+        let filter = doc! {
+            "_id": id,
+            "info": {"$exists": true},
+            "$or": [
+                {"_schemaVersion": {"$exists": false}},
+                {"_schemaVersion": {"$lt": SCHEMA_VERSION}},
+            ],
+        };
+        let stored = self
+            .db_call("Error finding match to migrate", || {
+                matches.find_one(filter.clone(), FindOneOptions::default())
+            })
+            .await?;
+        let stored = match stored {
+            Some(stored) => stored,
+            None => return Ok(()),
+        };
+        let game = match raw_match(&stored) {
+            Some(game) => game,
+            None => {
+                // e.g. pruned by `Config::raw_fields`
+                debug!("Match {} has no complete raw match to migrate from", id);
+                return Ok(());
+            }
+        };
+        let (_, avg_elo, avg_elo_text) = aggregate_participants(
+            &rerank::stored_infos(stored.get_array("_aggregatedPlayerInfo")?),
+            self.apex_cutoffs(),
+            self.config.elo_rounding,
+            self.config.omit_apex_division,
+        );
+        let mut fields = match_fields(&game);
+        fields.insert("_avgElo", avg_elo);
+        fields.insert("_avgEloText", avg_elo_text);
+        fields.insert("_schemaVersion", SCHEMA_VERSION);
+        let update = doc! {"$set": fields};
+        let _permit = self.write_permit().await;
+        self.db_call("Error migrating match", || {
+            matches.update_one(doc! {"_id": id}, update.clone(), None)
+        })
+        .await?;
+        incr(&self.metrics.migrated_matches);
+        Ok(())
+    }
+
+    // Look up again the participants stored as unranked in up to `limit` of the region's
+    // matches, across `match_lookup_collections`, and update the matches where some have
+    // since placed. Each collection continues after the last match checked by the
+    // previous pass, and starts over once none are left.
+    // `_placements` and `_upsetScore` keep the ranks at match time.
+    async fn rerank_unranked(&self, limit: u32) -> error::Result<()> {
+        let mut remaining = limit;
+        for collection_name in self.match_lookup_collections(Utc::now()) {
+            if remaining == 0 {
+                break;
+            }
+            remaining -= self.rerank_collection(&collection_name, remaining).await?;
+        }
+        Ok(())
+    }
 
-        let mut ranks_vec = vec![];
+    // `rerank_unranked` in one collection, returning how many matches were checked
+    async fn rerank_collection(&self, collection_name: &str, limit: u32) -> error::Result<u32> {
+        let state = self
+            .db
+            .collection::<Document>(&self.suffixed(CRAWL_STATE_COLLECTION_NAME));
+        let checkpoint_id = format!("rerank-{}-{}", self.cursor_id(), collection_name);
+        let last_id = self
+            .db_call("Error reading rerank checkpoint", || {
+                state.find_one(doc! {"_id": &checkpoint_id}, None)
+            })
+            .await?
+            .and_then(|checkpoint| checkpoint.get_str("lastId").ok().map(str::to_string))
+            .unwrap_or_default();
 
-        for puuid in &game.metadata.participants {
-            // 1. parse 8 puuids
-            trace!("puuid {:?}", puuid);
+        let matches = self.db.collection::<Document>(collection_name);
+        let filter = doc! {
+            "_id": {"$gt": &last_id},
+            "_region": self.region.to_string(),
+            "_aggregatedPlayerInfo.tftTier": "unranked",
+        };
+        let options = FindOptions::builder()
+            .sort(doc! {"_id": 1})
+            .limit(limit as i64)
+            .projection(doc! {"_aggregatedPlayerInfo": 1})
+            .build();
+        let cursor = self
+            .db_call("Error finding matches to rerank", || {
+                matches.find(filter.clone(), options.clone())
+            })
+            .await?;
+        let found: Vec<_> = cursor.collect().await;
+        let found = found
+            .into_iter()
+            .collect::<mongodb::error::Result<Vec<Document>>>()
+            .map_err(|e| CrawlerError::db("Error finding matches to rerank", e))?;
 
-            // 2. get 8 summonerIds (cached or riot query)
-            let summoner_doc = self
-                .tft_summoner_v1(puuid)
-                .await
-                .map_err(|_| anyhow::Error::msg("Error tft_summoner_v1"))?;
-            let summoner_id = summoner_doc.get_str("id")?;
-            trace!("{}", summoner_id);
-
-            // 3. get 8 tft league entries (cached or riot query)
-            let (rank_known, tft_tier, tft_rank, tft_league_points) = {
-                let league_doc = self.tft_league_v1(summoner_id).await;
-                match league_doc {
-                    Ok(league_doc) => {
-                        let ranked: bool = league_doc.get_str("_status")? == "ranked";
-                        let tft_tier = league_doc.get_str("tier").unwrap_or("unranked");
-                        let tft_rank = league_doc.get_str("rank").unwrap_or("unranked");
-                        let tft_league_points =
-                            league_doc.get_i32("leaguePoints").unwrap_or(i32::MIN);
-                        (
-                            ranked,
-                            tft_tier.to_string(),
-                            tft_rank.to_string(),
-                            tft_league_points,
-                        )
-                    }
-                    Err(_e) => {
-                        error!("Error tft_league_v1.by_summoner_id({})", summoner_id,);
-                        (
-                            false,
-                            "unknown".to_string(),
-                            "unknown".to_string(),
-                            i32::MIN,
-                        )
+        let mut next_id = String::new();
+        for doc in &found {
+            let id = doc.get_str("_id")?;
+            let mut player_data = doc.get_array("_aggregatedPlayerInfo")?.clone();
+            let mut infos = vec![];
+            for puuid in rerank::unranked_puuids(&player_data) {
+                // Left unranked this pass, so one failing participant can't pin it here
+                match self.get_participant_info(&puuid).await {
+                    Ok(info) => infos.push(info),
+                    Err(e @ CrawlerError::Deferred(_)) => {
+                        debug!("Not reranking {} of {}: {}", puuid, id, e)
                     }
+                    Err(e) => warn!("Error reranking {} of {}: {}", puuid, id, e),
                 }
-            };
+            }
+            if rerank::apply_ranks(&mut player_data, &infos) > 0 {
+                let (_, avg_elo, avg_elo_text) = aggregate_participants(
+                    &rerank::stored_infos(&player_data),
+                    self.apex_cutoffs(),
+                    self.config.elo_rounding,
+                    self.config.omit_apex_division,
+                );
+                let update = doc! {"$set": {
+                    "_aggregatedPlayerInfo": player_data,
+                    "_avgElo": avg_elo,
+                    "_avgEloText": avg_elo_text,
+                }};
+                let _permit = self.write_permit().await;
+                self.db_call("Error updating reranked match", || {
+                    matches.update_one(doc! {"_id": id}, update.clone(), None)
+                })
+                .await?;
+                incr(&self.metrics.reranked_matches);
+            }
+            next_id = id.to_string();
+        }
+        if found.len() < limit as usize {
+            // Reached the end, start over next time
+            next_id = String::new();
+        }
+        let _permit = self.write_permit().await;
+        self.db_call("Error writing rerank checkpoint", || {
+            state.replace_one(
+                doc! {"_id": &checkpoint_id},
+                doc! {"_id": &checkpoint_id, "lastId": &next_id},
+                ReplaceOptions::builder().upsert(true).build(),
+            )
+        })
+        .await?;
+        Ok(found.len() as u32)
+    }
+
+    // Insert a dummy document, so we don't keep trying to fetch this game
+    async fn insert_dummy(
+        &self,
+        matches: &mongodb::Collection<Document>,
+        id: &str,
+        current_timestamp: DateTime<Utc>,
+        expire: DateTime<Utc>,
+    ) -> error::Result<bool> {
+        let mut doc = doc! {};
+        doc.insert("_id", Bson::String(id.to_string()));
+        self.insert_datetime(&mut doc, "_documentCreated", current_timestamp);
+        self.insert_datetime(&mut doc, "_documentExpire", expire);
+        self.insert_one(matches, &doc).await
+    }
 
-            // 4. construct object to append to the game with all known info
-            let aggregated_doc = doc! {
-                "summonerId": summoner_id,
-                "summonerName": summoner_doc.get_str("name")?,
-                "accountId": summoner_doc.get_str("accountId")?,
-                "puuid": puuid,
-                "tftTier": tft_tier.clone(),
-                "tftRank": tft_rank.clone(),
-                "tftLeaguePoints": tft_league_points,
+    async fn get_extended_participant_info(
+        &self,
+        game: &riven::models::tft_match_v1::Match,
+    ) -> error::Result<Enrichment> {
+        // Results are tagged with their index so `_aggregatedPlayerInfo[i]` always
+        // corresponds to `metadata.participants[i]`, however the lookups are scheduled
+        let mut results = vec![];
+        let mut partial = false;
+        for (index, puuid) in game.metadata.participants.iter().enumerate() {
+            if self.config.validate_ids && !ids::is_valid_puuid(puuid) {
+                incr(&self.metrics.invalid_ids);
+                warn!(
+                    "Invalid puuid {:?} in {}, storing placeholder",
+                    puuid, game.metadata.match_id
+                );
+                partial = true;
+                results.push((index, ParticipantInfo::placeholder(puuid)));
+                continue;
+            }
+            let info = match self.get_participant_info(puuid).await {
+                Ok(info) => info,
+                // e.g. a puuid summoner-v1 no longer resolves. Transient errors still
+                // fail the match, so it is retried rather than stored incomplete.
+                Err(e) if self.config.partial_enrichment && !e.is_transient() => {
+                    error!(
+                        "Error getting participant {} of {}, storing placeholder: {}",
+                        puuid, game.metadata.match_id, e
+                    );
+                    partial = true;
+                    ParticipantInfo::placeholder(puuid)
+                }
+                Err(e) => return Err(e),
             };
-            ret.push(aggregated_doc.into());
+            results.push((index, info));
+        }
+        let mut infos = collect_indexed(game.metadata.participants.len(), results);
+        let level = self.config.enrichment_level;
+        if self.config.store_companions || level == EnrichmentLevel::Full {
+            for info in &mut infos {
+                info.companion = companion_info(&game.info.participants, &info.puuid);
+            }
+        }
+        let (mut player_data, avg_elo, avg_elo_text) = aggregate_participants(
+            &infos,
+            self.apex_cutoffs(),
+            self.config.elo_rounding,
+            self.config.omit_apex_division,
+        );
+        let performance_level = if self.config.participant_performance {
+            EnrichmentLevel::Full
+        } else {
+            level
+        };
+        add_performance(
+            &mut player_data,
+            &infos,
+            &game.info.participants,
+            performance_level,
+        );
+        if level == EnrichmentLevel::Full {
+            add_traits(&mut player_data, &infos, &game.info.participants);
+            if self.config.intern_traits {
+                let names = interning::trait_names(&player_data);
+                let ids = self.string_table.ids(&self.db, &names).await?;
+                interning::intern_traits(&mut player_data, &ids);
+            }
+        }
+        if self.config.store_pid_hashes {
+            add_pid_hashes(&mut player_data);
+        }
+        if self.config.rank_observed_at {
+            add_rank_observed_at(&mut player_data, &infos);
+        }
+        let upset_score = if self.config.upset_score {
+            lobby_upset_score(&infos, &game.info.participants)
+        } else {
+            None
+        };
+        let high_variance = self
+            .config
+            .high_variance_spread
+            .and_then(|threshold| Some(elo_spread(&infos)? > threshold));
+        let placements = if self.config.store_placements {
+            Some(placements(
+                &infos,
+                &game.info.participants,
+                self.config.store_pid_hashes,
+            ))
+        } else {
+            None
+        };
+        let meta_fingerprint = if self.config.meta_fingerprint && level == EnrichmentLevel::Full {
+            meta_fingerprint(&game.info.participants)
+        } else {
+            None
+        };
+        Ok(Enrichment {
+            player_data,
+            avg_elo,
+            avg_elo_text,
+            num_ranked: infos.iter().filter(|info| info.ranked).count(),
+            partial,
+            upset_score,
+            placements,
+            meta_fingerprint,
+            high_variance,
+        })
+    }
+
+    async fn get_participant_info(&self, puuid: &str) -> error::Result<ParticipantInfo> {
+        // 1. parse 8 puuids
+        trace!("puuid {:?}", puuid);
 
-            if rank_known {
-                ranks_vec.push((tft_tier.clone(), tft_rank.clone(), tft_league_points));
+        // 2. get 8 summonerIds (cached or riot query)
+        let summoner_doc = self
+            .participant_retry(|| self.tft_summoner_v1(puuid, self.config.summoner_cache_max_age))
+            .await?;
+        let summoner_id = summoner_doc.get_str("id")?;
+        trace!("{}", summoner_id);
 
-                sum += league_to_numeric(&tft_tier, &tft_rank, tft_league_points);
-                num_ranked += 1;
+        // 3. get 8 tft league entries (cached or riot query)
+        let (rank_known, tft_tier, tft_rank, tft_league_points, rank_observed_at) = {
+            let league_doc = self
+                .participant_retry(|| {
+                    self.tft_league_v1(summoner_id, self.config.league_cache_max_age)
+                })
+                .await;
+            match league_doc {
+                Ok(league_doc) => {
+                    let ranked: bool = league_doc.get_str("_status")? == "ranked";
+                    let tft_tier = league_doc.get_str("tier").unwrap_or("unranked");
+                    let tft_rank = league_doc.get_str("rank").unwrap_or("unranked");
+                    let tft_league_points = league_doc.get_i32("leaguePoints").unwrap_or(i32::MIN);
+                    // When the cached entry was fetched, now for a cache miss
+                    let observed_at = league_doc.get_datetime("_documentCreated").ok().copied();
+                    (
+                        ranked,
+                        tft_tier.to_string(),
+                        tft_rank.to_string(),
+                        tft_league_points,
+                        observed_at,
+                    )
+                }
+                Err(e) => {
+                    error!("Error tft_league_v1.by_summoner_id({}): {}", summoner_id, e);
+                    (
+                        false,
+                        "unknown".to_string(),
+                        "unknown".to_string(),
+                        i32::MIN,
+                        None,
+                    )
+                }
             }
+        };
+
+        // 4. construct object to append to the game with all known info
+        Ok(ParticipantInfo {
+            puuid: puuid.to_string(),
+            summoner_id: summoner_id.to_string(),
+            summoner_name: summoner_doc.get_str("name")?.to_string(),
+            account_id: summoner_doc.get_str("accountId")?.to_string(),
+            ranked: rank_known,
+            tier: tft_tier,
+            rank: tft_rank,
+            league_points: tft_league_points,
+            // Filled in from the match, see `Config::store_companions`
+            companion: None,
+            rank_observed_at,
+        })
+    }
+
+    // Count a crawl call towards `Config::failure_ratio_threshold`
+    fn record_api_outcome<T>(&self, ret: &Result<T, riven::RiotApiError>) {
+        if self.config.failure_ratio_threshold.is_none() {
+            return;
+        }
+        let failed = ret.as_ref().err().is_some_and(error::is_transient_api);
+        self.api_outcomes
+            .lock()
+            .unwrap()
+            .record(Instant::now(), failed);
+    }
+
+    // Whether recent calls failed often enough to pause, see
+    // `Config::failure_ratio_threshold`. Pausing starts the window afresh.
+    fn failing_too_often(&self) -> bool {
+        let threshold = match self.config.failure_ratio_threshold {
+            Some(threshold) => threshold,
+            None => return false,
+        };
+        let mut outcomes = self.api_outcomes.lock().unwrap();
+        let (ratio, calls) =
+            match outcomes.failure_ratio(Instant::now(), self.config.failure_ratio_min_calls) {
+                Some(ratio) => ratio,
+                None => return false,
+            };
+        if ratio <= threshold {
+            debug!(
+                "[{:?} {}] {:.1}% of the last {} calls failed",
+                self.queue_type,
+                self.region,
+                100.0 * ratio,
+                calls
+            );
+            return false;
         }
-        let (avg_elo, avg_elo_str) = if num_ranked >= 1 {
-            (sum / num_ranked, team_avg_rank_str(&ranks_vec))
+        warn!(
+            "[{:?} {}] {:.1}% of the last {} calls failed (threshold {:.1}%), pausing for {:?}",
+            self.queue_type,
+            self.region,
+            100.0 * ratio,
+            calls,
+            100.0 * threshold,
+            self.config.failure_ratio_cooldown
+        );
+        outcomes.clear();
+        true
+    }
+
+    // Cutoffs labelling apex lobbies, live ones if enabled and known
+    fn apex_cutoffs(&self) -> Option<ApexCutoffs> {
+        let live = if self.config.live_apex_cutoffs {
+            let apex_cutoffs = self.apex_cutoffs.lock().unwrap();
+            apex_cutoffs
+                .get(&self.region)
+                .and_then(|live| live.cutoffs())
         } else {
-            (i32::MIN, "UNRANKED".to_string())
+            None
         };
-        Ok((ret, avg_elo, avg_elo_str))
+        live.or(self.config.apex_lp_cutoffs)
+    }
+
+    // Retry a participant lookup on retryable API errors, see `Config::participant_retries`
+    async fn participant_retry<T, F, Fut>(&self, mut call: F) -> error::Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: std::future::Future<Output = error::Result<T>>,
+    {
+        let mut delay = self.config.participant_retry_backoff;
+        for _ in 0..self.config.participant_retries {
+            let e = match call().await {
+                // Already retried as far as `Config::db_error_policy` allows by `db_call`
+                Err(e @ CrawlerError::DbTransient(..)) => return Err(e),
+                Err(e) => e,
+                ret => return ret,
+            };
+            let wait = match error::retry_wait(&e, delay, self.config.max_retry_after) {
+                Some(wait) => wait,
+                None => return Err(e),
+            };
+            incr(&self.metrics.participant_retries);
+            debug!("Retrying participant lookup in {:?}: {}", wait, e);
+            sleep(wait).await;
+            delay *= 2;
+        }
+        call().await
     }
 
     // puuid -> summoner doc
-    async fn tft_summoner_v1(&self, puuid: &str) -> anyhow::Result<Document> {
-        let summoners = self.db.collection(SUMMONERS_COLLECTION_NAME);
+    // A cached doc created more than `max_age` ago is refetched and replaced
+    async fn tft_summoner_v1(
+        &self,
+        puuid: &str,
+        max_age: Option<std::time::Duration>,
+    ) -> error::Result<Document> {
+        let summoners = self.db.collection(&self.summoners_collection);
         let filter = doc! {"_id": puuid};
 
-        let find_options = FindOneOptions::default();
         let current_timestamp = Utc::now();
-        let doc = match summoners
-            .find_one(filter, find_options)
-            .await
-            .map_err(|_| anyhow::Error::msg("Error find_one"))?
-        {
+        let summoners_read = self
+            .db_read
+            .collection::<Document>(&self.summoners_collection);
+        let cached = self
+            .db_call("Error find_one", || {
+                summoners_read.find_one(filter.clone(), FindOneOptions::default())
+            })
+            .await?;
+        let stale = cached.is_some();
+        let doc = match cached.filter(|doc| cache::is_fresh(doc, current_timestamp, max_age)) {
             None => {
-                let tft_summoner = self
-                    .api
-                    .tft_summoner_v1()
-                    .get_by_puuid(self.region, puuid)
-                    .await?;
+                let allowed = self.summoner_call_budget.try_take(|| {
+                    warn!(
+                        "[{:?} {}] Summoner call cap reached, deferring matches to the next cycle",
+                        self.queue_type, self.region
+                    )
+                });
+                if !allowed {
+                    return Err(CrawlerError::Deferred("tft_summoner_v1.get_by_puuid"));
+                }
+                let tft_summoner = {
+                    let _permit = self
+                        .endpoints
+                        .acquire(Endpoint::Summoner, self.region)
+                        .await;
+                    self.api
+                        .tft_summoner_v1()
+                        .get_by_puuid(self.region, puuid)
+                        .await
+                        .map_err(|e| CrawlerError::api("Error tft_summoner_v1.get_by_puuid", e))?
+                };
                 let mut bson: Bson = serde_json::to_value(tft_summoner)?.try_into()?;
                 let doc = bson
                     .as_document_mut()
-                    .ok_or_else(|| anyhow::Error::msg("BSON is not a doc"))?;
+                    .ok_or_else(|| CrawlerError::serialization("BSON is not a doc"))?;
                 doc.insert("_id", Bson::String(puuid.to_string()));
-                doc.insert("_documentCreated", Bson::DateTime(current_timestamp));
+                self.insert_datetime(doc, "_documentCreated", current_timestamp);
                 // Don't expire this document for 60 days
                 let expire = current_timestamp + Duration::days(30);
-                doc.insert("_documentExpire", Bson::DateTime(expire));
-                summoners
-                    .insert_one(doc.clone(), None)
-                    .await
-                    .map_err(|_| anyhow::Error::msg("Error inserting document"))?;
-                // debug!("summoner (new)");
+                self.insert_datetime(doc, "_documentExpire", expire);
+                if stale {
+                    self.replace_one(&summoners, doc).await?;
+                } else {
+                    self.insert_one(&summoners, doc).await?;
+                }
+                incr(&self.metrics.summoner_cache_misses);
                 doc.clone()
             }
             Some(doc) => {
-                // debug!("summoner (cached)");
+                incr(&self.metrics.summoner_cache_hits);
                 doc
             }
         };
@@ -388,55 +1979,57 @@ impl Main {
     }
 
     // summonerId -> league doc
-    async fn tft_league_v1(&self, summoner_id: &str) -> anyhow::Result<Document> {
-        let leagues = self.db.collection(LEAGUES_COLLECTION_NAME);
+    // A cached doc created more than `max_age` ago is refetched and replaced
+    async fn tft_league_v1(
+        &self,
+        summoner_id: &str,
+        max_age: Option<std::time::Duration>,
+    ) -> error::Result<Document> {
+        let leagues = self.db.collection(&self.leagues_collection);
         let filter = doc! {"_id": summoner_id};
 
-        let find_options = FindOneOptions::default();
         let current_timestamp = Utc::now();
-        let doc = match leagues
-            .find_one(filter, find_options)
-            .await
-            .map_err(|_| anyhow::Error::msg("Error find one"))?
-        {
+        let leagues_read = self
+            .db_read
+            .collection::<Document>(&self.leagues_collection);
+        let cached = self
+            .db_call("Error find_one", || {
+                leagues_read.find_one(filter.clone(), FindOneOptions::default())
+            })
+            .await?;
+        let stale = cached.is_some();
+        let doc = match cached.filter(|doc| cache::is_fresh(doc, current_timestamp, max_age)) {
             None => {
-                let tft_league_vec = self
-                    .api
-                    .tft_league_v1()
-                    .get_league_entries_for_summoner(self.region, summoner_id)
-                    .await?;
+                let tft_league_vec = {
+                    let _permit = self.endpoints.acquire(Endpoint::League, self.region).await;
+                    self.api
+                        .tft_league_v1()
+                        .get_league_entries_for_summoner(self.region, summoner_id)
+                        .await
+                        .map_err(|e| {
+                            CrawlerError::api(
+                                "Error tft_league_v1.get_league_entries_for_summoner",
+                                e,
+                            )
+                        })?
+                };
                 #[allow(deprecated)] // riven::consts::QueueType::RANKED_TFT is marked deprecated
                 let tft_league_opt = tft_league_vec
                     .iter()
                     .find(|item| item.queue_type == riven::consts::QueueType::RANKED_TFT);
-                let mut doc = if let Some(tft_league) = tft_league_opt {
-                    // debug!("leagues (found)");
-                    let mut bson: Bson = serde_json::to_value(tft_league)?.try_into()?;
-                    let doc = bson
-                        .as_document_mut()
-                        .ok_or_else(|| anyhow::Error::msg("BSON is not a doc"))?;
-                    doc.insert("_status", Bson::String("ranked".to_string()));
-                    doc.clone()
+                let doc = self
+                    .league_doc(summoner_id, tft_league_opt, current_timestamp)
+                    .await?;
+                if stale {
+                    self.replace_one(&leagues, &doc).await?;
                 } else {
-                    // debug!("leagues (not found)");
-                    let mut doc = doc! {};
-                    doc.insert("_status", Bson::String("unranked".to_string()));
-                    doc
-                };
-                doc.insert("_id", Bson::String(summoner_id.to_string()));
-                doc.insert("_documentCreated", Bson::DateTime(current_timestamp));
-                // Don't expire this document for 1 days (or less if high ranked)
-                let expire =
-                    current_timestamp + self.variable_tft_league_v1_expiry_duration(&doc).await;
-                doc.insert("_documentExpire", Bson::DateTime(expire));
-                leagues
-                    .insert_one(doc.clone(), None)
-                    .await
-                    .map_err(|_| anyhow::Error::msg("Error inserting document"))?;
+                    self.insert_one(&leagues, &doc).await?;
+                }
+                incr(&self.metrics.league_cache_misses);
                 doc
             }
             Some(doc) => {
-                // debug!("leagues (cached)");
+                incr(&self.metrics.league_cache_hits);
                 doc
             }
         };
@@ -444,6 +2037,294 @@ impl Main {
         Ok(doc)
     }
 
+    // League cache document for a summoner, from their ranked TFT entry if they have one
+    async fn league_doc(
+        &self,
+        summoner_id: &str,
+        tft_league_opt: Option<&LeagueEntry>,
+        current_timestamp: DateTime<Utc>,
+    ) -> error::Result<Document> {
+        let mut doc = if let Some(tft_league) = tft_league_opt {
+            // debug!("leagues (found)");
+            let mut bson: Bson = serde_json::to_value(tft_league)?.try_into()?;
+            let doc = bson
+                .as_document_mut()
+                .ok_or_else(|| CrawlerError::serialization("BSON is not a doc"))?;
+            doc.insert("_status", Bson::String("ranked".to_string()));
+            doc.clone()
+        } else {
+            // debug!("leagues (not found)");
+            let mut doc = doc! {};
+            doc.insert("_status", Bson::String("unranked".to_string()));
+            doc
+        };
+        doc.insert("_id", Bson::String(summoner_id.to_string()));
+        self.insert_datetime(&mut doc, "_documentCreated", current_timestamp);
+        // Don't expire this document for 1 days (or less if high ranked)
+        let expire = current_timestamp + self.variable_tft_league_v1_expiry_duration(&doc).await;
+        self.insert_datetime(&mut doc, "_documentExpire", expire);
+        Ok(doc)
+    }
+
+    // Replace this tier and division's LP distribution in
+    // `RANK_DISTRIBUTIONS_COLLECTION_NAME`. Failures are only logged.
+    async fn record_rank_distribution(&self, tier: &str, division: &str, lps: &[i32]) {
+        let mut dist =
+            match rank_distribution::distribution(self.region.as_ref(), tier, division, lps) {
+                Some(dist) => dist,
+                None => return,
+            };
+        self.insert_datetime(&mut dist, "_documentCreated", Utc::now());
+        let collection = self
+            .db
+            .collection::<Document>(&self.suffixed(RANK_DISTRIBUTIONS_COLLECTION_NAME));
+        let _permit = self.write_permit().await;
+        let ret = self
+            .db_call("Error writing rank distribution", || {
+                collection.replace_one(
+                    doc! {"_id": dist.get_str("_id").unwrap_or_default()},
+                    dist.clone(),
+                    ReplaceOptions::builder().upsert(true).build(),
+                )
+            })
+            .await;
+        if let Err(e) = ret {
+            error!("{}", e);
+        }
+    }
+
+    // Prime the league cache with the rankings seen on a ladder page scan, in batches
+    // of `ladder::UPSERT_BATCH_SIZE` rather than one round-trip per entry
+    async fn persist_ladder_rankings(
+        &self,
+        tier: &str,
+        division: &str,
+        entries: &[LeagueEntry],
+    ) -> error::Result<()> {
+        let current_timestamp = Utc::now();
+        let mut docs = Vec::with_capacity(entries.len());
+        for entry in entries {
+            docs.push(
+                self.league_doc(&entry.summoner_id, Some(entry), current_timestamp)
+                    .await?,
+            );
+        }
+        if self.config.lp_gain_tracking {
+            // Before the upsert replaces the previous rankings
+            self.record_lp_changes(&docs, current_timestamp).await;
+        }
+        let commands =
+            ladder::upsert_commands(&self.leagues_collection, &docs, ladder::UPSERT_BATCH_SIZE);
+        let num_batches = commands.len();
+        for (i, command) in commands.into_iter().enumerate() {
+            let _permit = self.write_permit().await;
+            let ret = self
+                .db_call("Error upserting ladder rankings", || {
+                    self.db.run_command(command.clone(), None)
+                })
+                .await?;
+            info!(
+                "[{}] Ladder {} {} batch {}/{}: {} league entries written",
+                self.region,
+                tier,
+                division,
+                i + 1,
+                num_batches,
+                ret.get_i32("n").unwrap_or(0)
+            );
+        }
+        Ok(())
+    }
+
+    // Compare freshly scanned rankings with the cached ones and insert the changes into
+    // `LP_CHANGES_COLLECTION_NAME`. Failures are only logged.
+    async fn record_lp_changes(&self, docs: &[Document], current_timestamp: DateTime<Utc>) {
+        let leagues = self
+            .db_read
+            .collection::<Document>(&self.leagues_collection);
+        let mut changes = vec![];
+        for batch in docs.chunks(ladder::UPSERT_BATCH_SIZE) {
+            let ids: Vec<&str> = batch
+                .iter()
+                .filter_map(|doc| doc.get_str("_id").ok())
+                .collect();
+            let filter = doc! {"_id": {"$in": ids}};
+            let previous = async {
+                let cursor = self
+                    .db_call("Error reading previous rankings", || {
+                        leagues.find(filter.clone(), None)
+                    })
+                    .await?;
+                let previous: Vec<_> = cursor.collect().await;
+                previous
+                    .into_iter()
+                    .collect::<mongodb::error::Result<Vec<Document>>>()
+                    .map_err(|e| CrawlerError::db("Error reading previous rankings", e))
+            };
+            let previous: HashMap<String, Document> = match previous.await {
+                Ok(previous) => previous
+                    .into_iter()
+                    .filter_map(|doc| Some((doc.get_str("_id").ok()?.to_string(), doc)))
+                    .collect(),
+                Err(e) => return error!("[{}] {}", self.region, e),
+            };
+            for doc in batch {
+                let summoner_id = doc.get_str("_id").unwrap_or_default();
+                let previous_doc = match previous.get(summoner_id) {
+                    Some(previous_doc) => previous_doc,
+                    None => continue,
+                };
+                let standings = (
+                    lp_change::Standing::from_league_doc(previous_doc),
+                    lp_change::Standing::from_league_doc(doc),
+                );
+                let change = match standings {
+                    (Some(before), Some(after)) => {
+                        lp_change::lp_change(self.region.as_ref(), summoner_id, before, after)
+                    }
+                    _ => None,
+                };
+                if let Some(mut change) = change {
+                    change.insert(
+                        "_id",
+                        format!("{}-{}", summoner_id, current_timestamp.timestamp_millis()),
+                    );
+                    if let Ok(previous_at) = previous_doc.get_datetime("_documentCreated") {
+                        self.insert_datetime(&mut change, "_previousSnapshot", *previous_at);
+                    }
+                    self.insert_datetime(&mut change, "_documentCreated", current_timestamp);
+                    let expire =
+                        retention_expiry(current_timestamp, self.config.lp_changes_retention);
+                    self.insert_datetime(&mut change, "_documentExpire", expire);
+                    changes.push(change);
+                }
+            }
+        }
+        if changes.is_empty() {
+            return;
+        }
+        let collection = self
+            .db
+            .collection::<Document>(&self.suffixed(LP_CHANGES_COLLECTION_NAME));
+        let _permit = self.write_permit().await;
+        match self
+            .db_call("Error inserting LP changes", || {
+                collection.insert_many(changes.clone(), None)
+            })
+            .await
+        {
+            Ok(_) => debug!("[{}] {} LP changes recorded", self.region, changes.len()),
+            Err(e) => error!("[{}] {}", self.region, e),
+        }
+    }
+
+    // Insert a new document, returns false if one with the same _id was stored meanwhile
+    // (another task got there first, or a stale read replica made it look missing)
+    async fn insert_one(
+        &self,
+        collection: &mongodb::Collection<Document>,
+        doc: &Document,
+    ) -> error::Result<bool> {
+        let _permit = self.write_permit().await;
+        match self
+            .db_call("Error inserting document", || {
+                collection.insert_one(doc.clone(), None)
+            })
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(e) if e.is_duplicate_key() => {
+                debug!("Already stored: {}", doc.get("_id").unwrap_or(&Bson::Null));
+                incr(&self.metrics.duplicate_inserts);
+                Ok(false)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    // Overwrite the document with the same _id, or insert it if there is none
+    async fn replace_one(
+        &self,
+        collection: &mongodb::Collection<Document>,
+        doc: &Document,
+    ) -> error::Result<()> {
+        let _permit = self.write_permit().await;
+        let filter = doc! {"_id": doc.get("_id").cloned().unwrap_or(Bson::Null)};
+        self.db_call("Error replacing document", || {
+            let options = ReplaceOptions::builder().upsert(true).build();
+            collection.replace_one(filter.clone(), doc.clone(), options)
+        })
+        .await?;
+        Ok(())
+    }
+
+    // Run a MongoDB call under the configured `Config::db_error_policy`
+    async fn db_call<T, F, Fut>(&self, context: &'static str, call: F) -> error::Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: std::future::Future<Output = mongodb::error::Result<T>>,
+    {
+        db_policy::with_policy(
+            self.config.db_error_policy,
+            context,
+            || self.take_retry(),
+            call,
+        )
+        .await
+    }
+
+    // Whether the cycle's retry budget allows another retry
+    fn take_retry(&self) -> bool {
+        self.retry_budget.try_take(|| {
+            warn!(
+                "[{:?} {}] Cycle retry budget of {} exhausted, skipping instead of retrying until the next cycle",
+                self.queue_type, self.region, self.config.cycle_retry_budget
+            )
+        })
+    }
+
+    // 20s plus up to 5s of jitter, so region tasks that failed together don't retry together
+    fn retry_delay(&self) -> std::time::Duration {
+        let jitter = self.rng.lock().unwrap().gen_range(0..5000);
+        std::time::Duration::from_millis(20_000 + jitter)
+    }
+
+    // Wait for this platform's turn at a match-v1 slot of its cluster, if they are limited
+    async fn match_slot(&self) -> Option<FairSharePermit<'_>> {
+        incr(&self.metrics.match_calls);
+        match self.match_shares.get(&self.region_major) {
+            Some(share) => Some(share.acquire(self.region).await),
+            None => None,
+        }
+    }
+
+    // Wait for a slot in the shared write semaphore, hold the permit for the duration of the write
+    async fn write_permit(&self) -> SemaphorePermit<'_> {
+        let start = Instant::now();
+        let permit = self
+            .write_semaphore
+            .acquire()
+            .await
+            .expect("Write semaphore closed");
+        let waited = start.elapsed();
+        if waited > SLOW_WRITE_PERMIT {
+            incr(&self.metrics.slow_write_permits);
+            warn!(
+                "[{:?} {}] Waited {:?} for a write slot, MongoDB may be slow",
+                self.queue_type, self.region, waited
+            );
+        }
+        permit
+    }
+
+    // Insert a BSON DateTime, plus an ISO-8601 string copy if configured
+    fn insert_datetime(&self, doc: &mut Document, key: &str, value: DateTime<Utc>) {
+        doc.insert(key, Bson::DateTime(value));
+        if self.config.store_iso_timestamps {
+            doc.insert(format!("{}Iso", key), Bson::String(value.to_rfc3339()));
+        }
+    }
+
     async fn variable_tft_league_v1_expiry_duration(&self, league_doc: &Document) -> Duration {
         let tft_tier = league_doc.get_str("tier").unwrap_or("unranked");
         match tft_tier {
@@ -454,6 +2335,41 @@ impl Main {
         }
     }
 
+    // Reason to skip this cycle from tft-status-v1, cached briefly.
+    // A failed status check is logged and doesn't hold up crawling.
+    async fn get_platform_status(&self) -> Option<String> {
+        if let Some((checked, reason)) = &*self.platform_status.lock().unwrap() {
+            if checked.elapsed() < PLATFORM_STATUS_CACHE_DURATION {
+                return reason.clone();
+            }
+        }
+        let riot_url = format!(
+            "https://{}.api.riotgames.com/tft/status/v1/platform-data",
+            self.region.to_string().to_lowercase()
+        );
+        let platform_data = async {
+            // In a header, so errors (which include the URL) don't log the key
+            reqwest::Client::new()
+                .get(&riot_url)
+                .header("X-Riot-Token", &self.api_key)
+                .send()
+                .await?
+                .error_for_status()?
+                .json::<serde_json::Value>()
+                .await
+        }
+        .await;
+        let reason = match platform_data {
+            Ok(platform_data) => blocking_status(&platform_data),
+            Err(e) => {
+                error!("Error tft_status_v1 {}: {}", self.region, e);
+                None
+            }
+        };
+        *self.platform_status.lock().unwrap() = Some((Instant::now(), reason.clone()));
+        reason
+    }
+
     async fn get_top_players(&self) -> Vec<String> {
         match self.queue_type {
             TftQueue::Ranked => self.get_top_players_ranked().await,
@@ -465,40 +2381,36 @@ impl Main {
     async fn get_top_players_ranked(&self) -> Vec<String> {
         let mut ret = Vec::new();
 
-        // TODO: make divisions configurable
-        for (tier, division) in &[
-            ("CHALLENGER", "I"),
-            ("GRANDMASTER", "I"),
-            ("MASTER", "I"),
-            ("DIAMOND", "I"),
-            ("DIAMOND", "II"),
-            ("DIAMOND", "III"),
-            // ("DIAMOND", "IV"),
-            // ("PLATINUM", "I"),
-            // ("PLATINUM", "II"),
-            // ("PLATINUM", "III"),
-            // ("PLATINUM", "IV"),
-            // ("GOLD", "I"),
-            // ("GOLD", "II"),
-            // ("GOLD", "III"),
-            // ("GOLD", "IV"),
-            // ("SILVER", "I"),
-            // ("SILVER", "II"),
-            // ("SILVER", "III"),
-        ] {
+        for (tier, division) in &self.config.scan.divisions {
             let mut entries = {
                 let mut x = self.get_league_entries(tier, division).await;
                 let mut num_failures: i32 = 0;
                 while let Err(e) = &x {
                     error!("Error get_league_entries {} {}: {}", tier, division, e);
                     num_failures += 1;
-                    if num_failures == 5 {
-                        break;
-                    }
-                    sleep(tokio::time::Duration::from_secs(20)).await;
+                    // No point retrying e.g. a 403 from a bad key
+                    let wait =
+                        error::retry_wait(e, self.retry_delay(), self.config.max_retry_after);
+                    let wait = match wait {
+                        Some(wait) if num_failures < 5 && self.take_retry() => wait,
+                        _ => break,
+                    };
+                    sleep(wait).await;
                     x = self.get_league_entries(tier, division).await;
                 }
-                x.expect("Too many failures")
+                match x {
+                    Ok(entries) => entries,
+                    // Out of attempts, retry budget or patience for a long Retry-After;
+                    // the division is scanned again next cycle
+                    Err(e) if error::is_retryable(&e) => {
+                        warn!(
+                            "[{}] Skipping {} {} this cycle: {}",
+                            self.region, tier, division, e
+                        );
+                        continue;
+                    }
+                    Err(e) => panic!("Too many failures: {}", e),
+                }
             };
             info!("{} {} {}\t{}", self.region, tier, division, entries.len());
             ret.append(&mut entries);
@@ -512,16 +2424,12 @@ impl Main {
             self.region.to_string().to_lowercase()
         );
         info!("{}", riot_url);
-        let body = reqwest::get(&format!(
-            "{}?api_key={}",
-            &riot_url,
-            std::env::var("RGAPI_KEY").unwrap()
-        ))
-        .await
-        .unwrap()
-        .text()
-        .await
-        .unwrap();
+        let body = reqwest::get(&format!("{}?api_key={}", &riot_url, self.api_key))
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
         info!("{}", body);
         let val: serde_json::Value = serde_json::from_str(&body).unwrap();
         let vec = val.as_array().unwrap();
@@ -534,69 +2442,89 @@ impl Main {
     }
 
     // Returns a list of summoner ids
-    async fn get_league_entries(&self, tier: &str, division: &str) -> anyhow::Result<Vec<String>> {
+    async fn get_league_entries(&self, tier: &str, division: &str) -> error::Result<Vec<String>> {
         // non-paginated cases
+        let permit = self.endpoints.acquire(Endpoint::League, self.region).await;
         let x: Option<LeagueList> = match tier {
             "CHALLENGER" => Some(
                 self.api
                     .tft_league_v1()
                     .get_challenger_league(self.region)
-                    .await?,
+                    .await
+                    .map_err(|e| CrawlerError::api("Error get_challenger_league", e))?,
             ),
             "GRANDMASTER" => Some(
                 self.api
                     .tft_league_v1()
                     .get_grandmaster_league(self.region)
-                    .await?,
+                    .await
+                    .map_err(|e| CrawlerError::api("Error get_grandmaster_league", e))?,
             ),
             "MASTER" => Some(
                 self.api
                     .tft_league_v1()
                     .get_master_league(self.region)
-                    .await?,
+                    .await
+                    .map_err(|e| CrawlerError::api("Error get_master_league", e))?,
             ),
             _ => None,
         };
+        drop(permit);
         if let Some(ll) = x {
-            let summoner_id_list = ll.entries.iter().map(|y| y.summoner_id.clone()).collect();
-            return Ok(summoner_id_list);
+            let lps: Vec<i32> = ll.entries.iter().map(|y| y.league_points).collect();
+            if self.config.live_apex_cutoffs {
+                let mut apex_cutoffs = self.apex_cutoffs.lock().unwrap();
+                apex_cutoffs
+                    .entry(self.region)
+                    .or_default()
+                    .observe(tier, &lps);
+            }
+            if self.config.rank_distributions {
+                self.record_rank_distribution(tier, division, &lps).await;
+            }
+            return Ok(scan::apex_summoner_ids(&ll));
         }
 
         // paginated cases
-        let mut page = 1;
-        let mut ret = Vec::new();
-        loop {
-            let x = self
-                .api
+        // Here we get the list of entries, which we distill down to a list of summoner ids
+        let entries = fetch_all_pages(self.config.league_page_lookahead, |page| async move {
+            let _permit = self.endpoints.acquire(Endpoint::League, self.region).await;
+            self.api
                 .tft_league_v1()
                 .get_league_entries(self.region, tier, division, Some(page))
                 .await
-                .map_err(|_| anyhow::Error::msg("Error get_league_entries"))?;
-            if x.is_empty() {
-                break;
-            };
+                .map_err(|e| CrawlerError::api("Error get_league_entries", e))
+        })
+        .await?;
+        if self.config.persist_ladder_rankings {
+            // Only primes the league cache, the scan goes on without it
+            if let Err(e) = self.persist_ladder_rankings(tier, division, &entries).await {
+                error!("Error persisting {} {} rankings: {}", tier, division, e);
+            }
+        }
+        if self.config.rank_distributions {
+            let lps: Vec<i32> = entries.iter().filter_map(|y| y.league_points).collect();
+            self.record_rank_distribution(tier, division, &lps).await;
+        }
+        let mut ret = Vec::new();
+        for y in entries {
+            ret.push(y.summoner_id.clone());
+            /*
+            globally we know:
+                tier = "PLATINUM"
+                division="I"
+
+            for this specific entry:
+                y.leaguePoints="266"
+                y.rank="I" (same as above I hope)
 
-            // Here we have the list of entries, which we distill down to a list of summoner ids
-            for y in x {
-                ret.push(y.summoner_id.clone());
-                /*
-                globally we know:
-                    tier = "PLATINUM"
-                    division="I"
-
-                for this specific entry:
-                    y.leaguePoints="266"
-                    y.rank="I" (same as above I hope)
-
-                identity:
-                    y.summonerId
-                    y.summonerName
-                    NOT: puuid or accountId
-                */
-                // We may want to use this ranking to update DB knowledge about this player
-                // (it is indexed on summonerId)
-            }
-            page += 1;
+            identity:
+                y.summonerId
+                y.summonerName
+                NOT: puuid or accountId
+            */
+            // See `Config::persist_ladder_rankings` for using this ranking to update
+            // DB knowledge about this player (it is indexed on summonerId)
         }
         Ok(ret)
     }
@@ -0,0 +1,112 @@
+use chrono::{DateTime, Utc};
+
+/// Numeric part of a match id, e.g. 5234567890 for "EUW1_5234567890"
+pub fn match_number(id: &str) -> Option<u64> {
+    id.rsplit('_').next()?.parse().ok()
+}
+
+/// Newest match a region task has fetched. Match ids on a platform are handed out in
+/// increasing order, so a match numbered at or above it was played no earlier, which
+/// bounds the age of a match we failed to fetch.
+#[derive(Default)]
+pub struct NewestMatch {
+    newest: Option<(u64, DateTime<Utc>)>,
+}
+
+impl NewestMatch {
+    pub fn observe(&mut self, id: &str, played: DateTime<Utc>) {
+        if let Some(number) = match_number(id) {
+            if self.newest.is_none_or(|(newest, _)| number > newest) {
+                self.newest = Some((number, played));
+            }
+        }
+    }
+
+    /// Earliest `id` could have been played, if it is known to be at least as new as a
+    /// fetched match
+    pub fn played_no_earlier_than(&self, id: &str) -> Option<DateTime<Utc>> {
+        let (newest, played) = self.newest?;
+        if match_number(id)? >= newest {
+            Some(played)
+        } else {
+            None
+        }
+    }
+}
+
+/// Newest fetched match that was already older than `Config::max_match_age`. A match
+/// numbered at or below it was played no later, so is too old as well and needn't be
+/// fetched. Match histories list newest first, so once one of a player's matches is
+/// found too old, the rest of their list is skipped without fetching.
+#[derive(Default)]
+pub struct TooOld {
+    newest: Option<u64>,
+}
+
+impl TooOld {
+    /// Record a fetched match, if it was played before `cutoff`
+    pub fn observe(&mut self, id: &str, played: DateTime<Utc>, cutoff: DateTime<Utc>) {
+        if played >= cutoff {
+            return;
+        }
+        if let Some(number) = match_number(id) {
+            if self.newest.is_none_or(|newest| number > newest) {
+                self.newest = Some(number);
+            }
+        }
+    }
+
+    /// Whether `id` is known to be too old without fetching it. The cutoff only moves
+    /// forward, so a match once too old stays too old.
+    pub fn contains(&self, id: &str) -> bool {
+        match (self.newest, match_number(id)) {
+            (Some(newest), Some(number)) => number <= newest,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_match_number() {
+        assert_eq!(match_number("EUW1_5234567890"), Some(5234567890));
+        assert_eq!(match_number("5234567890"), Some(5234567890));
+        assert_eq!(match_number("EUW1_"), None);
+    }
+
+    #[test]
+    fn test_newest_match() {
+        let played = Utc.ymd(2021, 3, 1).and_hms(12, 0, 0);
+        let mut newest = NewestMatch::default();
+        assert_eq!(newest.played_no_earlier_than("EUW1_100"), None);
+        newest.observe("EUW1_100", played);
+        // Older ids don't move it back
+        newest.observe("EUW1_90", played - chrono::Duration::hours(1));
+        assert_eq!(newest.played_no_earlier_than("EUW1_100"), Some(played));
+        assert_eq!(newest.played_no_earlier_than("EUW1_120"), Some(played));
+        assert_eq!(newest.played_no_earlier_than("EUW1_95"), None);
+    }
+
+    #[test]
+    fn test_too_old() {
+        let cutoff = Utc.ymd(2021, 3, 1).and_hms(12, 0, 0);
+        let mut too_old = TooOld::default();
+        assert!(!too_old.contains("EUW1_100"));
+        // Recent enough, bounds nothing
+        too_old.observe("EUW1_200", cutoff, cutoff);
+        assert!(!too_old.contains("EUW1_100"));
+
+        too_old.observe("EUW1_100", cutoff - chrono::Duration::days(8), cutoff);
+        assert!(too_old.contains("EUW1_100"));
+        assert!(too_old.contains("EUW1_42"));
+        assert!(!too_old.contains("EUW1_101"));
+        // An older match doesn't move it back
+        too_old.observe("EUW1_50", cutoff - chrono::Duration::days(30), cutoff);
+        assert!(too_old.contains("EUW1_99"));
+        assert!(!too_old.contains("EUW1_"));
+    }
+}
@@ -0,0 +1,108 @@
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+/// Ceiling on the new matches a region task stores in one cycle, see
+/// `Config::max_new_matches_per_cycle`. A slot is reserved before a match is fetched and
+/// given back unless the match ends up stored, so concurrent summoners can't overshoot.
+pub struct MatchCap {
+    used: AtomicU32,
+    limit: AtomicU32,
+    reached: AtomicBool,
+}
+
+/// A reserved slot, released on drop unless kept
+pub struct Reservation<'a> {
+    cap: &'a MatchCap,
+    kept: bool,
+}
+
+impl Reservation<'_> {
+    /// The match was stored, the slot stays used
+    pub fn keep(mut self) {
+        self.kept = true;
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        if !self.kept {
+            self.cap.used.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Default for MatchCap {
+    fn default() -> MatchCap {
+        MatchCap {
+            used: AtomicU32::new(0),
+            limit: AtomicU32::new(u32::MAX),
+            reached: AtomicBool::new(false),
+        }
+    }
+}
+
+impl MatchCap {
+    /// Start a new cycle allowing `limit` new matches
+    pub fn reset(&self, limit: u32) {
+        self.used.store(0, Ordering::Relaxed);
+        self.limit.store(limit, Ordering::Relaxed);
+        self.reached.store(false, Ordering::Relaxed);
+    }
+
+    /// Reserve a slot if any are left. Otherwise `on_reached` is called, on the first
+    /// refusal of the cycle only.
+    pub fn reserve(&self, on_reached: impl FnOnce()) -> Option<Reservation<'_>> {
+        let limit = self.limit.load(Ordering::Relaxed);
+        let reserved = self
+            .used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                if n < limit {
+                    Some(n + 1)
+                } else {
+                    None
+                }
+            })
+            .is_ok();
+        if reserved {
+            return Some(Reservation {
+                cap: self,
+                kept: false,
+            });
+        }
+        if !self.reached.swap(true, Ordering::Relaxed) {
+            on_reached();
+        }
+        None
+    }
+
+    /// Whether a reservation has been refused this cycle
+    pub fn reached(&self) -> bool {
+        self.reached.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_match_cap() {
+        let cap = MatchCap::default();
+        let mut logged = 0;
+        cap.reset(2);
+        cap.reserve(|| logged += 1).unwrap().keep();
+        // Not stored, so the slot comes back
+        drop(cap.reserve(|| logged += 1).unwrap());
+        let second = cap.reserve(|| logged += 1).unwrap();
+        assert!(!cap.reached());
+        assert!(cap.reserve(|| logged += 1).is_none());
+        assert!(cap.reserve(|| logged += 1).is_none());
+        assert!(cap.reached());
+        assert_eq!(logged, 1);
+        second.keep();
+
+        // Next cycle
+        cap.reset(1);
+        assert!(!cap.reached());
+        assert!(cap.reserve(|| logged += 1).is_some());
+    }
+}
@@ -0,0 +1,484 @@
+use mongodb::bson::{doc, Bson, Document};
+use riven::models::tft_match_v1::Match;
+use sha2::{Digest, Sha256};
+use std::convert::TryInto;
+use std::str::FromStr;
+
+use crate::participant_info::Enrichment;
+use crate::patch;
+
+/// Version of the derived fields, stored as `_schemaVersion`. Bump when they change,
+/// so `Config::migrate_stored_matches` recomputes them on older documents.
+pub const SCHEMA_VERSION: i32 = 2;
+
+/// Size of the document once encoded as BSON, which is what MongoDB's 16MB limit applies to
+pub fn document_size(doc: &Document) -> usize {
+    let mut buf = Vec::new();
+    match doc.to_writer(&mut buf) {
+        Ok(()) => buf.len(),
+        // Can't be encoded at all, the insert will report why
+        Err(_) => 0,
+    }
+}
+
+/// The raw match as a document. `direct` serializes straight to BSON, skipping the
+/// intermediate `serde_json::Value`, and falls back to going through JSON if that fails
+/// (the BSON serializer rejects unsigned integers). See `Config::direct_bson`.
+pub fn match_document(game: &Match, direct: bool) -> Result<Document, String> {
+    if direct {
+        match mongodb::bson::to_document(game) {
+            Ok(doc) => return Ok(doc),
+            Err(e) => log::debug!("Direct BSON conversion failed, going through JSON: {}", e),
+        }
+    }
+    let json = serde_json::to_value(game).map_err(|e| e.to_string())?;
+    match json.try_into() {
+        Ok(Bson::Document(doc)) => Ok(doc),
+        Ok(_) => Err("BSON is not a doc".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Fields derived from the match and its participants, recomputable from the raw match
+pub fn derived_fields(game: &Match, enrichment: Enrichment) -> Document {
+    let mut doc = doc! {
+        "_aggregatedPlayerInfo": enrichment.player_data,
+        "_avgElo": enrichment.avg_elo,
+        "_avgEloText": enrichment.avg_elo_text,
+    };
+    doc.extend(match_fields(game));
+    if enrichment.partial {
+        doc.insert("_partialEnrichment", true);
+    }
+    if let Some(upset_score) = enrichment.upset_score {
+        doc.insert("_upsetScore", upset_score);
+    }
+    if let Some(placements) = enrichment.placements {
+        doc.insert("_placements", placements);
+    }
+    if let Some(fingerprint) = enrichment.meta_fingerprint {
+        doc.insert("_metaFingerprint", fingerprint);
+    }
+    if let Some(high_variance) = enrichment.high_variance {
+        doc.insert("_highVariance", high_variance);
+    }
+    doc.insert("_schemaVersion", SCHEMA_VERSION);
+    doc
+}
+
+/// The derived fields that come from the match alone, without participant lookups
+pub fn match_fields(game: &Match) -> Document {
+    doc! {
+        "_tftSet": game.info.tft_set_number,
+        "_patch": patch::parse_patch(&game.info.game_version).map_or(Bson::Null, Bson::String),
+        "_lobbyHash": lobby_hash(&game.metadata.participants),
+    }
+}
+
+/// `_lobbyHash`: first 32 hex chars (128 bits) of the SHA-256 of the sorted participant
+/// puuids, equal for matches played by exactly the same 8 players. Indexed, see
+/// `lobby_index_command`.
+pub fn lobby_hash(puuids: &[String]) -> String {
+    let mut puuids: Vec<&str> = puuids.iter().map(String::as_str).collect();
+    puuids.sort_unstable();
+    Sha256::digest(puuids.join("\n").as_bytes())[..16]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// `createIndexes` command for the `_lobbyHash` index, a no-op if it already exists
+pub fn lobby_index_command(collection_name: &str) -> Document {
+    doc! {
+        "createIndexes": collection_name,
+        "indexes": [{"key": {"_lobbyHash": 1}, "name": "_lobbyHash_1"}],
+    }
+}
+
+/// `createIndexes` command for the index behind top-lobbies queries. They must sort by
+/// `{_avgElo: -1, _matchTimestamp: -1, _id: 1}`: many matches share an `_avgElo`, and
+/// without the tie-breaks the order of equal ones can change between requests, so pages
+/// skip or repeat matches. `_id` is unique, so the order is total.
+pub fn top_lobbies_index_command(collection_name: &str) -> Document {
+    doc! {
+        "createIndexes": collection_name,
+        "indexes": [{
+            "key": {"_avgElo": -1, "_matchTimestamp": -1, "_id": 1},
+            "name": "_avgElo_-1__matchTimestamp_-1__id_1",
+        }],
+    }
+}
+
+/// `createIndexes` command for the `_metaFingerprint` index, by patch, see
+/// `Config::meta_fingerprint`. Sparse, as only matches stored with the option have it.
+pub fn meta_fingerprint_index_command(collection_name: &str) -> Document {
+    doc! {
+        "createIndexes": collection_name,
+        "indexes": [{
+            "key": {"_patch": 1, "_metaFingerprint": 1},
+            "name": "_patch_1__metaFingerprint_1",
+            "sparse": true,
+        }],
+    }
+}
+
+/// `createIndexes` command for the TTL index removing matches at their `_documentExpire`.
+/// Created by the crawler only on monthly partitions, see
+/// `Config::partition_matches_monthly`, as nobody is around to create it on a new one.
+pub fn ttl_index_command(collection_name: &str) -> Document {
+    doc! {
+        "createIndexes": collection_name,
+        "indexes": [{
+            "key": {"_documentExpire": 1},
+            "name": "_documentExpire_1",
+            "expireAfterSeconds": 0,
+        }],
+    }
+}
+
+/// `createIndexes` command for the index behind `Main::rerank_unranked`, which looks
+/// for a region's matches with unranked participants in `_id` order
+pub fn unranked_index_command(collection_name: &str) -> Document {
+    doc! {
+        "createIndexes": collection_name,
+        "indexes": [{
+            "key": {"_aggregatedPlayerInfo.tftTier": 1, "_region": 1, "_id": 1},
+            "name": "_aggregatedPlayerInfo.tftTier_1__region_1__id_1",
+        }],
+    }
+}
+
+/// The raw match of a stored document, if it has a complete one
+pub fn raw_match(doc: &Document) -> Option<Match> {
+    let mut raw = doc.clone();
+    strip_derived_fields(&mut raw);
+    serde_json::from_value(Bson::Document(raw).into_relaxed_extjson()).ok()
+}
+
+fn strip_derived_fields(doc: &mut Document) {
+    let derived_keys: Vec<String> = doc.keys().filter(|k| k.starts_with('_')).cloned().collect();
+    for key in derived_keys {
+        doc.remove(&key);
+    }
+}
+
+/// Reduce a match document to its summary: the fields we derived (all prefixed
+/// with `_`) stay, the raw match blob from Riot (`metadata`, `info`) goes.
+pub fn strip_raw_fields(doc: &mut Document) {
+    let raw_keys: Vec<String> = doc
+        .keys()
+        .filter(|k| !k.starts_with('_'))
+        .cloned()
+        .collect();
+    for key in raw_keys {
+        doc.remove(&key);
+    }
+}
+
+/// Which parts of the raw match to store, as dotted paths into it that descend through
+/// arrays, e.g. `info.participants.units.items`. Parsed from `keep:<paths>` or
+/// `drop:<paths>`, comma separated. Derived `_` fields are never affected.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RawFieldFilter {
+    /// Only these paths (and the objects containing them)
+    Keep(Vec<String>),
+    /// Everything except these paths
+    Drop(Vec<String>),
+}
+
+/// Every path of a riven `tft_match_v1::Match`
+pub const KNOWN_RAW_PATHS: &[&str] = &[
+    "metadata",
+    "metadata.data_version",
+    "metadata.match_id",
+    "metadata.participants",
+    "info",
+    "info.game_datetime",
+    "info.game_length",
+    "info.game_variation",
+    "info.game_version",
+    "info.participants",
+    "info.participants.companion",
+    "info.participants.companion.skin_ID",
+    "info.participants.companion.content_ID",
+    "info.participants.companion.species",
+    "info.participants.gold_left",
+    "info.participants.last_round",
+    "info.participants.level",
+    "info.participants.placement",
+    "info.participants.players_eliminated",
+    "info.participants.puuid",
+    "info.participants.time_eliminated",
+    "info.participants.total_damage_to_players",
+    "info.participants.traits",
+    "info.participants.traits.name",
+    "info.participants.traits.num_units",
+    "info.participants.traits.style",
+    "info.participants.traits.tier_current",
+    "info.participants.traits.tier_total",
+    "info.participants.units",
+    "info.participants.units.items",
+    "info.participants.units.character_id",
+    "info.participants.units.chosen",
+    "info.participants.units.name",
+    "info.participants.units.rarity",
+    "info.participants.units.tier",
+    "info.queue_id",
+    "info.tft_set_number",
+];
+
+impl FromStr for RawFieldFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<RawFieldFilter, String> {
+        let (mode, paths) = s
+            .split_once(':')
+            .ok_or_else(|| format!("Expected keep:<paths> or drop:<paths>, got {}", s))?;
+        let paths: Vec<String> = paths
+            .split(',')
+            .map(str::trim)
+            .filter(|path| !path.is_empty())
+            .map(String::from)
+            .collect();
+        match mode.trim().to_lowercase().as_str() {
+            "keep" => Ok(RawFieldFilter::Keep(paths)),
+            "drop" => Ok(RawFieldFilter::Drop(paths)),
+            _ => Err(format!("Unknown raw field filter mode: {}", mode)),
+        }
+    }
+}
+
+impl RawFieldFilter {
+    fn paths(&self) -> &[String] {
+        match self {
+            RawFieldFilter::Keep(paths) | RawFieldFilter::Drop(paths) => paths,
+        }
+    }
+
+    /// Configured paths that aren't part of the match schema, so would never match
+    pub fn unknown_paths(&self) -> Vec<&str> {
+        self.paths()
+            .iter()
+            .map(String::as_str)
+            .filter(|path| !KNOWN_RAW_PATHS.contains(path))
+            .collect()
+    }
+
+    /// Prune the raw fields of a match document
+    pub fn apply(&self, doc: &mut Document) {
+        let paths: Vec<Vec<&str>> = self
+            .paths()
+            .iter()
+            .map(|path| path.split('.').collect())
+            .collect();
+        let paths: Vec<&[&str]> = paths.iter().map(Vec::as_slice).collect();
+        let keep = matches!(self, RawFieldFilter::Keep(_));
+        prune_document(doc, &paths, keep, true);
+    }
+}
+
+fn prune_document(doc: &mut Document, paths: &[&[&str]], keep: bool, top_level: bool) {
+    let keys: Vec<String> = doc.keys().cloned().collect();
+    for key in keys {
+        if top_level && key.starts_with('_') {
+            continue;
+        }
+        // Remainders of the paths going through this key
+        let rest: Vec<&[&str]> = paths
+            .iter()
+            .filter(|path| path.first() == Some(&key.as_str()))
+            .map(|path| &path[1..])
+            .collect();
+        let whole = rest.iter().any(|path| path.is_empty());
+        if rest.is_empty() {
+            if keep {
+                doc.remove(&key);
+            }
+        } else if whole {
+            if !keep {
+                doc.remove(&key);
+            }
+        } else if let Some(value) = doc.get_mut(&key) {
+            prune_value(value, &rest, keep);
+        }
+    }
+}
+
+fn prune_value(value: &mut Bson, paths: &[&[&str]], keep: bool) {
+    match value {
+        Bson::Document(doc) => prune_document(doc, paths, keep, false),
+        Bson::Array(values) => {
+            for value in values {
+                prune_value(value, paths, keep);
+            }
+        }
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::bson::doc;
+
+    #[test]
+    fn test_strip_raw_fields() {
+        let mut d = doc! {
+            "_id": "EUW1_1",
+            "metadata": {"participants": ["a", "b"]},
+            "info": {"game_version": "x"},
+            "_avgElo": 2400,
+        };
+        let full_size = document_size(&d);
+        strip_raw_fields(&mut d);
+        assert_eq!(d, doc! {"_id": "EUW1_1", "_avgElo": 2400});
+        assert!(document_size(&d) < full_size);
+    }
+
+    fn stored_match() -> Document {
+        doc! {
+            "_id": "EUW1_1",
+            "metadata": {"match_id": "EUW1_1", "participants": ["a"]},
+            "info": {
+                "game_version": "x",
+                "participants": [
+                    {"placement": 1, "units": [{"character_id": "c", "items": [1, 2]}]},
+                ],
+            },
+            "_avgElo": 2400,
+        }
+    }
+
+    #[test]
+    fn test_raw_field_filter_keep() {
+        let mut d = stored_match();
+        let filter: RawFieldFilter =
+            "keep:info.participants.placement, info.participants.units.character_id"
+                .parse()
+                .unwrap();
+        filter.apply(&mut d);
+        assert_eq!(
+            d,
+            doc! {
+                "_id": "EUW1_1",
+                "info": {"participants": [{"placement": 1, "units": [{"character_id": "c"}]}]},
+                "_avgElo": 2400,
+            }
+        );
+    }
+
+    #[test]
+    fn test_raw_field_filter_drop() {
+        let mut d = stored_match();
+        let filter: RawFieldFilter = "drop:metadata,info.participants.units.items"
+            .parse()
+            .unwrap();
+        filter.apply(&mut d);
+        assert_eq!(
+            d,
+            doc! {
+                "_id": "EUW1_1",
+                "info": {
+                    "game_version": "x",
+                    "participants": [{"placement": 1, "units": [{"character_id": "c"}]}],
+                },
+                "_avgElo": 2400,
+            }
+        );
+    }
+
+    #[test]
+    fn test_raw_field_filter_parse() {
+        let filter: RawFieldFilter = "DROP:info.participants.unit,metadata".parse().unwrap();
+        assert_eq!(filter.unknown_paths(), vec!["info.participants.unit"]);
+        assert!("info.participants".parse::<RawFieldFilter>().is_err());
+        assert!("only:info".parse::<RawFieldFilter>().is_err());
+    }
+
+    fn game() -> Match {
+        serde_json::from_value(serde_json::json!({
+            "metadata": {"data_version": "5", "match_id": "EUW1_1", "participants": ["a"]},
+            "info": {
+                "game_datetime": 1600000000000i64,
+                "game_length": 2000.5,
+                "game_version": "Version 11.1.352.5559 (Dec 11 2020/16:29:22) [PUBLIC] <Releases/11.1>",
+                "participants": [],
+                "queue_id": 1100,
+                "tft_set_number": 4,
+            },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_derived_fields() {
+        let enrichment = Enrichment {
+            player_data: vec![Bson::Document(doc! {"puuid": "a"})],
+            avg_elo: 2400,
+            avg_elo_text: "MASTER I 0LP".to_string(),
+            num_ranked: 1,
+            partial: false,
+            upset_score: None,
+            placements: None,
+            meta_fingerprint: None,
+            high_variance: None,
+        };
+        assert_eq!(
+            derived_fields(&game(), enrichment),
+            doc! {
+                "_aggregatedPlayerInfo": [{"puuid": "a"}],
+                "_avgElo": 2400,
+                "_avgEloText": "MASTER I 0LP",
+                "_tftSet": 4,
+                "_patch": "11.1",
+                "_lobbyHash": lobby_hash(&["a".to_string()]),
+                "_schemaVersion": SCHEMA_VERSION,
+            }
+        );
+    }
+
+    #[test]
+    fn test_lobby_hash() {
+        let puuids: Vec<String> = ["c", "a", "b"].iter().map(|p| p.to_string()).collect();
+        let mut reordered = puuids.clone();
+        reordered.sort();
+        assert_eq!(lobby_hash(&puuids), lobby_hash(&reordered));
+        assert_eq!(lobby_hash(&puuids).len(), 32);
+        assert_ne!(lobby_hash(&puuids), lobby_hash(&puuids[..2]));
+    }
+
+    #[test]
+    fn test_match_document() {
+        let game = game();
+        let direct = match_document(&game, true).unwrap();
+        assert_eq!(direct, match_document(&game, false).unwrap());
+        assert_eq!(
+            direct
+                .get_document("info")
+                .unwrap()
+                .get_i64("game_datetime"),
+            Ok(1600000000000)
+        );
+    }
+
+    #[test]
+    fn test_raw_match_round_trip() {
+        let game = game();
+        let mut stored = match_document(&game, false).unwrap();
+        stored.insert("_id", "EUW1_1");
+        stored.insert("_avgElo", 2400);
+        let raw = raw_match(&stored).unwrap();
+        assert_eq!(raw.metadata.match_id, "EUW1_1");
+        assert_eq!(raw.info.game_length, 2000.5);
+        assert_eq!(raw.info.tft_set_number, 4);
+
+        // Dummies and summaries have no raw match
+        assert!(raw_match(&doc! {"_id": "EUW1_1", "_avgElo": 2400}).is_none());
+    }
+
+    #[test]
+    fn test_document_size() {
+        // int32 document: 4 (length) + 1 (type) + 2 ("a\0") + 4 (value) + 1 (terminator)
+        assert_eq!(document_size(&doc! {"a": 1}), 12);
+    }
+}
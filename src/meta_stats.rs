@@ -0,0 +1,150 @@
+use mongodb::bson::{doc, Bson, Document};
+use riven::models::tft_match_v1::Participant;
+use std::collections::BTreeMap;
+
+const RANKED_TIERS: [&str; 9] = [
+    "IRON",
+    "BRONZE",
+    "SILVER",
+    "GOLD",
+    "PLATINUM",
+    "DIAMOND",
+    "MASTER",
+    "GRANDMASTER",
+    "CHALLENGER",
+];
+
+/// Placement of each participant along with the bracket (their own tier) it counts
+/// towards. `player_data` is `_aggregatedPlayerInfo`, participants are matched by puuid.
+pub fn bracket_placements(
+    participants: &[Participant],
+    player_data: &[Bson],
+) -> Vec<(String, i32)> {
+    participants
+        .iter()
+        .map(|participant| {
+            let tier = player_data
+                .iter()
+                .filter_map(Bson::as_document)
+                .find(|info| info.get_str("puuid") == Ok(participant.puuid.as_str()))
+                .and_then(|info| info.get_str("tftTier").ok())
+                .filter(|tier| RANKED_TIERS.contains(tier))
+                .unwrap_or("UNRANKED");
+            (tier.to_string(), participant.placement)
+        })
+        .collect()
+}
+
+/// `update` command adding one match's placements to the per set and bracket counters.
+/// Each bracket is a single `$inc` upsert, so concurrent tasks can't lose counts.
+pub fn update_command(
+    collection_name: &str,
+    tft_set: i32,
+    placements: &[(String, i32)],
+) -> Document {
+    let mut brackets: BTreeMap<&str, Document> = BTreeMap::new();
+    for (bracket, placement) in placements {
+        let inc = brackets.entry(bracket).or_default();
+        let mut add = |key: String| {
+            let count = inc.get_i32(&key).unwrap_or(0);
+            inc.insert(key, count + 1);
+        };
+        add("games".to_string());
+        add(format!("placements.{}", placement));
+        if *placement == 1 {
+            add("wins".to_string());
+        }
+        if *placement <= 4 {
+            add("top4".to_string());
+        }
+    }
+    let updates: Vec<Bson> = brackets
+        .into_iter()
+        .map(|(bracket, inc)| {
+            Bson::Document(doc! {
+                "q": {"_id": format!("{}-{}", tft_set, bracket)},
+                "u": {"$inc": inc, "$setOnInsert": {"tftSet": tft_set, "bracket": bracket}},
+                "upsert": true,
+            })
+        })
+        .collect();
+    doc! {"update": collection_name, "updates": updates, "ordered": false}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn participant(puuid: &str, placement: i32) -> Participant {
+        serde_json::from_value(serde_json::json!({
+            "companion": {"skin_ID": 1, "content_ID": "c", "species": "s"},
+            "gold_left": 0,
+            "last_round": 30,
+            "level": 8,
+            "placement": placement,
+            "players_eliminated": 0,
+            "puuid": puuid,
+            "time_eliminated": 1800.0,
+            "total_damage_to_players": 100,
+            "traits": [],
+            "units": [],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_bracket_placements() {
+        let participants = vec![
+            participant("a", 1),
+            participant("b", 5),
+            participant("c", 2),
+        ];
+        let player_data: Vec<Bson> = vec![
+            doc! {"puuid": "c", "tftTier": "unranked"}.into(),
+            doc! {"puuid": "a", "tftTier": "MASTER"}.into(),
+            doc! {"puuid": "b", "tftTier": "MASTER"}.into(),
+        ];
+        assert_eq!(
+            bracket_placements(&participants, &player_data),
+            vec![
+                ("MASTER".to_string(), 1),
+                ("MASTER".to_string(), 5),
+                ("UNRANKED".to_string(), 2)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_update_command() {
+        let placements = vec![
+            ("MASTER".to_string(), 1),
+            ("MASTER".to_string(), 5),
+            ("UNRANKED".to_string(), 2),
+        ];
+        let command = update_command("meta", 5, &placements);
+        let updates = command.get_array("updates").unwrap();
+        // One upsert per bracket
+        assert_eq!(updates.len(), 2);
+        let master = updates[0].as_document().unwrap();
+        assert_eq!(master.get_document("q").unwrap(), &doc! {"_id": "5-MASTER"});
+        let inc = master
+            .get_document("u")
+            .unwrap()
+            .get_document("$inc")
+            .unwrap();
+        let counts: BTreeMap<&str, i32> = inc
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_i32().unwrap()))
+            .collect();
+        let expected: BTreeMap<&str, i32> = vec![
+            ("games", 2),
+            ("placements.1", 1),
+            ("placements.5", 1),
+            ("wins", 1),
+            ("top4", 1),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(counts, expected);
+    }
+}
@@ -0,0 +1,259 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+macro_rules! metrics {
+    ($($name:ident),* $(,)?) => {
+        /// Counters for a single region task. Cumulative since startup; take a
+        /// snapshot at the start of a cycle and subtract to get per-cycle numbers.
+        #[derive(Default)]
+        pub struct Metrics {
+            $(pub $name: AtomicU64,)*
+        }
+
+        #[derive(Copy, Clone, Debug, Default, PartialEq)]
+        pub struct MetricsSnapshot {
+            $(pub $name: u64,)*
+        }
+
+        impl Metrics {
+            pub fn snapshot(&self) -> MetricsSnapshot {
+                MetricsSnapshot {
+                    $($name: self.$name.load(Ordering::Relaxed),)*
+                }
+            }
+        }
+
+        impl std::ops::Sub for MetricsSnapshot {
+            type Output = MetricsSnapshot;
+
+            fn sub(self, rhs: MetricsSnapshot) -> MetricsSnapshot {
+                MetricsSnapshot {
+                    $($name: self.$name - rhs.$name,)*
+                }
+            }
+        }
+    };
+}
+
+metrics!(
+    summoner_cache_hits,
+    summoner_cache_misses,
+    league_cache_hits,
+    league_cache_misses,
+    // Writes that waited longer than `SLOW_WRITE_PERMIT` for a write slot
+    slow_write_permits,
+    // Inserts that found the document already stored
+    duplicate_inserts,
+    // Matches with fewer than `Config::min_ranked_to_store` ranked players
+    unranked_matches_skipped,
+    // match-v1 calls made, list and get
+    match_calls,
+    // Top players whose match history was fetched
+    summoners_processed,
+    // Matches newly stored
+    new_matches,
+    // Matches left for a later cycle by `Config::max_summoner_calls_per_cycle`
+    deferred_matches,
+    // Stored matches whose derived fields were recomputed, see `Config::migrate_stored_matches`
+    migrated_matches,
+    // Participant lookups retried, see `Config::participant_retries`
+    participant_retries,
+    // Top players whose match history came back empty
+    summoners_without_matches,
+    // Malformed puuids and summoner ids skipped, see `Config::validate_ids`
+    invalid_ids,
+    // Matches that failed to process, whether or not a dummy was stored
+    match_errors,
+    // Matches skipped as older than `Config::max_match_age`
+    too_old_matches,
+    // Stored matches whose unranked participants have since placed, see
+    // `Config::rerank_unranked_per_cycle`
+    reranked_matches,
+);
+
+pub fn incr(counter: &AtomicU64) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Outcomes of one top player's matches, for the per-summoner log line. However many
+/// ids the match list returned, each is counted once.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct MatchCounts {
+    pub new: u32,
+    pub old: u32,
+    pub error: u32,
+    pub retry: u32,
+}
+
+impl MatchCounts {
+    /// Count a `process_match_id` result, an `Err` counting as an error
+    pub fn add(&mut self, ret: Option<i64>) {
+        match ret {
+            Some(1) => self.new += 1,
+            Some(0) => self.old += 1,
+            Some(2) => self.retry += 1,
+            Some(-1) | None => self.error += 1,
+            Some(ret) => unreachable!("process_match_id returned {}", ret),
+        }
+    }
+}
+
+impl std::fmt::Display for MatchCounts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} New, {} Old, {} Error, {} Retry",
+            self.new, self.old, self.error, self.retry
+        )
+    }
+}
+
+// Percentage of lookups served from the cache, for logging
+pub fn hit_rate_str(hits: u64, misses: u64) -> String {
+    let total = hits + misses;
+    if total == 0 {
+        return "n/a".to_string();
+    }
+    format!("{:.1}%", 100.0 * hits as f64 / total as f64)
+}
+
+// Events per minute over a period, for logging
+pub fn rate_str(count: u64, period: std::time::Duration, what: &str) -> String {
+    let minutes = period.as_secs_f64() / 60.0;
+    if minutes <= 0.0 {
+        return format!("{} {}", count, what);
+    }
+    format!("{} {} ({:.1}/min)", count, what, count as f64 / minutes)
+}
+
+impl std::fmt::Display for MetricsSnapshot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "summoner cache {} hit / {} miss ({}), league cache {} hit / {} miss ({})",
+            self.summoner_cache_hits,
+            self.summoner_cache_misses,
+            hit_rate_str(self.summoner_cache_hits, self.summoner_cache_misses),
+            self.league_cache_hits,
+            self.league_cache_misses,
+            hit_rate_str(self.league_cache_hits, self.league_cache_misses),
+        )?;
+        if self.summoners_processed > 0 {
+            write!(
+                f,
+                ", {} summoners, {} new matches",
+                self.summoners_processed, self.new_matches
+            )?;
+        }
+        if self.slow_write_permits > 0 {
+            write!(f, ", {} slow write permits", self.slow_write_permits)?;
+        }
+        if self.duplicate_inserts > 0 {
+            write!(f, ", {} already stored", self.duplicate_inserts)?;
+        }
+        if self.deferred_matches > 0 {
+            write!(
+                f,
+                ", {} matches deferred by the summoner call cap",
+                self.deferred_matches
+            )?;
+        }
+        if self.migrated_matches > 0 {
+            write!(f, ", {} stored matches migrated", self.migrated_matches)?;
+        }
+        if self.reranked_matches > 0 {
+            write!(f, ", {} stored matches reranked", self.reranked_matches)?;
+        }
+        if self.participant_retries > 0 {
+            write!(
+                f,
+                ", {} participant lookups retried",
+                self.participant_retries
+            )?;
+        }
+        if self.summoners_without_matches > 0 {
+            write!(
+                f,
+                ", {} summoners without recent matches",
+                self.summoners_without_matches
+            )?;
+        }
+        if self.too_old_matches > 0 {
+            write!(f, ", {} matches skipped as too old", self.too_old_matches)?;
+        }
+        if self.match_errors > 0 {
+            write!(f, ", {} match errors", self.match_errors)?;
+        }
+        if self.invalid_ids > 0 {
+            write!(f, ", {} invalid ids skipped", self.invalid_ids)?;
+        }
+        if self.unranked_matches_skipped > 0 {
+            write!(
+                f,
+                ", {} matches skipped for too few ranked players",
+                self.unranked_matches_skipped
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_match_counts() {
+        // A player with no recent matches
+        let counts = MatchCounts::default();
+        assert_eq!(counts.to_string(), "0 New, 0 Old, 0 Error, 0 Retry");
+
+        // Fewer ids than requested, including a failed match
+        let mut counts = MatchCounts::default();
+        for ret in &[Some(1), Some(0), None, Some(2), Some(-1), Some(1)] {
+            counts.add(*ret);
+        }
+        assert_eq!(
+            counts,
+            MatchCounts {
+                new: 2,
+                old: 1,
+                error: 2,
+                retry: 1,
+            }
+        );
+        assert_eq!(counts.to_string(), "2 New, 1 Old, 2 Error, 1 Retry");
+    }
+
+    #[test]
+    fn test_hit_rate_str() {
+        assert_eq!(hit_rate_str(0, 0), "n/a");
+        assert_eq!(hit_rate_str(3, 1), "75.0%");
+        assert_eq!(hit_rate_str(0, 5), "0.0%");
+    }
+
+    #[test]
+    fn test_rate_str() {
+        let two_minutes = std::time::Duration::from_secs(120);
+        assert_eq!(
+            rate_str(30, two_minutes, "match calls"),
+            "30 match calls (15.0/min)"
+        );
+        assert_eq!(
+            rate_str(3, std::time::Duration::from_secs(0), "match calls"),
+            "3 match calls"
+        );
+    }
+
+    #[test]
+    fn test_snapshot_delta() {
+        let metrics = Metrics::default();
+        incr(&metrics.summoner_cache_hits);
+        let start = metrics.snapshot();
+        incr(&metrics.summoner_cache_hits);
+        incr(&metrics.league_cache_misses);
+        let delta = metrics.snapshot() - start;
+        assert_eq!(delta.summoner_cache_hits, 1);
+        assert_eq!(delta.league_cache_misses, 1);
+        assert_eq!(delta.summoner_cache_misses, 0);
+    }
+}
@@ -1,3 +1,5 @@
+use std::str::FromStr;
+
 pub fn league_to_numeric(tier: &str, rank: &str, league_points: i32) -> i32 {
     let base = match tier {
         "IRON" => 0,
@@ -25,6 +27,19 @@ pub fn league_to_numeric(tier: &str, rank: &str, league_points: i32) -> i32 {
     base + rank_addition + league_points
 }
 
+/// `league_to_numeric` of a rank that may not be one, e.g. "unranked" or "unknown"
+/// from the caches. None instead of a panic.
+pub fn try_league_to_numeric(tier: &str, rank: &str, league_points: i32) -> Option<i32> {
+    const APEX_TIERS: [&str; 3] = ["MASTER", "GRANDMASTER", "CHALLENGER"];
+    const TIERS: [&str; 6] = ["IRON", "BRONZE", "SILVER", "GOLD", "PLATINUM", "DIAMOND"];
+    const DIVISIONS: [&str; 4] = ["I", "II", "III", "IV"];
+    let ranked = APEX_TIERS.contains(&tier) || (TIERS.contains(&tier) && DIVISIONS.contains(&rank));
+    if !ranked {
+        return None;
+    }
+    Some(league_to_numeric(tier, rank, league_points))
+}
+
 pub fn numeric_to_league(mut x: i32) -> (String, String, i32) {
     let tier = match x {
         i32::MIN..=399 => "IRON",
@@ -72,18 +87,141 @@ pub fn numeric_to_league(mut x: i32) -> (String, String, i32) {
     (tier.to_string(), division.to_string(), x)
 }
 
-pub fn league_to_str(league: &str, rank: &str, lp: i32) -> String {
+/// `omit_apex_division` leaves out the division of MASTER and above, which is always
+/// "I" as apex tiers have none, see `Config::omit_apex_division`
+pub fn league_to_str(league: &str, rank: &str, lp: i32, omit_apex_division: bool) -> String {
+    let apex = matches!(league, "MASTER" | "GRANDMASTER" | "CHALLENGER" | "MASTER+");
+    if omit_apex_division && apex {
+        return format!("{} {}LP", league, lp);
+    }
     format!("{} {} {}LP", league, rank, lp)
 }
 
 #[allow(dead_code)]
-pub fn elo_to_str(x: i32) -> String {
+pub fn elo_to_str(x: i32, omit_apex_division: bool) -> String {
     let (tier, rank, league_points) = numeric_to_league(x);
-    league_to_str(&tier, &rank, league_points)
+    league_to_str(&tier, &rank, league_points, omit_apex_division)
+}
+
+/// Apex LP at which the average of a lobby is labelled GRANDMASTER / CHALLENGER,
+/// instead of going by how many of the players are in each apex tier
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ApexCutoffs {
+    pub grandmaster: i32,
+    pub challenger: i32,
+}
+
+impl ApexCutoffs {
+    fn tier(self, league_points: i32) -> &'static str {
+        if league_points >= self.challenger {
+            "CHALLENGER"
+        } else if league_points >= self.grandmaster {
+            "GRANDMASTER"
+        } else {
+            "MASTER"
+        }
+    }
+}
+
+/// A platform's apex cutoffs as of its last ladder scan: the lowest LP in the
+/// grandmaster and challenger leagues, see `Config::live_apex_cutoffs`
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct LiveApexCutoffs {
+    grandmaster: Option<i32>,
+    challenger: Option<i32>,
+}
+
+impl LiveApexCutoffs {
+    /// Take the cutoff from a scanned apex league, other tiers are ignored
+    pub fn observe(&mut self, tier: &str, league_points: &[i32]) {
+        let cutoff = match tier {
+            "GRANDMASTER" => &mut self.grandmaster,
+            "CHALLENGER" => &mut self.challenger,
+            _ => return,
+        };
+        if let Some(lowest) = league_points.iter().min() {
+            *cutoff = Some(*lowest);
+        }
+    }
+
+    /// Both cutoffs, once both leagues have been scanned
+    pub fn cutoffs(self) -> Option<ApexCutoffs> {
+        Some(ApexCutoffs {
+            grandmaster: self.grandmaster?,
+            challenger: self.challenger?,
+        })
+    }
+}
+
+// Parsed from "<grandmaster LP>,<challenger LP>", e.g. "200,500"
+impl FromStr for ApexCutoffs {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ApexCutoffs, String> {
+        let err = || format!("Invalid apex LP cutoffs: {}", s);
+        let (grandmaster, challenger) = s.split_once(',').ok_or_else(err)?;
+        let grandmaster = grandmaster.trim().parse().map_err(|_| err())?;
+        let challenger = challenger.trim().parse().map_err(|_| err())?;
+        if grandmaster > challenger {
+            return Err(err());
+        }
+        Ok(ApexCutoffs {
+            grandmaster,
+            challenger,
+        })
+    }
+}
+
+/// How an average elo is rounded to a whole number, see `Config::elo_rounding`
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum EloRounding {
+    /// Drop the fraction, as integer division does
+    Truncate,
+    /// To the nearest, halves rounded up
+    HalfUp,
+    /// To the nearest, halves rounded to even
+    #[default]
+    Nearest,
+}
+
+impl FromStr for EloRounding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<EloRounding, String> {
+        match s.to_lowercase().as_str() {
+            "truncate" => Ok(EloRounding::Truncate),
+            "half-up" => Ok(EloRounding::HalfUp),
+            "nearest" => Ok(EloRounding::Nearest),
+            _ => Err(format!("Unknown elo rounding: {}", s)),
+        }
+    }
 }
 
-// Given a list of players, return the average elo, in string form
-pub fn team_avg_rank_str(ranks: &[(String, String, i32)]) -> String {
+impl EloRounding {
+    /// `sum / count` rounded by this mode. `count` must be positive.
+    pub fn average(self, sum: i32, count: i32) -> i32 {
+        let quotient = sum.div_euclid(count);
+        let twice_remainder = 2 * sum.rem_euclid(count);
+        let round_up = match self {
+            // Toward zero, like `/`
+            EloRounding::Truncate => sum < 0 && twice_remainder != 0,
+            EloRounding::HalfUp => twice_remainder >= count,
+            EloRounding::Nearest => {
+                twice_remainder > count || (twice_remainder == count && quotient % 2 != 0)
+            }
+        };
+        quotient + round_up as i32
+    }
+}
+
+// Given a list of players, return the average elo, in string form.
+// An apex average is labelled by `apex_cutoffs` if given, otherwise by tier counts.
+pub fn team_avg_rank_str(
+    ranks: &[(String, String, i32)],
+    apex_cutoffs: Option<ApexCutoffs>,
+    rounding: EloRounding,
+    omit_apex_division: bool,
+) -> String {
     let num_players = ranks.len() as i32;
     assert!(num_players > 0);
 
@@ -91,33 +229,75 @@ pub fn team_avg_rank_str(ranks: &[(String, String, i32)]) -> String {
     for (tier, rank, league_points) in ranks {
         sum += league_to_numeric(tier, rank, *league_points);
     }
-    let x: i32 = sum / num_players;
+    let x: i32 = rounding.average(sum, num_players);
     let (mut tier, rank, avg_lp) = numeric_to_league(x);
 
     if tier == "MASTER+" {
-        // Take another average over the N players, where
-        // CHALLENGER=3, GM=2, MASTER=1. Round to the closest.
-        let mut sum = 0;
-        for (tier, _, _) in ranks {
-            sum += match tier.as_str() {
-                "CHALLENGER" => 3,
-                "GRANDMASTER" => 2,
-                "MASTER" => 1,
-                _ => 0,
+        if let Some(cutoffs) = apex_cutoffs {
+            tier = cutoffs.tier(avg_lp).to_string();
+        } else {
+            // Take another average over the N players, where
+            // CHALLENGER=3, GM=2, MASTER=1. Round to the closest.
+            let mut sum = 0;
+            for (tier, _, _) in ranks {
+                sum += match tier.as_str() {
+                    "CHALLENGER" => 3,
+                    "GRANDMASTER" => 2,
+                    "MASTER" => 1,
+                    _ => 0,
+                }
             }
+            tier = if 2 * sum < 3 * num_players {
+                // avg less than 1.5
+                "MASTER".to_string()
+            } else if 2 * sum < 5 * num_players {
+                // avg less than 2.5
+                "GRANDMASTER".to_string()
+            } else {
+                "CHALLENGER".to_string()
+            };
         }
-        tier = if 2 * sum < 3 * num_players {
-            // avg less than 1.5
-            "MASTER".to_string()
-        } else if 2 * sum < 5 * num_players {
-            // avg less than 2.5
-            "GRANDMASTER".to_string()
-        } else {
-            "CHALLENGER".to_string()
-        };
     }
 
-    league_to_str(&tier, &rank, avg_lp)
+    league_to_str(&tier, &rank, avg_lp, omit_apex_division)
+}
+
+// How far a lobby's placements strayed from what ranks predicted, from (numeric elo,
+// placement) of each ranked player. The expected order is by elo, highest first, with
+// tied elos sharing the average of their positions; the actual order is by placement.
+// Sums each player's distance between the two, scaled by the largest possible sum
+// (the reversed order), so 0.0 is a lobby that finished exactly by rank and 1.0 one
+// that finished in reverse. None with fewer than two ranked players.
+pub fn upset_score(players: &[(i32, i32)]) -> Option<f64> {
+    let n = players.len();
+    if n < 2 {
+        return None;
+    }
+    let mut by_elo: Vec<usize> = (0..n).collect();
+    by_elo.sort_by_key(|&i| std::cmp::Reverse(players[i].0));
+    let mut expected = vec![0.0; n];
+    let mut start = 0;
+    while start < n {
+        let mut end = start;
+        while end + 1 < n && players[by_elo[end + 1]].0 == players[by_elo[start]].0 {
+            end += 1;
+        }
+        let position = (start + end) as f64 / 2.0;
+        for &i in &by_elo[start..=end] {
+            expected[i] = position;
+        }
+        start = end + 1;
+    }
+
+    let mut by_placement: Vec<usize> = (0..n).collect();
+    by_placement.sort_by_key(|&i| players[i].1);
+    let surprise: f64 = by_placement
+        .iter()
+        .enumerate()
+        .map(|(actual, &i)| (expected[i] - actual as f64).abs())
+        .sum();
+    let max = (n * n / 2) as f64;
+    Some(surprise / max)
 }
 
 #[cfg(test)]
@@ -127,7 +307,7 @@ mod tests {
     /// Helper function for tests
     fn test_conversions(rank: (&str, &str, i32), elo: i32, elo_string: &str) {
         assert_eq!(league_to_numeric(rank.0, rank.1, rank.2), elo);
-        assert_eq!(elo_to_str(elo), elo_string);
+        assert_eq!(elo_to_str(elo, false), elo_string);
     }
 
     #[test]
@@ -156,6 +336,46 @@ mod tests {
         test_conversions(("CHALLENGER", "I", 620), 3020, "MASTER+ I 620LP");
     }
 
+    #[test]
+    fn test_sub_apex_bijective() {
+        // Every (tier, division, 0..=99 LP) below apex maps to a distinct value,
+        // and converting back gives the same rank
+        let mut seen = std::collections::HashSet::new();
+        for tier in &["IRON", "BRONZE", "SILVER", "GOLD", "PLATINUM", "DIAMOND"] {
+            for division in &["IV", "III", "II", "I"] {
+                for lp in 0..=99 {
+                    let x = league_to_numeric(tier, division, lp);
+                    assert!(seen.insert(x), "{} {} {}LP collides", tier, division, lp);
+                    assert!(x < 2400);
+                    assert_eq!(
+                        numeric_to_league(x),
+                        (tier.to_string(), division.to_string(), lp)
+                    );
+                }
+            }
+        }
+        // Sub-apex ranks tile 0..2400 exactly
+        assert_eq!(seen.len(), 2400);
+        assert_eq!(*seen.iter().min().unwrap(), 0);
+        assert_eq!(*seen.iter().max().unwrap(), 2399);
+    }
+
+    #[test]
+    fn test_apex_collapse() {
+        // Apex tiers share one continuous LP ladder, so they intentionally
+        // collapse onto the same values and only "MASTER+" comes back
+        for lp in &[0, 1, 250, 1000] {
+            let master = league_to_numeric("MASTER", "I", *lp);
+            assert_eq!(master, 2400 + lp);
+            assert_eq!(league_to_numeric("GRANDMASTER", "I", *lp), master);
+            assert_eq!(league_to_numeric("CHALLENGER", "I", *lp), master);
+            assert_eq!(
+                numeric_to_league(master),
+                ("MASTER+".to_string(), "I".to_string(), *lp)
+            );
+        }
+    }
+
     #[test]
     #[should_panic]
     fn test_league_to_numeric_invalid_league() {
@@ -168,42 +388,231 @@ mod tests {
         league_to_numeric("IRON", "V", 0);
     }
 
+    #[test]
+    fn test_try_league_to_numeric() {
+        assert_eq!(try_league_to_numeric("DIAMOND", "II", 50), Some(2250));
+        assert_eq!(try_league_to_numeric("MASTER", "unknown", 50), Some(2450));
+        assert_eq!(try_league_to_numeric("IRON", "V", 0), None);
+        assert_eq!(
+            try_league_to_numeric("unranked", "unranked", i32::MIN),
+            None
+        );
+    }
+
     #[test]
     fn test_team_avg_rank_str() {
-        let ret = team_avg_rank_str(&vec![
-            ("CHALLENGER".to_string(), "I".to_string(), 1144),
-            ("CHALLENGER".to_string(), "I".to_string(), 653),
-            ("CHALLENGER".to_string(), "I".to_string(), 625),
-            ("GRANDMASTER".to_string(), "I".to_string(), 506),
-            ("GRANDMASTER".to_string(), "I".to_string(), 526),
-            ("MASTER".to_string(), "I".to_string(), 192),
-            ("MASTER".to_string(), "I".to_string(), 0),
-            ("DIAMOND".to_string(), "II".to_string(), 0),
-        ]);
-        assert_eq!(ret, "GRANDMASTER I 430LP");
-
-        let ret = team_avg_rank_str(&vec![
-            ("GRANDMASTER".to_string(), "I".to_string(), 270),
-            ("MASTER".to_string(), "I".to_string(), 260),
-            ("MASTER".to_string(), "I".to_string(), 250),
-            ("GRANDMASTER".to_string(), "I".to_string(), 240),
-            ("MASTER".to_string(), "I".to_string(), 230),
-            ("MASTER".to_string(), "I".to_string(), 220),
-            ("MASTER".to_string(), "I".to_string(), 210),
-            ("MASTER".to_string(), "I".to_string(), 200),
-        ]);
+        let ret = team_avg_rank_str(
+            &[
+                ("CHALLENGER".to_string(), "I".to_string(), 1144),
+                ("CHALLENGER".to_string(), "I".to_string(), 653),
+                ("CHALLENGER".to_string(), "I".to_string(), 625),
+                ("GRANDMASTER".to_string(), "I".to_string(), 506),
+                ("GRANDMASTER".to_string(), "I".to_string(), 526),
+                ("MASTER".to_string(), "I".to_string(), 192),
+                ("MASTER".to_string(), "I".to_string(), 0),
+                ("DIAMOND".to_string(), "II".to_string(), 0),
+            ],
+            None,
+            EloRounding::Nearest,
+            false,
+        );
+        // 2830.75 rounds up
+        assert_eq!(ret, "GRANDMASTER I 431LP");
+
+        let ret = team_avg_rank_str(
+            &[
+                ("GRANDMASTER".to_string(), "I".to_string(), 270),
+                ("MASTER".to_string(), "I".to_string(), 260),
+                ("MASTER".to_string(), "I".to_string(), 250),
+                ("GRANDMASTER".to_string(), "I".to_string(), 240),
+                ("MASTER".to_string(), "I".to_string(), 230),
+                ("MASTER".to_string(), "I".to_string(), 220),
+                ("MASTER".to_string(), "I".to_string(), 210),
+                ("MASTER".to_string(), "I".to_string(), 200),
+            ],
+            None,
+            EloRounding::Nearest,
+            false,
+        );
         assert_eq!(ret, "MASTER I 235LP");
 
-        let ret = team_avg_rank_str(&vec![
-            ("CHALLENGER".to_string(), "I".to_string(), 570),
-            ("CHALLENGER".to_string(), "I".to_string(), 560),
-            ("CHALLENGER".to_string(), "I".to_string(), 550),
-            ("CHALLENGER".to_string(), "I".to_string(), 540),
-            ("GRANDMASTER".to_string(), "I".to_string(), 530),
-            ("GRANDMASTER".to_string(), "I".to_string(), 520),
-            ("GRANDMASTER".to_string(), "I".to_string(), 510),
-            ("GRANDMASTER".to_string(), "I".to_string(), 500),
-        ]);
+        let ret = team_avg_rank_str(
+            &[
+                ("CHALLENGER".to_string(), "I".to_string(), 570),
+                ("CHALLENGER".to_string(), "I".to_string(), 560),
+                ("CHALLENGER".to_string(), "I".to_string(), 550),
+                ("CHALLENGER".to_string(), "I".to_string(), 540),
+                ("GRANDMASTER".to_string(), "I".to_string(), 530),
+                ("GRANDMASTER".to_string(), "I".to_string(), 520),
+                ("GRANDMASTER".to_string(), "I".to_string(), 510),
+                ("GRANDMASTER".to_string(), "I".to_string(), 500),
+            ],
+            None,
+            EloRounding::Nearest,
+            false,
+        );
         assert_eq!(ret, "CHALLENGER I 535LP");
     }
+
+    #[test]
+    fn test_omit_apex_division() {
+        assert_eq!(elo_to_str(2400 + 120, true), "MASTER+ 120LP");
+        assert_eq!(elo_to_str(2350, true), "DIAMOND I 50LP");
+        assert_eq!(
+            league_to_str("GRANDMASTER", "I", 431, true),
+            "GRANDMASTER 431LP"
+        );
+
+        let ranks: Vec<(String, String, i32)> = [570, 560, 550, 540]
+            .iter()
+            .map(|lp| ("CHALLENGER".to_string(), "I".to_string(), *lp))
+            .collect();
+        assert_eq!(
+            team_avg_rank_str(&ranks, None, EloRounding::Nearest, true),
+            "CHALLENGER 555LP"
+        );
+        let cutoffs = ApexCutoffs {
+            grandmaster: 200,
+            challenger: 500,
+        };
+        assert_eq!(
+            team_avg_rank_str(&ranks, Some(cutoffs), EloRounding::Nearest, true),
+            "CHALLENGER 555LP"
+        );
+        // Divisions below apex are kept
+        let ranks = [("DIAMOND".to_string(), "II".to_string(), 50)];
+        assert_eq!(
+            team_avg_rank_str(&ranks, None, EloRounding::Nearest, true),
+            "DIAMOND II 50LP"
+        );
+    }
+
+    #[test]
+    fn test_elo_rounding() {
+        use EloRounding::*;
+        assert_eq!(Truncate.average(22646, 8), 2830);
+        assert_eq!(HalfUp.average(22646, 8), 2831);
+        assert_eq!(Nearest.average(22646, 8), 2831);
+        // 2399.5, the boundary between DIAMOND I and MASTER
+        assert_eq!(Truncate.average(4799, 2), 2399);
+        assert_eq!(HalfUp.average(4799, 2), 2400);
+        assert_eq!(Nearest.average(4799, 2), 2400);
+        assert_eq!(Nearest.average(4801, 2), 2400);
+        assert_eq!(HalfUp.average(4801, 2), 2401);
+        assert_eq!(Truncate.average(-7, 2), -3);
+        assert_eq!(Nearest.average(2400, 8), 300);
+        assert_eq!("half-up".parse(), Ok(HalfUp));
+        assert!("up".parse::<EloRounding>().is_err());
+    }
+
+    #[test]
+    fn test_team_avg_rank_str_apex_cutoffs() {
+        let cutoffs = ApexCutoffs {
+            grandmaster: 200,
+            challenger: 500,
+        };
+        // All masters by tier, but at an LP only grandmasters have
+        let ranks: Vec<(String, String, i32)> = [300, 290, 280, 270]
+            .iter()
+            .map(|lp| ("MASTER".to_string(), "I".to_string(), *lp))
+            .collect();
+        assert_eq!(
+            team_avg_rank_str(&ranks, None, EloRounding::Nearest, false),
+            "MASTER I 285LP"
+        );
+        assert_eq!(
+            team_avg_rank_str(&ranks, Some(cutoffs), EloRounding::Nearest, false),
+            "GRANDMASTER I 285LP"
+        );
+
+        let ranks = [
+            ("CHALLENGER".to_string(), "I".to_string(), 1144),
+            ("MASTER".to_string(), "I".to_string(), 0),
+        ];
+        assert_eq!(
+            team_avg_rank_str(&ranks, Some(cutoffs), EloRounding::Nearest, false),
+            "CHALLENGER I 572LP"
+        );
+        // Cutoffs don't apply below apex
+        let ranks = [("DIAMOND".to_string(), "I".to_string(), 50)];
+        assert_eq!(
+            team_avg_rank_str(&ranks, Some(cutoffs), EloRounding::Nearest, false),
+            "DIAMOND I 50LP"
+        );
+        assert_eq!(cutoffs.tier(199), "MASTER");
+        assert_eq!(cutoffs.tier(200), "GRANDMASTER");
+        assert_eq!(cutoffs.tier(500), "CHALLENGER");
+    }
+
+    #[test]
+    fn test_upset_score() {
+        // Finished in rank order
+        assert_eq!(upset_score(&[(2800, 1), (2600, 2), (2400, 3)]), Some(0.0));
+        // Reversed: surprises 2 + 0 + 2 out of a possible 4
+        assert_eq!(upset_score(&[(2800, 3), (2600, 2), (2400, 1)]), Some(1.0));
+        assert_eq!(
+            upset_score(&[
+                (2800, 8),
+                (2700, 7),
+                (2600, 6),
+                (2500, 5),
+                (2400, 4),
+                (2300, 3)
+            ]),
+            Some(1.0)
+        );
+        // Top two swapped among four: 1 + 1 out of 8
+        assert_eq!(
+            upset_score(&[(2800, 2), (2600, 1), (2400, 5), (2200, 8)]),
+            Some(0.25)
+        );
+        // Equal elos are each expected half way between their positions
+        assert_eq!(upset_score(&[(2400, 2), (2400, 1)]), Some(0.5));
+        assert_eq!(
+            upset_score(&[(2800, 1), (2400, 2), (2400, 3), (2000, 4)]),
+            Some(0.125)
+        );
+        assert_eq!(upset_score(&[(2400, 1)]), None);
+        assert_eq!(upset_score(&[]), None);
+    }
+
+    #[test]
+    fn test_parse_apex_cutoffs() {
+        assert_eq!(
+            "200, 500".parse(),
+            Ok(ApexCutoffs {
+                grandmaster: 200,
+                challenger: 500
+            })
+        );
+        assert!("200".parse::<ApexCutoffs>().is_err());
+        assert!("500,200".parse::<ApexCutoffs>().is_err());
+        assert!("a,b".parse::<ApexCutoffs>().is_err());
+    }
+
+    #[test]
+    fn test_live_apex_cutoffs() {
+        let mut live = LiveApexCutoffs::default();
+        live.observe("GRANDMASTER", &[420, 250, 300]);
+        assert_eq!(live.cutoffs(), None);
+        live.observe("MASTER", &[0, 100]);
+        live.observe("CHALLENGER", &[900, 610]);
+        assert_eq!(
+            live.cutoffs(),
+            Some(ApexCutoffs {
+                grandmaster: 250,
+                challenger: 610,
+            })
+        );
+        // An empty scan keeps the last cutoff
+        live.observe("CHALLENGER", &[]);
+        live.observe("GRANDMASTER", &[270]);
+        assert_eq!(
+            live.cutoffs(),
+            Some(ApexCutoffs {
+                grandmaster: 270,
+                challenger: 610,
+            })
+        );
+    }
 }
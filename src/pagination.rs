@@ -0,0 +1,68 @@
+use futures::stream::{self, StreamExt};
+use std::future::Future;
+
+/// Fetch pages 1, 2, 3... until an empty page, keeping up to `lookahead` page
+/// requests in flight. Pages are returned in order. Since the page count isn't
+/// known up front, at most `lookahead - 1` requests past the first empty page
+/// are made (and their results discarded).
+pub async fn fetch_all_pages<T, E, F, Fut>(lookahead: usize, fetch: F) -> Result<Vec<T>, E>
+where
+    F: Fn(i32) -> Fut,
+    Fut: Future<Output = Result<Vec<T>, E>>,
+{
+    let mut pages = stream::iter(1..).map(fetch).buffered(lookahead.max(1));
+    let mut ret = Vec::new();
+    while let Some(page) = pages.next().await {
+        let page = page?;
+        if page.is_empty() {
+            break;
+        }
+        ret.extend(page);
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use std::sync::atomic::{AtomicI32, Ordering};
+
+    // 3 full pages of 2 entries, then empty pages
+    fn fetch_counted(calls: &AtomicI32, page: i32) -> impl Future<Output = Result<Vec<i32>, ()>> {
+        calls.fetch_add(1, Ordering::SeqCst);
+        let entries = if page <= 3 {
+            vec![page * 10, page * 10 + 1]
+        } else {
+            vec![]
+        };
+        async move { Ok(entries) }
+    }
+
+    #[test]
+    fn test_fetch_all_pages() {
+        for lookahead in 1..=5 {
+            let calls = AtomicI32::new(0);
+            let ret = block_on(fetch_all_pages(lookahead, |page| {
+                fetch_counted(&calls, page)
+            }))
+            .unwrap();
+            assert_eq!(ret, vec![10, 11, 20, 21, 30, 31]);
+            // 4 pages are needed to see the end, at most lookahead - 1 more are requested
+            let calls = calls.load(Ordering::SeqCst);
+            assert!((4..4 + lookahead as i32).contains(&calls), "{}", calls);
+        }
+    }
+
+    #[test]
+    fn test_fetch_all_pages_error() {
+        let ret: Result<Vec<i32>, &str> = block_on(fetch_all_pages(3, |page| async move {
+            if page == 2 {
+                Err("boom")
+            } else {
+                Ok(vec![page])
+            }
+        }));
+        assert_eq!(ret, Err("boom"));
+    }
+}
@@ -0,0 +1,817 @@
+use chrono::{DateTime, Utc};
+use mongodb::bson::{doc, Bson, Document};
+use riven::models::tft_match_v1::Participant;
+use sha2::{Digest, Sha256};
+use std::cmp::Reverse;
+use std::str::FromStr;
+
+use crate::numeric_league_util::{
+    league_to_numeric, team_avg_rank_str, upset_score, ApexCutoffs, EloRounding,
+};
+
+/// Everything known about one participant of a match, gathered from the
+/// summoner and league caches
+#[derive(Clone, Debug)]
+pub struct ParticipantInfo {
+    pub puuid: String,
+    pub summoner_id: String,
+    pub summoner_name: String,
+    pub account_id: String,
+    pub ranked: bool,
+    pub tier: String,
+    pub rank: String,
+    pub league_points: i32,
+    pub companion: Option<CompanionInfo>,
+    /// When the league entry the rank comes from was fetched, which for an old match
+    /// can be long after it was played
+    pub rank_observed_at: Option<DateTime<Utc>>,
+}
+
+/// The participant's Little Legend
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompanionInfo {
+    pub species: String,
+    pub content_id: String,
+}
+
+/// Companion of the participant with this puuid. None if they aren't in the match,
+/// or the match predates companion data and the fields are empty.
+pub fn companion_info(participants: &[Participant], puuid: &str) -> Option<CompanionInfo> {
+    let companion = &participants.iter().find(|p| p.puuid == puuid)?.companion;
+    if companion.species.is_empty() || companion.content_id.is_empty() {
+        return None;
+    }
+    Some(CompanionInfo {
+        species: companion.species.clone(),
+        content_id: companion.content_id.clone(),
+    })
+}
+
+impl ParticipantInfo {
+    /// Stand-in for a participant whose lookups failed, counted as unranked
+    pub fn placeholder(puuid: &str) -> ParticipantInfo {
+        ParticipantInfo {
+            puuid: puuid.to_string(),
+            summoner_id: "unknown".to_string(),
+            summoner_name: "unknown".to_string(),
+            account_id: "unknown".to_string(),
+            ranked: false,
+            tier: "unknown".to_string(),
+            rank: "unknown".to_string(),
+            league_points: i32::MIN,
+            companion: None,
+            rank_observed_at: None,
+        }
+    }
+
+    // Entry of the `_aggregatedPlayerInfo` array
+    pub fn to_doc(&self) -> Document {
+        let mut doc = doc! {
+            "summonerId": self.summoner_id.clone(),
+            "summonerName": self.summoner_name.clone(),
+            "accountId": self.account_id.clone(),
+            "puuid": self.puuid.clone(),
+            "tftTier": self.tier.clone(),
+            "tftRank": self.rank.clone(),
+            "tftLeaguePoints": self.league_points,
+        };
+        if let Some(companion) = &self.companion {
+            doc.insert(
+                "companion",
+                doc! {"species": &companion.species, "contentId": &companion.content_id},
+            );
+        }
+        doc
+    }
+}
+
+/// Participant-derived fields of a match document
+pub struct Enrichment {
+    /// `_aggregatedPlayerInfo`
+    pub player_data: Vec<Bson>,
+    pub avg_elo: i32,
+    pub avg_elo_text: String,
+    pub num_ranked: usize,
+    /// Some participants are placeholders, see `ParticipantInfo::placeholder`
+    pub partial: bool,
+    /// `_upsetScore`, see `lobby_upset_score`
+    pub upset_score: Option<f64>,
+    /// `_placements`, see `placements`
+    pub placements: Option<Vec<Bson>>,
+    /// `_metaFingerprint`, see `meta_fingerprint`
+    pub meta_fingerprint: Option<Vec<String>>,
+    /// `_highVariance`, see `Config::high_variance_spread`
+    pub high_variance: Option<bool>,
+}
+
+/// Short stable join key for a puuid: the first 16 hex chars (64 bits) of its SHA-256.
+/// Among n distinct puuids the chance of any collision is about n² / 2^65, e.g. ~3e-6
+/// for 10 million players.
+pub fn pid_hash(puuid: &str) -> String {
+    Sha256::digest(puuid.as_bytes())[..8]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// `numeric_league_util::upset_score` of the ranked participants
+pub fn lobby_upset_score(infos: &[ParticipantInfo], participants: &[Participant]) -> Option<f64> {
+    let players: Vec<(i32, i32)> = infos
+        .iter()
+        .filter(|info| info.ranked)
+        .filter_map(|info| {
+            let participant = participants.iter().find(|p| p.puuid == info.puuid)?;
+            let elo = league_to_numeric(&info.tier, &info.rank, info.league_points);
+            Some((elo, participant.placement))
+        })
+        .collect();
+    upset_score(&players)
+}
+
+/// Difference between the highest and lowest numeric elo of the ranked participants,
+/// e.g. 1500 for a 1500LP challenger in a lobby with a 0LP master. None with fewer than
+/// two ranked participants.
+pub fn elo_spread(infos: &[ParticipantInfo]) -> Option<i32> {
+    let elos: Vec<i32> = infos
+        .iter()
+        .filter(|info| info.ranked)
+        .map(|info| league_to_numeric(&info.tier, &info.rank, info.league_points))
+        .collect();
+    if elos.len() < 2 {
+        return None;
+    }
+    Some(elos.iter().max()? - elos.iter().min()?)
+}
+
+/// How much goes into each `_aggregatedPlayerInfo` entry, the main lever on match
+/// document size. Augments aren't part of the match data we get, so no level has them.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EnrichmentLevel {
+    /// Identity (summoner id and name, account id, puuid) and rank (tier, division, LP)
+    Minimal,
+    /// Also `ranked`, `placement` and `level`
+    #[default]
+    Standard,
+    /// Also `lastRound`, `goldLeft`, `totalDamageToPlayers`, the active `traits` and
+    /// the `companion`
+    Full,
+}
+
+impl FromStr for EnrichmentLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<EnrichmentLevel, String> {
+        match s.to_lowercase().as_str() {
+            "minimal" => Ok(EnrichmentLevel::Minimal),
+            "standard" => Ok(EnrichmentLevel::Standard),
+            "full" => Ok(EnrichmentLevel::Full),
+            _ => Err(format!("Unknown enrichment level: {}", s)),
+        }
+    }
+}
+
+/// Add each participant's ranked flag and match performance, as far as `level` goes,
+/// to their `_aggregatedPlayerInfo` entry, for every participant whether ranked or not,
+/// so queries can choose whether to include unranked players. `infos` is in the same
+/// order as `player_data`.
+pub fn add_performance(
+    player_data: &mut [Bson],
+    infos: &[ParticipantInfo],
+    participants: &[Participant],
+    level: EnrichmentLevel,
+) {
+    if level < EnrichmentLevel::Standard {
+        return;
+    }
+    let entries = player_data.iter_mut().filter_map(Bson::as_document_mut);
+    for (entry, info) in entries.zip(infos) {
+        entry.insert("ranked", info.ranked);
+        if let Some(participant) = participants.iter().find(|p| p.puuid == info.puuid) {
+            entry.insert("placement", participant.placement);
+            entry.insert("level", participant.level);
+            if level == EnrichmentLevel::Full {
+                entry.insert("lastRound", participant.last_round);
+                entry.insert("goldLeft", participant.gold_left);
+                entry.insert("totalDamageToPlayers", participant.total_damage_to_players);
+            }
+        }
+    }
+}
+
+/// Add `_rankObservedAt`, when the rank was looked up, to the `_aggregatedPlayerInfo`
+/// entry of each participant whose lookup succeeded
+pub fn add_rank_observed_at(player_data: &mut [Bson], infos: &[ParticipantInfo]) {
+    let entries = player_data.iter_mut().filter_map(Bson::as_document_mut);
+    for (entry, info) in entries.zip(infos) {
+        if let Some(observed_at) = info.rank_observed_at {
+            entry.insert("_rankObservedAt", observed_at);
+        }
+    }
+}
+
+/// Add each participant's active traits (`name`, `tierCurrent`) to their
+/// `_aggregatedPlayerInfo` entry
+pub fn add_traits(
+    player_data: &mut [Bson],
+    infos: &[ParticipantInfo],
+    participants: &[Participant],
+) {
+    let entries = player_data.iter_mut().filter_map(Bson::as_document_mut);
+    for (entry, info) in entries.zip(infos) {
+        if let Some(participant) = participants.iter().find(|p| p.puuid == info.puuid) {
+            let traits: Vec<Bson> = participant
+                .traits
+                .iter()
+                .filter(|t| t.tier_current > 0)
+                .map(|t| doc! {"name": &t.name, "tierCurrent": t.tier_current}.into())
+                .collect();
+            entry.insert("traits", traits);
+        }
+    }
+}
+
+/// `_metaFingerprint`: one `<primary trait>:<carry unit>` pair per participant, sorted,
+/// so lobbies with the same mix of comps have equal fingerprints whatever the finishing
+/// order. The primary trait is the active trait with the highest tier, then the most
+/// units; the carry is the unit holding the most items, then the highest star level and
+/// cost. Ties go to the lowest name, so the pairs are stable. A participant without an
+/// active trait or any units has `none` in its place, as in early-set matches. None if
+/// the match has no participant data at all.
+pub fn meta_fingerprint(participants: &[Participant]) -> Option<Vec<String>> {
+    if participants.is_empty() {
+        return None;
+    }
+    let mut pairs: Vec<String> = participants
+        .iter()
+        .map(|participant| {
+            let primary = participant
+                .traits
+                .iter()
+                .filter(|t| t.tier_current > 0)
+                .max_by_key(|t| (t.tier_current, t.num_units, Reverse(&t.name)))
+                .map_or("none", |t| t.name.as_str());
+            let carry = participant
+                .units
+                .iter()
+                .map(|unit| {
+                    // Older match data has the unit's `name` but an empty `character_id`
+                    let id = if unit.character_id.is_empty() {
+                        &unit.name
+                    } else {
+                        &unit.character_id
+                    };
+                    (unit, id.as_str())
+                })
+                .filter(|(_, id)| !id.is_empty())
+                .max_by_key(|(unit, id)| (unit.items.len(), unit.tier, unit.rarity, Reverse(*id)))
+                .map_or("none", |(_, id)| id);
+            format!("{}:{}", primary, carry)
+        })
+        .collect();
+    pairs.sort_unstable();
+    Some(pairs)
+}
+
+/// `_placements`: one entry per participant in finishing order, with the puuid (or its
+/// `pid_hash` if `hash_ids`) and the rank counted towards `_avgElo`. `elo` is null for
+/// unranked players. Tied placements keep participant order; a participant without
+/// lookup results is listed with an unknown tier.
+pub fn placements(
+    infos: &[ParticipantInfo],
+    participants: &[Participant],
+    hash_ids: bool,
+) -> Vec<Bson> {
+    let mut ordered: Vec<&Participant> = participants.iter().collect();
+    ordered.sort_by_key(|p| p.placement);
+    ordered
+        .into_iter()
+        .map(|participant| {
+            let mut entry = doc! {"placement": participant.placement};
+            if hash_ids {
+                entry.insert("pidHash", pid_hash(&participant.puuid));
+            } else {
+                entry.insert("puuid", &participant.puuid);
+            }
+            let info = infos.iter().find(|info| info.puuid == participant.puuid);
+            match info {
+                Some(info) if info.ranked => {
+                    entry.insert("tier", &info.tier);
+                    entry.insert("rank", &info.rank);
+                    let elo = league_to_numeric(&info.tier, &info.rank, info.league_points);
+                    entry.insert("elo", elo);
+                }
+                Some(info) => {
+                    entry.insert("tier", &info.tier);
+                    entry.insert("elo", Bson::Null);
+                }
+                None => {
+                    entry.insert("tier", "unknown");
+                    entry.insert("elo", Bson::Null);
+                }
+            }
+            Bson::Document(entry)
+        })
+        .collect()
+}
+
+/// Add `_pidHash` to every `_aggregatedPlayerInfo` entry, next to its puuid
+pub fn add_pid_hashes(player_data: &mut [Bson]) {
+    for info in player_data.iter_mut().filter_map(Bson::as_document_mut) {
+        if let Ok(puuid) = info.get_str("puuid") {
+            let hash = pid_hash(puuid);
+            info.insert("_pidHash", hash);
+        }
+    }
+}
+
+/// Place results tagged with their participant index back into participant order,
+/// whatever order they were produced in.
+/// Panics if an index is out of range, repeated or missing.
+pub fn collect_indexed<T>(len: usize, results: impl IntoIterator<Item = (usize, T)>) -> Vec<T> {
+    let mut slots: Vec<Option<T>> = (0..len).map(|_| None).collect();
+    for (index, value) in results {
+        assert!(
+            slots[index].is_none(),
+            "Duplicate result for index {}",
+            index
+        );
+        slots[index] = Some(value);
+    }
+    slots
+        .into_iter()
+        .enumerate()
+        .map(|(index, value)| value.unwrap_or_else(|| panic!("Missing result for index {}", index)))
+        .collect()
+}
+
+/// Build `_aggregatedPlayerInfo`, `_avgElo` and `_avgEloText` from the participants,
+/// which must already be in `metadata.participants` order.
+pub fn aggregate_participants(
+    infos: &[ParticipantInfo],
+    apex_cutoffs: Option<ApexCutoffs>,
+    rounding: EloRounding,
+    omit_apex_division: bool,
+) -> (Vec<Bson>, i32, String) {
+    let mut ret: Vec<Bson> = vec![];
+    let mut sum = 0;
+    let mut num_ranked = 0;
+
+    let mut ranks_vec = vec![];
+
+    for info in infos {
+        ret.push(info.to_doc().into());
+
+        if info.ranked {
+            ranks_vec.push((info.tier.clone(), info.rank.clone(), info.league_points));
+
+            sum += league_to_numeric(&info.tier, &info.rank, info.league_points);
+            num_ranked += 1;
+        }
+    }
+    let (avg_elo, avg_elo_str) = if num_ranked >= 1 {
+        (
+            rounding.average(sum, num_ranked),
+            team_avg_rank_str(&ranks_vec, apex_cutoffs, rounding, omit_apex_division),
+        )
+    } else {
+        (i32::MIN, "UNRANKED".to_string())
+    };
+    (ret, avg_elo, avg_elo_str)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranked(puuid: &str, tier: &str, rank: &str, lp: i32) -> ParticipantInfo {
+        ParticipantInfo {
+            puuid: puuid.to_string(),
+            summoner_id: format!("summoner-{}", puuid),
+            summoner_name: format!("name-{}", puuid),
+            account_id: format!("account-{}", puuid),
+            ranked: true,
+            tier: tier.to_string(),
+            rank: rank.to_string(),
+            league_points: lp,
+            companion: None,
+            rank_observed_at: None,
+        }
+    }
+
+    fn participant(puuid: &str, species: &str, content_id: &str) -> Participant {
+        serde_json::from_value(serde_json::json!({
+            "companion": {"skin_ID": 1, "content_ID": content_id, "species": species},
+            "gold_left": 0,
+            "last_round": 30,
+            "level": 8,
+            "placement": 1,
+            "players_eliminated": 2,
+            "puuid": puuid,
+            "time_eliminated": 1800.0,
+            "total_damage_to_players": 100,
+            "traits": [],
+            "units": [],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_lobby_upset_score() {
+        let placed = |puuid, placement| {
+            let mut p = participant(puuid, "", "");
+            p.placement = placement;
+            p
+        };
+        let participants = vec![placed("a", 1), placed("b", 2), placed("c", 3)];
+        let mut unranked = ParticipantInfo::placeholder("c");
+        unranked.ranked = false;
+        // The diamond beat the master; the unranked player doesn't count
+        let infos = vec![
+            ranked("a", "DIAMOND", "I", 0),
+            ranked("b", "MASTER", "I", 100),
+            unranked,
+        ];
+        assert_eq!(lobby_upset_score(&infos, &participants), Some(1.0));
+        assert_eq!(lobby_upset_score(&infos[..1], &participants), None);
+    }
+
+    #[test]
+    fn test_elo_spread() {
+        let infos = vec![
+            ranked("a", "CHALLENGER", "I", 1500),
+            ranked("b", "MASTER", "I", 0),
+            ranked("c", "MASTER", "I", 300),
+            ParticipantInfo::placeholder("d"),
+        ];
+        assert_eq!(elo_spread(&infos), Some(1500));
+        // The unranked placeholder doesn't count
+        assert_eq!(elo_spread(&infos[1..]), Some(300));
+        assert_eq!(elo_spread(&infos[2..]), None);
+    }
+
+    #[test]
+    fn test_add_performance() {
+        let mut second = participant("b", "", "");
+        second.placement = 6;
+        second.level = 7;
+        let participants = vec![participant("a", "", ""), second];
+        let mut unranked = ParticipantInfo::placeholder("b");
+        unranked.tier = "unranked".to_string();
+        let infos = vec![ranked("a", "MASTER", "I", 10), unranked];
+        let (mut player_data, _, _) =
+            aggregate_participants(&infos, None, EloRounding::Nearest, false);
+        add_performance(
+            &mut player_data,
+            &infos,
+            &participants,
+            EnrichmentLevel::Full,
+        );
+
+        let entry = player_data[1].as_document().unwrap();
+        assert_eq!(entry.get_bool("ranked"), Ok(false));
+        assert_eq!(entry.get_i32("placement"), Ok(6));
+        assert_eq!(entry.get_i32("level"), Ok(7));
+        assert_eq!(entry.get_i32("lastRound"), Ok(30));
+        let entry = player_data[0].as_document().unwrap();
+        assert_eq!(entry.get_bool("ranked"), Ok(true));
+        assert_eq!(entry.get_i32("placement"), Ok(1));
+
+        // Standard stops at placement and level
+        let (mut player_data, _, _) =
+            aggregate_participants(&infos, None, EloRounding::Nearest, false);
+        add_performance(
+            &mut player_data,
+            &infos,
+            &participants,
+            EnrichmentLevel::Standard,
+        );
+        let entry = player_data[1].as_document().unwrap();
+        assert_eq!(entry.get_i32("level"), Ok(7));
+        assert!(!entry.contains_key("lastRound"));
+
+        let (mut player_data, _, _) =
+            aggregate_participants(&infos, None, EloRounding::Nearest, false);
+        let minimal = player_data.clone();
+        add_performance(
+            &mut player_data,
+            &infos,
+            &participants,
+            EnrichmentLevel::Minimal,
+        );
+        assert_eq!(player_data, minimal);
+    }
+
+    #[test]
+    fn test_add_traits() {
+        let mut first = participant("a", "", "");
+        first.traits = serde_json::from_value(serde_json::json!([
+            {"name": "Set4_Cultist", "num_units": 6, "tier_current": 2},
+            {"name": "Set4_Mystic", "num_units": 1, "tier_current": 0},
+        ]))
+        .unwrap();
+        let infos = vec![ranked("a", "MASTER", "I", 10)];
+        let (mut player_data, _, _) =
+            aggregate_participants(&infos, None, EloRounding::Nearest, false);
+        add_traits(&mut player_data, &infos, &[first]);
+        assert_eq!(
+            player_data[0].as_document().unwrap().get_array("traits"),
+            Ok(&vec![Bson::Document(
+                doc! {"name": "Set4_Cultist", "tierCurrent": 2}
+            )])
+        );
+    }
+
+    #[test]
+    fn test_meta_fingerprint() {
+        let mut first = participant("a", "", "");
+        first.traits = serde_json::from_value(serde_json::json!([
+            {"name": "Set4_Mystic", "num_units": 2, "tier_current": 1},
+            {"name": "Set4_Cultist", "num_units": 6, "tier_current": 2},
+            {"name": "Set4_Vanguard", "num_units": 4, "tier_current": 2},
+            {"name": "Set4_Divine", "num_units": 7, "tier_current": 0},
+        ]))
+        .unwrap();
+        first.units = serde_json::from_value(serde_json::json!([
+            {"items": [1, 2], "character_id": "TFT4_Aatrox", "name": "", "rarity": 2, "tier": 2},
+            {"items": [1, 2, 3], "character_id": "TFT4_Kalista", "name": "", "rarity": 2, "tier": 2},
+            {"items": [], "character_id": "TFT4_Jhin", "name": "", "rarity": 4, "tier": 3},
+        ]))
+        .unwrap();
+        // Early-set data: no traits, units identified by name
+        let mut second = participant("b", "", "");
+        second.units = serde_json::from_value(serde_json::json!([
+            {"items": [], "character_id": "", "name": "Zed", "rarity": 4, "tier": 1},
+            {"items": [], "character_id": "", "name": "Ahri", "rarity": 4, "tier": 1},
+        ]))
+        .unwrap();
+        let third = participant("c", "", "");
+
+        let mut participants = vec![first, second, third];
+        let fingerprint = meta_fingerprint(&participants);
+        assert_eq!(
+            fingerprint,
+            Some(vec![
+                "Set4_Cultist:TFT4_Kalista".to_string(),
+                "none:Ahri".to_string(),
+                "none:none".to_string(),
+            ])
+        );
+        // Independent of participant order
+        participants.reverse();
+        assert_eq!(meta_fingerprint(&participants), fingerprint);
+        assert_eq!(meta_fingerprint(&[]), None);
+    }
+
+    #[test]
+    fn test_add_rank_observed_at() {
+        use chrono::TimeZone;
+        let observed_at = Utc.ymd(2021, 3, 1).and_hms(12, 0, 0);
+        let infos = vec![
+            ParticipantInfo {
+                rank_observed_at: Some(observed_at),
+                ..ranked("a", "MASTER", "I", 10)
+            },
+            ParticipantInfo::placeholder("b"),
+        ];
+        let (mut player_data, _, _) =
+            aggregate_participants(&infos, None, EloRounding::Nearest, false);
+        add_rank_observed_at(&mut player_data, &infos);
+        let entry = |i: usize| player_data[i].as_document().unwrap();
+        assert_eq!(entry(0).get_datetime("_rankObservedAt"), Ok(&observed_at));
+        // A failed lookup observed nothing
+        assert!(!entry(1).contains_key("_rankObservedAt"));
+    }
+
+    #[test]
+    fn test_enrichment_level() {
+        assert_eq!("FULL".parse(), Ok(EnrichmentLevel::Full));
+        assert_eq!(EnrichmentLevel::default(), EnrichmentLevel::Standard);
+        assert!(EnrichmentLevel::Minimal < EnrichmentLevel::Standard);
+        assert!("everything".parse::<EnrichmentLevel>().is_err());
+    }
+
+    #[test]
+    fn test_placements() {
+        let mut first = participant("b", "", "");
+        first.placement = 1;
+        let mut tied = participant("c", "", "");
+        tied.placement = 4;
+        let mut last = participant("a", "", "");
+        last.placement = 4;
+        let participants = vec![last, first, tied];
+        let infos = vec![
+            ranked("a", "DIAMOND", "II", 50),
+            ParticipantInfo::placeholder("b"),
+        ];
+        let entries = placements(&infos, &participants, false);
+        let entry = |i: usize| entries[i].as_document().unwrap();
+        assert_eq!(
+            entry(0),
+            &doc! {"placement": 1, "puuid": "b", "tier": "unknown", "elo": Bson::Null}
+        );
+        // Ties keep participant order
+        assert_eq!(
+            entry(1),
+            &doc! {"placement": 4, "puuid": "a", "tier": "DIAMOND", "rank": "II", "elo": 2250}
+        );
+        // No lookup results at all
+        assert_eq!(entry(2).get_str("tier"), Ok("unknown"));
+
+        let hashed = placements(&infos, &participants, true);
+        let entry = hashed[1].as_document().unwrap();
+        assert_eq!(entry.get_str("pidHash"), Ok(pid_hash("a").as_str()));
+        assert!(!entry.contains_key("puuid"));
+    }
+
+    #[test]
+    fn test_companion_info() {
+        let participants = vec![
+            participant("a", "PetTFTAvatar", "c1"),
+            participant("b", "", ""),
+        ];
+        let companion = companion_info(&participants, "a");
+        assert_eq!(
+            companion,
+            Some(CompanionInfo {
+                species: "PetTFTAvatar".to_string(),
+                content_id: "c1".to_string(),
+            })
+        );
+        // Old sets have empty companion fields
+        assert_eq!(companion_info(&participants, "b"), None);
+        assert_eq!(companion_info(&participants, "c"), None);
+
+        let mut info = ranked("a", "GOLD", "I", 0);
+        assert!(!info.to_doc().contains_key("companion"));
+        info.companion = companion;
+        assert_eq!(
+            info.to_doc().get_document("companion").unwrap(),
+            &doc! {"species": "PetTFTAvatar", "contentId": "c1"}
+        );
+    }
+
+    #[test]
+    fn test_collect_indexed_preserves_participant_order() {
+        let participants: Vec<String> = (0..8).map(|i| format!("puuid-{}", i)).collect();
+        // Simulate enrichment completing in reverse order
+        let results = participants
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(i, puuid)| (i, ranked(puuid, "DIAMOND", "I", i as i32)));
+        let infos = collect_indexed(participants.len(), results);
+
+        let (player_data, _, _) = aggregate_participants(&infos, None, EloRounding::Nearest, false);
+        assert_eq!(player_data.len(), participants.len());
+        for (i, puuid) in participants.iter().enumerate() {
+            let doc = player_data[i].as_document().unwrap();
+            assert_eq!(doc.get_str("puuid").unwrap(), puuid);
+            assert_eq!(doc.get_i32("tftLeaguePoints").unwrap(), i as i32);
+        }
+    }
+
+    #[test]
+    fn test_aggregate_all_ranked() {
+        let infos: Vec<ParticipantInfo> = (0..8)
+            .map(|i| ranked(&format!("puuid-{}", i), "DIAMOND", "II", 10 * i))
+            .collect();
+        let (player_data, avg_elo, avg_elo_text) =
+            aggregate_participants(&infos, None, EloRounding::Nearest, false);
+        // DIAMOND II 0LP is 2200, LP averages to 35
+        assert_eq!(avg_elo, 2235);
+        assert_eq!(avg_elo_text, "DIAMOND II 35LP");
+
+        let doc = player_data[3].as_document().unwrap();
+        let keys: Vec<&str> = doc.keys().map(String::as_str).collect();
+        assert_eq!(
+            keys,
+            vec![
+                "summonerId",
+                "summonerName",
+                "accountId",
+                "puuid",
+                "tftTier",
+                "tftRank",
+                "tftLeaguePoints"
+            ]
+        );
+        assert_eq!(doc.get_str("summonerId").unwrap(), "summoner-puuid-3");
+        assert_eq!(doc.get_str("tftTier").unwrap(), "DIAMOND");
+        assert_eq!(doc.get_str("tftRank").unwrap(), "II");
+        assert_eq!(doc.get_i32("tftLeaguePoints").unwrap(), 30);
+    }
+
+    #[test]
+    fn test_aggregate_unranked() {
+        let unranked = |puuid: &str| ParticipantInfo {
+            ranked: false,
+            tier: "unranked".to_string(),
+            rank: "unranked".to_string(),
+            league_points: i32::MIN,
+            ..ranked(puuid, "", "", 0)
+        };
+        // Unranked players are left out of the average, not counted as zero
+        let mut infos: Vec<ParticipantInfo> = (0..7)
+            .map(|i| ranked(&format!("puuid-{}", i), "GOLD", "I", 20))
+            .collect();
+        infos.push(unranked("puuid-7"));
+        let (player_data, avg_elo, avg_elo_text) =
+            aggregate_participants(&infos, None, EloRounding::Nearest, false);
+        assert_eq!(player_data.len(), 8);
+        assert_eq!(avg_elo, 1520);
+        assert_eq!(avg_elo_text, "GOLD I 20LP");
+        let doc = player_data[7].as_document().unwrap();
+        assert_eq!(doc.get_str("tftTier").unwrap(), "unranked");
+        assert_eq!(doc.get_i32("tftLeaguePoints").unwrap(), i32::MIN);
+
+        // Only with nobody ranked is the lobby UNRANKED
+        let infos: Vec<ParticipantInfo> =
+            (0..8).map(|i| unranked(&format!("puuid-{}", i))).collect();
+        let (_, avg_elo, avg_elo_text) =
+            aggregate_participants(&infos, None, EloRounding::Nearest, false);
+        assert_eq!(avg_elo, i32::MIN);
+        assert_eq!(avg_elo_text, "UNRANKED");
+    }
+
+    #[test]
+    fn test_aggregate_sentinel_not_averaged() {
+        // Unranked players and failed lookups carry i32::MIN LP, interleaved with the
+        // ranked. Summed, a single one would overflow (a panic in debug builds) or drag
+        // the average to the bottom of the ladder.
+        let unranked = |puuid: &str| ParticipantInfo {
+            ranked: false,
+            tier: "unranked".to_string(),
+            rank: "unranked".to_string(),
+            league_points: i32::MIN,
+            ..ranked(puuid, "", "", 0)
+        };
+        let infos = vec![
+            unranked("a"),
+            ranked("b", "PLATINUM", "I", 60),
+            ParticipantInfo::placeholder("c"),
+            unranked("d"),
+            ranked("e", "DIAMOND", "IV", 40),
+            unranked("f"),
+        ];
+        let (player_data, avg_elo, avg_elo_text) =
+            aggregate_participants(&infos, None, EloRounding::Nearest, false);
+        assert_eq!(player_data.len(), 6);
+        // (1960 + 2040) / 2, the two ranked players only
+        assert_eq!(avg_elo, 2000);
+        assert_eq!(avg_elo_text, "DIAMOND IV 0LP");
+
+        // A lone ranked player among seven without a rank is the lobby's rank
+        let mut infos: Vec<ParticipantInfo> =
+            (0..7).map(|i| unranked(&format!("puuid-{}", i))).collect();
+        infos.insert(3, ranked("r", "MASTER", "I", 150));
+        let (_, avg_elo, avg_elo_text) =
+            aggregate_participants(&infos, None, EloRounding::Nearest, false);
+        assert_eq!(avg_elo, 2550);
+        assert_eq!(avg_elo_text, "MASTER I 150LP");
+    }
+
+    #[test]
+    fn test_placeholder_excluded_from_avg() {
+        let mut infos: Vec<ParticipantInfo> = (0..7)
+            .map(|i| ranked(&format!("puuid-{}", i), "DIAMOND", "IV", 50))
+            .collect();
+        infos.push(ParticipantInfo::placeholder("puuid-7"));
+
+        let (player_data, avg_elo, avg_elo_text) =
+            aggregate_participants(&infos, None, EloRounding::Nearest, false);
+        assert_eq!(player_data.len(), 8);
+        assert_eq!(avg_elo, 2050);
+        assert_eq!(avg_elo_text, "DIAMOND IV 50LP");
+        let placeholder = player_data[7].as_document().unwrap();
+        assert_eq!(placeholder.get_str("puuid").unwrap(), "puuid-7");
+        assert_eq!(placeholder.get_str("summonerId").unwrap(), "unknown");
+    }
+
+    #[test]
+    fn test_pid_hash() {
+        // SHA-256("abc") starts ba7816bf8f01cfea
+        assert_eq!(pid_hash("abc"), "ba7816bf8f01cfea");
+        assert_eq!(pid_hash("puuid-1"), pid_hash("puuid-1"));
+        assert_ne!(pid_hash("puuid-1"), pid_hash("puuid-2"));
+
+        let infos = vec![ranked("abc", "GOLD", "I", 0)];
+        let (mut player_data, _, _) =
+            aggregate_participants(&infos, None, EloRounding::Nearest, false);
+        add_pid_hashes(&mut player_data);
+        let doc = player_data[0].as_document().unwrap();
+        assert_eq!(doc.get_str("puuid").unwrap(), "abc");
+        assert_eq!(doc.get_str("_pidHash").unwrap(), "ba7816bf8f01cfea");
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_collect_indexed_missing() {
+        collect_indexed(3, vec![(0, 'a'), (2, 'c')]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_collect_indexed_duplicate() {
+        collect_indexed(2, vec![(0, 'a'), (0, 'b'), (1, 'c')]);
+    }
+}
@@ -0,0 +1,39 @@
+/// Patch ("major.minor") of a raw match `game_version`, e.g.
+/// "Version 13.24.549.1234 (Dec 01 2023/12:00:00) [PUBLIC] <Releases/13.24>" -> "13.24".
+/// Takes the first dotted number with at least major.minor, so a bare "10.19.336.9196"
+/// works too. None if there isn't one.
+pub fn parse_patch(game_version: &str) -> Option<String> {
+    game_version.split_whitespace().find_map(|token| {
+        let mut parts = token.split('.');
+        let major: u32 = parts.next()?.parse().ok()?;
+        // The build part may be glued to trailing text, only major and minor must be clean
+        let minor: u32 = parts.next()?.parse().ok()?;
+        Some(format!("{}.{}", major, minor))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_patch() {
+        assert_eq!(
+            parse_patch("Version 13.24.549.1234 (Dec 01 2023/12:00:00) [PUBLIC] <Releases/13.24>"),
+            Some("13.24".to_string())
+        );
+        assert_eq!(
+            parse_patch("Version 10.19.336.9196 (Sep 25 2020/14:17:59) [PUBLIC] <Releases/10.19>"),
+            Some("10.19".to_string())
+        );
+        assert_eq!(
+            parse_patch("Linux Version 11.1.353.3542 [PUBLIC]"),
+            Some("11.1".to_string())
+        );
+        assert_eq!(parse_patch("9.22.296.5190"), Some("9.22".to_string()));
+        assert_eq!(parse_patch("Version 13.01.1.2"), Some("13.1".to_string()));
+        assert_eq!(parse_patch(""), None);
+        assert_eq!(parse_patch("Version unknown"), None);
+        assert_eq!(parse_patch("Version 13"), None);
+    }
+}
@@ -0,0 +1,63 @@
+use log::{error, warn};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Flip the paused flag, returning the new state
+pub fn toggle(paused: &AtomicBool) -> bool {
+    !paused.fetch_xor(true, Ordering::Relaxed)
+}
+
+/// Toggle `paused` on every SIGUSR1, e.g. `kill -USR1 <pid>`, for as long as the process runs
+#[cfg(unix)]
+pub async fn toggle_on_sigusr1(paused: Arc<AtomicBool>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut signals = match signal(SignalKind::user_defined1()) {
+        Ok(signals) => signals,
+        Err(e) => {
+            error!("Failed to listen for SIGUSR1, pausing unavailable: {}", e);
+            return;
+        }
+    };
+    while signals.recv().await.is_some() {
+        if toggle(&paused) {
+            warn!("SIGUSR1: crawling paused, send SIGUSR1 again to resume");
+        } else {
+            warn!("SIGUSR1: crawling resumed");
+        }
+    }
+}
+
+/// Set `draining` on SIGUSR2, e.g. `kill -USR2 <pid>`. Tasks stop launching summoners,
+/// finish the ones in flight and then idle, so the process can be stopped without
+/// losing work. There is no undoing it short of a restart.
+#[cfg(unix)]
+pub async fn drain_on_sigusr2(draining: Arc<AtomicBool>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut signals = match signal(SignalKind::user_defined2()) {
+        Ok(signals) => signals,
+        Err(e) => {
+            error!("Failed to listen for SIGUSR2, draining unavailable: {}", e);
+            return;
+        }
+    };
+    if signals.recv().await.is_some() {
+        draining.store(true, Ordering::Relaxed);
+        warn!("SIGUSR2: draining, in-flight summoners finish and then crawling stops");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_toggle() {
+        let paused = AtomicBool::new(false);
+        assert!(toggle(&paused));
+        assert!(paused.load(Ordering::Relaxed));
+        assert!(!toggle(&paused));
+        assert!(!paused.load(Ordering::Relaxed));
+    }
+}
@@ -0,0 +1,87 @@
+use serde_json::Value;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Last tft-status-v1 check of a platform: when, and the blocking reason if any
+pub type StatusCache = Mutex<Option<(Instant, Option<String>)>>;
+
+/// Reason to hold off crawling a platform, given its tft-status-v1 platform data:
+/// a maintenance in progress or a critical incident. `None` if it looks fine.
+pub fn blocking_status(platform_data: &Value) -> Option<String> {
+    let statuses = |key: &str| {
+        platform_data[key]
+            .as_array()
+            .cloned()
+            .unwrap_or_default()
+            .into_iter()
+    };
+    for status in statuses("maintenances") {
+        if status["maintenance_status"].as_str() == Some("in_progress") {
+            return Some(format!("Maintenance in progress: {}", title(&status)));
+        }
+    }
+    for status in statuses("incidents") {
+        if status["incident_severity"].as_str() == Some("critical") {
+            return Some(format!("Critical incident: {}", title(&status)));
+        }
+    }
+    None
+}
+
+// English title of a status entry, or its id
+fn title(status: &Value) -> String {
+    status["titles"]
+        .as_array()
+        .and_then(|titles| {
+            titles
+                .iter()
+                .find(|t| t["locale"].as_str() == Some("en_US"))
+                .or_else(|| titles.first())
+        })
+        .and_then(|t| t["content"].as_str())
+        .map(|s| s.to_string())
+        .unwrap_or_else(|| format!("#{}", status["id"]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_blocking_status() {
+        let ok = json!({"id": "EUW1", "maintenances": [], "incidents": []});
+        assert_eq!(blocking_status(&ok), None);
+
+        let scheduled = json!({
+            "maintenances": [{"id": 1, "maintenance_status": "scheduled", "titles": []}],
+            "incidents": [{"id": 2, "incident_severity": "info", "titles": []}],
+        });
+        assert_eq!(blocking_status(&scheduled), None);
+
+        let maintenance = json!({
+            "maintenances": [{
+                "id": 3,
+                "maintenance_status": "in_progress",
+                "titles": [
+                    {"locale": "de_DE", "content": "Wartung"},
+                    {"locale": "en_US", "content": "Patch 11.5"},
+                ],
+            }],
+            "incidents": [],
+        });
+        assert_eq!(
+            blocking_status(&maintenance).unwrap(),
+            "Maintenance in progress: Patch 11.5"
+        );
+
+        let incident = json!({
+            "maintenances": [],
+            "incidents": [{"id": 4, "incident_severity": "critical", "titles": []}],
+        });
+        assert_eq!(blocking_status(&incident).unwrap(), "Critical incident: #4");
+
+        // Missing or null fields are tolerated
+        assert_eq!(blocking_status(&json!({})), None);
+    }
+}
@@ -0,0 +1,66 @@
+use mongodb::bson::{doc, Document};
+
+/// Top percentages the LP cutoff is published for
+pub const TOP_PERCENTS: [u32; 6] = [1, 5, 10, 25, 50, 75];
+
+/// LP needed to be in the top `percent`% of a league, i.e. that of the player ranked
+/// `ceil(n * percent / 100)`. `lps` must be sorted highest first.
+pub fn top_percent_cutoff(lps: &[i32], percent: u32) -> Option<i32> {
+    let rank = (lps.len() * percent as usize).div_ceil(100).max(1);
+    lps.get(rank - 1).copied()
+}
+
+/// Distribution document for one scanned tier and division, keyed by region, tier and
+/// division so each cycle replaces the last. For apex tiers `minLeaguePoints` is the
+/// tier's cutoff. Timestamps are added by the caller. None for an empty league.
+pub fn distribution(region: &str, tier: &str, division: &str, lps: &[i32]) -> Option<Document> {
+    let mut lps = lps.to_vec();
+    lps.sort_unstable_by(|a, b| b.cmp(a));
+    let total: i64 = lps.iter().map(|&lp| lp as i64).sum();
+    let mut top_percent = Document::new();
+    for &percent in &TOP_PERCENTS {
+        top_percent.insert(percent.to_string(), top_percent_cutoff(&lps, percent)?);
+    }
+    Some(doc! {
+        "_id": format!("{}-{}-{}", region, tier, division),
+        "region": region,
+        "tier": tier,
+        "division": division,
+        "players": lps.len() as i64,
+        "minLeaguePoints": *lps.last()?,
+        "maxLeaguePoints": lps[0],
+        "meanLeaguePoints": total as f64 / lps.len() as f64,
+        "topPercent": top_percent,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_top_percent_cutoff() {
+        let lps: Vec<i32> = (1..=200).rev().collect();
+        assert_eq!(top_percent_cutoff(&lps, 1), Some(199));
+        assert_eq!(top_percent_cutoff(&lps, 50), Some(101));
+        // Fewer than 100 players, the top player is the top 1%
+        assert_eq!(top_percent_cutoff(&[90, 50, 10], 1), Some(90));
+        assert_eq!(top_percent_cutoff(&[90, 50, 10], 50), Some(50));
+        assert_eq!(top_percent_cutoff(&[], 1), None);
+    }
+
+    #[test]
+    fn test_distribution() {
+        let dist = distribution("EUW1", "DIAMOND", "I", &[10, 90, 50, 30]).unwrap();
+        assert_eq!(dist.get_str("_id").unwrap(), "EUW1-DIAMOND-I");
+        assert_eq!(dist.get_i64("players").unwrap(), 4);
+        assert_eq!(dist.get_i32("minLeaguePoints").unwrap(), 10);
+        assert_eq!(dist.get_i32("maxLeaguePoints").unwrap(), 90);
+        assert_eq!(dist.get_f64("meanLeaguePoints").unwrap(), 45.0);
+        assert_eq!(
+            dist.get_document("topPercent").unwrap(),
+            &doc! {"1": 90, "5": 90, "10": 90, "25": 90, "50": 50, "75": 30}
+        );
+        assert_eq!(distribution("EUW1", "DIAMOND", "I", &[]), None);
+    }
+}
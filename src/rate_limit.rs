@@ -0,0 +1,258 @@
+use crate::config::KeyTier;
+use crate::endpoints::Endpoint;
+use riven::consts::Region;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// `count` calls per `period`, as Riot states its limits. Parsed from `count/secs`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RateLimit {
+    pub count: u32,
+    pub period: Duration,
+}
+
+impl RateLimit {
+    pub const fn new(count: u32, secs: u64) -> RateLimit {
+        RateLimit {
+            count,
+            period: Duration::from_secs(secs),
+        }
+    }
+
+    /// Riot's documented method limit for `endpoint`, the same for every key tier
+    pub fn documented(endpoint: Endpoint) -> RateLimit {
+        match endpoint {
+            Endpoint::Summoner => RateLimit::new(1600, 60),
+            Endpoint::MatchList => RateLimit::new(600, 10),
+            Endpoint::Match => RateLimit::new(250, 10),
+            // entries by summoner and the paginated entries; the apex leagues are
+            // stricter but called once per cycle
+            Endpoint::League => RateLimit::new(270, 60),
+        }
+    }
+
+    /// The long window of the application limit, the one a sustained crawl runs into
+    pub fn application(tier: KeyTier) -> RateLimit {
+        match tier {
+            KeyTier::Development => RateLimit::new(100, 120),
+            KeyTier::Production => RateLimit::new(30000, 600),
+        }
+    }
+}
+
+impl FromStr for RateLimit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<RateLimit, String> {
+        let err = || format!("Expected <count>/<secs>, got: {}", s);
+        let (count, secs) = s.split_once('/').ok_or_else(err)?;
+        let count: u32 = count.trim().parse().map_err(|_| err())?;
+        let secs: u64 = secs.trim().parse().map_err(|_| err())?;
+        if count == 0 || secs == 0 {
+            return Err(err());
+        }
+        Ok(RateLimit::new(count, secs))
+    }
+}
+
+/// Refills continuously at `headroom` times the limit's rate. The burst is a tenth of
+/// the limit: Riot counts fixed windows, and a full-size burst on top of a window's
+/// worth of refill could nearly double the calls landing in one window.
+#[derive(Clone, Debug)]
+pub struct TokenBucket {
+    capacity: f64,
+    per_sec: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(limit: RateLimit, headroom: f64, now: Instant) -> TokenBucket {
+        let count = limit.count as f64 * headroom;
+        let capacity = (count / 10.0).max(1.0);
+        TokenBucket {
+            capacity,
+            per_sec: count / limit.period.as_secs_f64(),
+            tokens: capacity,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.per_sec).min(self.capacity);
+        self.updated = now;
+    }
+
+    /// Time until a whole token is available, zero if one is
+    fn wait(&self) -> Duration {
+        if self.tokens >= 1.0 {
+            return Duration::from_secs(0);
+        }
+        Duration::from_secs_f64((1.0 - self.tokens) / self.per_sec)
+    }
+
+    /// Fraction of the burst currently available
+    pub fn fill(&mut self, now: Instant) -> f64 {
+        self.refill(now);
+        self.tokens / self.capacity
+    }
+}
+
+/// One routing value's buckets: the application limit and each method's
+struct RouteBuckets {
+    application: TokenBucket,
+    methods: Vec<TokenBucket>,
+}
+
+/// Pre-flight for Riot calls: shapes each routing value's traffic to stay under the
+/// method and application limits before riven's reactive limiter sees a 429. Limits
+/// are per key and routing value, and each routing value has a single key.
+pub struct RateLimiter {
+    methods: [RateLimit; 4],
+    application: RateLimit,
+    headroom: f64,
+    routes: Mutex<HashMap<Region, RouteBuckets>>,
+    waits: AtomicU64,
+}
+
+impl RateLimiter {
+    /// `methods` in `Endpoint::ALL` order
+    pub fn new(methods: [RateLimit; 4], application: RateLimit, headroom: f64) -> RateLimiter {
+        RateLimiter {
+            methods,
+            application,
+            headroom,
+            routes: Mutex::new(HashMap::new()),
+            waits: AtomicU64::new(0),
+        }
+    }
+
+    /// Take a token from both of `route`'s buckets for `endpoint` if they each have
+    /// one, otherwise the time until they will
+    fn try_take(&self, endpoint: Endpoint, route: Region, now: Instant) -> Option<Duration> {
+        let mut routes = self.routes.lock().unwrap();
+        let buckets = routes.entry(route).or_insert_with(|| RouteBuckets {
+            application: TokenBucket::new(self.application, self.headroom, now),
+            methods: self
+                .methods
+                .iter()
+                .map(|limit| TokenBucket::new(*limit, self.headroom, now))
+                .collect(),
+        });
+        let method = &mut buckets.methods[endpoint as usize];
+        method.refill(now);
+        buckets.application.refill(now);
+        let wait = method.wait().max(buckets.application.wait());
+        if wait > Duration::from_secs(0) {
+            return Some(wait);
+        }
+        method.tokens -= 1.0;
+        buckets.application.tokens -= 1.0;
+        None
+    }
+
+    /// Wait until a call to `endpoint` on `route` fits under the limits
+    pub async fn acquire(&self, endpoint: Endpoint, route: Region) {
+        let mut waited = false;
+        while let Some(wait) = self.try_take(endpoint, route, Instant::now()) {
+            waited = true;
+            tokio::time::sleep(wait).await;
+        }
+        if waited {
+            self.waits.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Calls that had to wait for a token, since startup
+    pub fn waits(&self) -> u64 {
+        self.waits.load(Ordering::Relaxed)
+    }
+
+    /// Fill levels of `route`'s buckets, for logging
+    pub fn fill_str(&self, route: Region) -> String {
+        let now = Instant::now();
+        let mut routes = self.routes.lock().unwrap();
+        let buckets = match routes.get_mut(&route) {
+            Some(buckets) => buckets,
+            None => return "idle".to_string(),
+        };
+        let mut levels: Vec<String> = Endpoint::ALL
+            .iter()
+            .zip(buckets.methods.iter_mut())
+            .map(|(endpoint, bucket)| {
+                format!("{} {:.0}%", endpoint.name(), 100.0 * bucket.fill(now))
+            })
+            .collect();
+        levels.push(format!("app {:.0}%", 100.0 * buckets.application.fill(now)));
+        levels.join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rate_limit() {
+        assert_eq!("250/10".parse(), Ok(RateLimit::new(250, 10)));
+        assert_eq!(" 100 / 120".parse(), Ok(RateLimit::new(100, 120)));
+        assert!("250".parse::<RateLimit>().is_err());
+        assert!("0/10".parse::<RateLimit>().is_err());
+        assert!("250/0".parse::<RateLimit>().is_err());
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let start = Instant::now();
+        // 100 per 10s at 90%: bursts of 9, refilled at 9/s
+        let mut bucket = TokenBucket::new(RateLimit::new(100, 10), 0.9, start);
+        assert_eq!(bucket.fill(start), 1.0);
+        bucket.tokens -= 9.0;
+        assert_eq!(bucket.wait(), Duration::from_secs_f64(1.0 / 9.0));
+        assert!((bucket.fill(start + Duration::from_secs(1)) - 1.0).abs() < 1e-9);
+        // Never fills past the burst
+        assert_eq!(bucket.fill(start + Duration::from_secs(60)), 1.0);
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let start = Instant::now();
+        let methods = [
+            RateLimit::new(10, 1),
+            RateLimit::new(1000, 1),
+            RateLimit::new(1000, 1),
+            RateLimit::new(1000, 1),
+        ];
+        let limiter = RateLimiter::new(methods, RateLimit::new(30, 1), 1.0);
+        assert_eq!(limiter.fill_str(Region::EUW), "idle");
+
+        // The summoner burst is one call
+        assert_eq!(
+            limiter.try_take(Endpoint::Summoner, Region::EUW, start),
+            None
+        );
+        assert!(limiter
+            .try_take(Endpoint::Summoner, Region::EUW, start)
+            .is_some());
+        // Other methods and routes have their own buckets
+        assert_eq!(limiter.try_take(Endpoint::Match, Region::EUW, start), None);
+        assert_eq!(
+            limiter.try_take(Endpoint::Summoner, Region::NA, start),
+            None
+        );
+
+        // The application burst of three is shared by the route's methods
+        assert_eq!(limiter.try_take(Endpoint::League, Region::EUW, start), None);
+        assert!(limiter
+            .try_take(Endpoint::League, Region::EUW, start)
+            .is_some());
+        assert_eq!(
+            limiter.fill_str(Region::EUW),
+            "summoner 0%, match list 100%, match 99%, league 99%, app 0%"
+        );
+    }
+}
@@ -0,0 +1,67 @@
+use riven::consts::Region;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Summoner pacing of particular platforms in milliseconds, `EUNE=3000,BR=2500`, to
+/// balance platforms sharing a cluster's rate limit. Platforms not listed use
+/// `Config::summoner_pacing`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RegionPacing(HashMap<Region, Duration>);
+
+impl RegionPacing {
+    pub fn pacing(&self, region: Region, default: Duration) -> Duration {
+        self.0.get(&region).copied().unwrap_or(default)
+    }
+}
+
+impl FromStr for RegionPacing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<RegionPacing, String> {
+        let mut pacing = HashMap::new();
+        for entry in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (region, millis) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("Expected REGION=milliseconds, got {}", entry))?;
+            let region: Region = region
+                .trim()
+                .parse()
+                .map_err(|_| format!("Unknown region {}", region))?;
+            let millis: u64 = millis
+                .trim()
+                .parse()
+                .map_err(|_| format!("Invalid pacing {}", millis))?;
+            if millis == 0 {
+                return Err(format!("Pacing of {:?} must be positive", region));
+            }
+            pacing.insert(region, Duration::from_millis(millis));
+        }
+        Ok(RegionPacing(pacing))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_region_pacing() {
+        let default = Duration::from_millis(2000);
+        let pacing: RegionPacing = "EUNE=3000, BR=2500".parse().unwrap();
+        assert_eq!(
+            pacing.pacing(Region::EUNE, default),
+            Duration::from_millis(3000)
+        );
+        assert_eq!(
+            pacing.pacing(Region::BR, default),
+            Duration::from_millis(2500)
+        );
+        assert_eq!(pacing.pacing(Region::EUW, default), default);
+        assert_eq!("".parse(), Ok(RegionPacing::default()));
+        assert!("EUNE".parse::<RegionPacing>().is_err());
+        assert!("EUNE=0".parse::<RegionPacing>().is_err());
+        assert!("EUNE=-5".parse::<RegionPacing>().is_err());
+        assert!("XX=3000".parse::<RegionPacing>().is_err());
+    }
+}
@@ -0,0 +1,87 @@
+use riven::consts::Region;
+
+/// Platforms the crawler runs a task for, in each queue
+pub const SUPPORTED_REGIONS: &[Region] = &[
+    Region::EUW,
+    Region::EUNE,
+    Region::KR,
+    Region::JP,
+    Region::NA,
+    Region::BR,
+    Region::OCE,
+];
+
+/// Routing region serving the match endpoints of a platform, `None` for anything we
+/// don't crawl. Deliberately exhaustive with no catch-all, so a riven upgrade that adds
+/// a region fails to compile here instead of being routed somewhere by default.
+pub fn major_region(region: Region) -> Option<Region> {
+    match region {
+        Region::EUW | Region::EUNE => Some(Region::EUROPE),
+        Region::KR | Region::JP => Some(Region::ASIA),
+        Region::NA | Region::BR | Region::OCE => Some(Region::AMERICAS),
+        Region::LAN | Region::LAS | Region::RU | Region::TR | Region::PBE => None,
+        Region::AMERICAS | Region::EUROPE | Region::ASIA => None,
+        Region::VAL_AP
+        | Region::VAL_BR
+        | Region::VAL_EU
+        | Region::VAL_KR
+        | Region::VAL_LATAM
+        | Region::VAL_NA => None,
+    }
+}
+
+/// Stable key for a platform in stored data (collection names, crawl cursors),
+/// independent of riven's `Debug` output. `None` for anything we don't crawl.
+pub fn region_key(region: Region) -> Option<&'static str> {
+    match region {
+        Region::EUW => Some("EUW"),
+        Region::EUNE => Some("EUNE"),
+        Region::KR => Some("KR"),
+        Region::JP => Some("JP"),
+        Region::NA => Some("NA"),
+        Region::BR => Some("BR"),
+        Region::OCE => Some("OCE"),
+        Region::LAN | Region::LAS | Region::RU | Region::TR | Region::PBE => None,
+        Region::AMERICAS | Region::EUROPE | Region::ASIA => None,
+        Region::VAL_AP
+        | Region::VAL_BR
+        | Region::VAL_EU
+        | Region::VAL_KR
+        | Region::VAL_LATAM
+        | Region::VAL_NA => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_supported_regions_fully_mapped() {
+        let mut keys = vec![];
+        for region in SUPPORTED_REGIONS {
+            let major = major_region(*region);
+            assert!(
+                matches!(
+                    major,
+                    Some(Region::EUROPE) | Some(Region::ASIA) | Some(Region::AMERICAS)
+                ),
+                "{:?} has no major region",
+                region
+            );
+            let key = region_key(*region).unwrap_or_else(|| panic!("{:?} has no key", region));
+            // Keys are what existing data was stored under
+            assert_eq!(key, format!("{:?}", region));
+            assert!(!keys.contains(&key), "duplicate key {}", key);
+            keys.push(key);
+        }
+    }
+
+    #[test]
+    fn test_unsupported_regions_unmapped() {
+        for region in &[Region::PBE, Region::EUROPE, Region::VAL_EU] {
+            assert_eq!(major_region(*region), None);
+            assert_eq!(region_key(*region), None);
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use mongodb::bson::{Bson, Document};
+
+use crate::numeric_league_util::try_league_to_numeric;
+use crate::participant_info::ParticipantInfo;
+
+fn entries(player_data: &[Bson]) -> impl Iterator<Item = &Document> {
+    player_data.iter().filter_map(Bson::as_document)
+}
+
+/// Puuids of the `_aggregatedPlayerInfo` entries stored as unranked
+pub fn unranked_puuids(player_data: &[Bson]) -> Vec<String> {
+    entries(player_data)
+        .filter(|entry| entry.get_str("tftTier") == Ok("unranked"))
+        .filter_map(|entry| entry.get_str("puuid").ok().map(str::to_string))
+        .collect()
+}
+
+/// Overwrite the stored rank of each participant in `infos` who is now ranked, returning
+/// how many entries changed. `ranked` is only set where the entry already has it, see
+/// `EnrichmentLevel::Standard`.
+pub fn apply_ranks(player_data: &mut [Bson], infos: &[ParticipantInfo]) -> usize {
+    let mut changed = 0;
+    for entry in player_data.iter_mut().filter_map(Bson::as_document_mut) {
+        let info = infos
+            .iter()
+            .filter(|info| info.ranked)
+            .find(|info| entry.get_str("puuid") == Ok(info.puuid.as_str()));
+        if let Some(info) = info {
+            entry.insert("tftTier", &info.tier);
+            entry.insert("tftRank", &info.rank);
+            entry.insert("tftLeaguePoints", info.league_points);
+            if entry.contains_key("ranked") {
+                entry.insert("ranked", true);
+            }
+            changed += 1;
+        }
+    }
+    changed
+}
+
+/// The participants' ranks as stored, enough for `aggregate_participants` to recompute
+/// `_avgElo` and `_avgEloText`
+pub fn stored_infos(player_data: &[Bson]) -> Vec<ParticipantInfo> {
+    entries(player_data)
+        .map(|entry| {
+            let mut info = ParticipantInfo::placeholder(entry.get_str("puuid").unwrap_or_default());
+            info.tier = entry.get_str("tftTier").unwrap_or("unknown").to_string();
+            info.rank = entry.get_str("tftRank").unwrap_or("unknown").to_string();
+            info.league_points = entry.get_i32("tftLeaguePoints").unwrap_or(i32::MIN);
+            info.ranked =
+                try_league_to_numeric(&info.tier, &info.rank, info.league_points).is_some();
+            info
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::numeric_league_util::EloRounding;
+    use crate::participant_info::aggregate_participants;
+    use mongodb::bson::doc;
+
+    fn entry(puuid: &str, tier: &str, rank: &str, lp: i32) -> Bson {
+        doc! {
+            "puuid": puuid, "tftTier": tier, "tftRank": rank, "tftLeaguePoints": lp,
+            "ranked": tier != "unranked", "placement": 1,
+        }
+        .into()
+    }
+
+    #[test]
+    fn test_rerank() {
+        let mut player_data = vec![
+            entry("a", "DIAMOND", "II", 50),
+            entry("b", "unranked", "unranked", i32::MIN),
+            entry("c", "unranked", "unranked", i32::MIN),
+        ];
+        assert_eq!(unranked_puuids(&player_data), vec!["b", "c"]);
+
+        // b has placed since, c still hasn't
+        let mut b = ParticipantInfo::placeholder("b");
+        b.ranked = true;
+        b.tier = "DIAMOND".to_string();
+        b.rank = "IV".to_string();
+        b.league_points = 50;
+        let c = ParticipantInfo::placeholder("c");
+        assert_eq!(apply_ranks(&mut player_data, &[b, c]), 1);
+        assert_eq!(unranked_puuids(&player_data), vec!["c"]);
+        let updated = player_data[1].as_document().unwrap();
+        assert_eq!(updated.get_str("tftTier"), Ok("DIAMOND"));
+        assert_eq!(updated.get_bool("ranked"), Ok(true));
+        // Other fields of the entry are kept
+        assert_eq!(updated.get_i32("placement"), Ok(1));
+
+        let infos = stored_infos(&player_data);
+        assert_eq!(
+            infos.iter().map(|info| info.ranked).collect::<Vec<_>>(),
+            vec![true, true, false]
+        );
+        let (_, avg_elo, avg_elo_text) =
+            aggregate_participants(&infos, None, EloRounding::Nearest, false);
+        assert_eq!(avg_elo, 2150);
+        assert_eq!(avg_elo_text, "DIAMOND III 50LP");
+    }
+}
@@ -0,0 +1,62 @@
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+/// Retries a region task may spend in one cycle, across every retry loop. During a
+/// broad outage this caps how much extra load the task's retries add. Also used to cap
+/// other per-cycle calls, see `Config::max_summoner_calls_per_cycle`.
+#[derive(Default)]
+pub struct RetryBudget {
+    remaining: AtomicU32,
+    exhausted: AtomicBool,
+}
+
+impl RetryBudget {
+    /// Start a new cycle with `retries` available
+    pub fn reset(&self, retries: u32) {
+        self.remaining.store(retries, Ordering::Relaxed);
+        self.exhausted.store(false, Ordering::Relaxed);
+    }
+
+    /// Take one retry if any are left. Otherwise `on_exhausted` is called, on the
+    /// first refusal of the cycle only.
+    pub fn try_take(&self, on_exhausted: impl FnOnce()) -> bool {
+        let taken = self
+            .remaining
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1))
+            .is_ok();
+        if !taken && !self.exhausted.swap(true, Ordering::Relaxed) {
+            on_exhausted();
+        }
+        taken
+    }
+
+    /// Whether a take has been refused this cycle
+    pub fn exhausted(&self) -> bool {
+        self.exhausted.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_budget() {
+        let budget = RetryBudget::default();
+        let mut refusals_logged = 0;
+        budget.reset(2);
+        assert!(budget.try_take(|| refusals_logged += 1));
+        assert!(budget.try_take(|| refusals_logged += 1));
+        assert!(!budget.exhausted());
+        assert!(!budget.try_take(|| refusals_logged += 1));
+        assert!(!budget.try_take(|| refusals_logged += 1));
+        assert_eq!(refusals_logged, 1);
+        assert!(budget.exhausted());
+
+        // Next cycle
+        budget.reset(1);
+        assert!(!budget.exhausted());
+        assert!(budget.try_take(|| refusals_logged += 1));
+        assert!(!budget.try_take(|| refusals_logged += 1));
+        assert_eq!(refusals_logged, 2);
+    }
+}
@@ -0,0 +1,241 @@
+use riven::models::tft_league_v1::LeagueList;
+use std::collections::{HashSet, VecDeque};
+use std::str::FromStr;
+
+const APEX_TIERS: [&str; 3] = ["CHALLENGER", "GRANDMASTER", "MASTER"];
+const TIERS: [&str; 6] = ["DIAMOND", "PLATINUM", "GOLD", "SILVER", "BRONZE", "IRON"];
+const DIVISIONS: [&str; 4] = ["I", "II", "III", "IV"];
+
+/// The (tier, division) pairs whose players are crawled each ranked cycle, in order.
+/// Apex tiers come from the non-paginated league endpoints and always have division I.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScanConfig {
+    pub divisions: Vec<(String, String)>,
+}
+
+impl Default for ScanConfig {
+    fn default() -> ScanConfig {
+        "APEX,DIAMOND I,DIAMOND II,DIAMOND III".parse().unwrap()
+    }
+}
+
+// Comma-separated entries: "APEX" for all three apex tiers, an apex tier on its own,
+// a tier for all four of its divisions, or "<tier> <division>"
+impl FromStr for ScanConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ScanConfig, String> {
+        let mut divisions = vec![];
+        let mut invalid = vec![];
+        for entry in s.split(',').map(str::trim).filter(|x| !x.is_empty()) {
+            let entry = entry.to_uppercase();
+            let mut parts = entry.split_whitespace();
+            let tier = parts.next().unwrap();
+            let division = parts.next();
+            if parts.next().is_some() {
+                invalid.push(entry.clone());
+                continue;
+            }
+            match (tier, division) {
+                ("APEX", None) => {
+                    for apex in &APEX_TIERS {
+                        divisions.push((apex.to_string(), "I".to_string()));
+                    }
+                }
+                (tier, None) | (tier, Some("I")) if APEX_TIERS.contains(&tier) => {
+                    divisions.push((tier.to_string(), "I".to_string()));
+                }
+                (tier, None) if TIERS.contains(&tier) => {
+                    for division in &DIVISIONS {
+                        divisions.push((tier.to_string(), division.to_string()));
+                    }
+                }
+                (tier, Some(division))
+                    if TIERS.contains(&tier) && DIVISIONS.contains(&division) =>
+                {
+                    divisions.push((tier.to_string(), division.to_string()));
+                }
+                _ => invalid.push(entry.clone()),
+            }
+        }
+        if !invalid.is_empty() {
+            return Err(invalid_entries_error(&invalid));
+        }
+        if divisions.is_empty() {
+            return Err("Scan config has no tiers".to_string());
+        }
+        Ok(ScanConfig { divisions })
+    }
+}
+
+impl ScanConfig {
+    /// Check every (tier, division) pair is one the league endpoints accept, listing all
+    /// that aren't
+    pub fn validate(&self) -> Result<(), String> {
+        let invalid: Vec<String> = self
+            .divisions
+            .iter()
+            .filter(|(tier, division)| !is_valid_division(tier, division))
+            .map(|(tier, division)| format!("{} {}", tier, division))
+            .collect();
+        if invalid.is_empty() {
+            Ok(())
+        } else {
+            Err(invalid_entries_error(&invalid))
+        }
+    }
+}
+
+fn is_valid_division(tier: &str, division: &str) -> bool {
+    if APEX_TIERS.contains(&tier) {
+        division == "I"
+    } else {
+        TIERS.contains(&tier) && DIVISIONS.contains(&division)
+    }
+}
+
+fn invalid_entries_error(invalid: &[String]) -> String {
+    format!(
+        "Invalid scan entries: {} (expected APEX, a tier, or \"<tier> <division>\" with division I to IV, I only for apex tiers)",
+        invalid.join(", ")
+    )
+}
+
+/// Summoner ids of every player on an apex ladder
+pub fn apex_summoner_ids(league: &LeagueList) -> Vec<String> {
+    league
+        .entries
+        .iter()
+        .map(|y| y.summoner_id.clone())
+        .collect()
+}
+
+/// The summoners a cycle processes, with their index in the scan: exactly this cycle's
+/// ladder scan, nothing carried over from earlier cycles or the caches, so players who
+/// dropped out of the scanned tiers cost no calls. A player seen twice, e.g. promoted
+/// between pages of a paginated scan, is processed once.
+pub fn cycle_queue(scanned: &[String]) -> VecDeque<(usize, &String)> {
+    let mut seen = HashSet::new();
+    scanned
+        .iter()
+        .enumerate()
+        .filter(|(_, id)| seen.insert(id.as_str()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pairs(list: &[(&str, &str)]) -> Vec<(String, String)> {
+        list.iter()
+            .map(|(tier, division)| (tier.to_string(), division.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_default_scan() {
+        assert_eq!(
+            ScanConfig::default().divisions,
+            pairs(&[
+                ("CHALLENGER", "I"),
+                ("GRANDMASTER", "I"),
+                ("MASTER", "I"),
+                ("DIAMOND", "I"),
+                ("DIAMOND", "II"),
+                ("DIAMOND", "III"),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_scan_config() {
+        let apex_only: ScanConfig = "challenger, grandmaster,MASTER".parse().unwrap();
+        assert_eq!(apex_only, "APEX".parse().unwrap());
+        assert_eq!(
+            "platinum".parse::<ScanConfig>().unwrap().divisions,
+            pairs(&[
+                ("PLATINUM", "I"),
+                ("PLATINUM", "II"),
+                ("PLATINUM", "III"),
+                ("PLATINUM", "IV"),
+            ])
+        );
+        assert!("MASTER II".parse::<ScanConfig>().is_err());
+        assert!("DIAMOND V".parse::<ScanConfig>().is_err());
+        assert!("DIAMOND I II".parse::<ScanConfig>().is_err());
+        assert!("".parse::<ScanConfig>().is_err());
+
+        // Every invalid entry is reported at once
+        let err = "DIAMOND V,MASTER,gold 5,MASTER II"
+            .parse::<ScanConfig>()
+            .unwrap_err();
+        assert!(err.starts_with("Invalid scan entries: DIAMOND V, GOLD 5, MASTER II ("));
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(ScanConfig::default().validate().is_ok());
+        let scan = ScanConfig {
+            divisions: pairs(&[("DIAMOND", "V"), ("DIAMOND", "I"), ("MASTER", "II")]),
+        };
+        assert!(scan
+            .validate()
+            .unwrap_err()
+            .starts_with("Invalid scan entries: DIAMOND V, MASTER II ("));
+    }
+
+    #[test]
+    fn test_cycle_queue() {
+        let ids = |list: &[&str]| -> Vec<String> { list.iter().map(|s| s.to_string()).collect() };
+        let queued = |queue: VecDeque<(usize, &String)>| -> Vec<(usize, String)> {
+            queue
+                .into_iter()
+                .map(|(index, id)| (index, id.clone()))
+                .collect()
+        };
+        let previous_scan = ids(&["a", "demoted", "b"]);
+        let current_scan = ids(&["c", "a", "b", "a"]);
+        let previous = queued(cycle_queue(&previous_scan));
+        assert_eq!(
+            previous,
+            vec![(0, "a".into()), (1, "demoted".into()), (2, "b".into())]
+        );
+        // Processed last cycle, and so in the caches, but not in this scan: the queue is
+        // this scan's order and indices, with nothing of the previous queue carried over
+        let current = queued(cycle_queue(&current_scan));
+        assert_eq!(
+            current,
+            vec![(0, "c".into()), (1, "a".into()), (2, "b".into())]
+        );
+        assert!(previous.iter().any(|(_, id)| id == "demoted"));
+        assert!(current.iter().all(|(_, id)| id != "demoted"));
+    }
+
+    #[test]
+    fn test_apex_summoner_ids() {
+        let entry = |summoner_id: &str, lp: i32| {
+            serde_json::json!({
+                "freshBlood": false,
+                "wins": 10,
+                "summonerName": "name",
+                "inactive": false,
+                "veteran": false,
+                "hotStreak": false,
+                "rank": "I",
+                "leaguePoints": lp,
+                "losses": 5,
+                "summonerId": summoner_id,
+            })
+        };
+        let league: LeagueList = serde_json::from_value(serde_json::json!({
+            "leagueId": "league",
+            "entries": [entry("a", 900), entry("b", 800), entry("c", 700)],
+            "tier": "CHALLENGER",
+            "name": "name",
+            "queue": "RANKED_TFT",
+        }))
+        .unwrap();
+        assert_eq!(apex_summoner_ids(&league), vec!["a", "b", "c"]);
+    }
+}
@@ -0,0 +1,101 @@
+use anyhow::{anyhow, bail};
+use log::{error, info, warn};
+use std::str::FromStr;
+
+use crate::commands::recent_top_match;
+use crate::Main;
+
+/// What to do when live matches are from another set than the collections are for,
+/// see `Config::set_check`
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum SetCheck {
+    Off,
+    /// Log an error and crawl anyway
+    #[default]
+    Warn,
+    /// Refuse to start
+    Refuse,
+}
+
+impl FromStr for SetCheck {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<SetCheck, String> {
+        match s.to_lowercase().as_str() {
+            "off" => Ok(SetCheck::Off),
+            "warn" => Ok(SetCheck::Warn),
+            "refuse" => Ok(SetCheck::Refuse),
+            _ => Err(format!("Unknown set check: {}", s)),
+        }
+    }
+}
+
+/// TFT set a collection is for, from its suffix: `matches-4-1` is set 4
+pub fn collection_set(name: &str) -> Option<i32> {
+    name.split('-').find_map(|part| part.parse().ok())
+}
+
+/// Compare the set of each task's platform's latest top match with `collection`'s.
+/// A platform whose match can't be fetched is skipped with a warning.
+pub async fn check(mains: &[Main], collection: &str, mode: SetCheck) -> anyhow::Result<()> {
+    if mode == SetCheck::Off {
+        return Ok(());
+    }
+    let expected = collection_set(collection)
+        .ok_or_else(|| anyhow!("Collection {} has no set suffix", collection))?;
+    let observed = futures::future::join_all(mains.iter().map(|main| async move {
+        let (_, match_id) = recent_top_match(main).await?;
+        let game = main
+            .api
+            .tft_match_v1()
+            .get_match(main.region_major, &match_id)
+            .await?
+            .ok_or_else(|| anyhow!("{} not found", match_id))?;
+        Ok::<_, anyhow::Error>((match_id, game.info.tft_set_number))
+    }))
+    .await;
+
+    let mut mismatched = vec![];
+    for (main, observed) in mains.iter().zip(observed) {
+        match observed {
+            Ok((_, set)) if set == expected => (),
+            Ok((match_id, set)) => {
+                error!(
+                    "[{}] Latest match {} is from set {}, but {} is for set {}",
+                    main.region, match_id, set, collection, expected
+                );
+                mismatched.push(main.region);
+            }
+            Err(e) => warn!("[{}] Set check skipped: {:#}", main.region, e),
+        }
+    }
+    if mismatched.is_empty() {
+        info!("Set check passed: live matches are from set {}", expected);
+    } else if mode == SetCheck::Refuse {
+        bail!(
+            "Set check failed on {:?}: update the collection suffix for the new set, or set SET_CHECK=warn",
+            mismatched
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collection_set() {
+        assert_eq!(collection_set("matches-4-1"), Some(4));
+        assert_eq!(collection_set("matches-4-1-EUW"), Some(4));
+        assert_eq!(collection_set("matches-10-2"), Some(10));
+        assert_eq!(collection_set("matches"), None);
+    }
+
+    #[test]
+    fn test_parse_set_check() {
+        assert_eq!("Refuse".parse(), Ok(SetCheck::Refuse));
+        assert_eq!(SetCheck::default(), SetCheck::Warn);
+        assert!("strict".parse::<SetCheck>().is_err());
+    }
+}
@@ -0,0 +1,37 @@
+use tokio::task::JoinError;
+
+/// How a task that should run forever ended, for the log line before exiting
+pub fn describe(result: Result<(), JoinError>) -> String {
+    match result {
+        Ok(()) => "returned".to_string(),
+        Err(e) if e.is_panic() => {
+            let payload = e.into_panic();
+            let message = if let Some(message) = payload.downcast_ref::<&str>() {
+                message.to_string()
+            } else if let Some(message) = payload.downcast_ref::<String>() {
+                message.clone()
+            } else {
+                "non-string payload".to_string()
+            };
+            format!("panicked: {}", message)
+        }
+        Err(_) => "was cancelled".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_describe() {
+        assert_eq!(describe(tokio::spawn(async {}).await), "returned");
+        let panicked = tokio::spawn(async { panic!("No major region for {:?}", "EUW1") }).await;
+        assert_eq!(describe(panicked), "panicked: No major region for \"EUW1\"");
+        let panicked = tokio::spawn(async { std::panic::panic_any(5) }).await;
+        assert_eq!(describe(panicked), "panicked: non-string payload");
+        let cancelled = tokio::spawn(futures::future::pending::<()>());
+        cancelled.abort();
+        assert_eq!(describe(cancelled.await), "was cancelled");
+    }
+}
@@ -0,0 +1,38 @@
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::Resource;
+use tracing_subscriber::layer::SubscriberExt;
+
+/// Export the crawler's tracing spans over OTLP/gRPC to OTEL_EXPORTER_OTLP_ENDPOINT,
+/// e.g. `http://localhost:4317`. Each processed summoner is one trace. Returns whether
+/// export was set up; without the variable nothing is exported.
+pub fn init() -> Result<bool, String> {
+    let endpoint = match std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT") {
+        Ok(endpoint) => endpoint,
+        Err(_) => return Ok(false),
+    };
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint),
+        )
+        .with_trace_config(
+            opentelemetry_sdk::trace::config().with_resource(Resource::new(vec![KeyValue::new(
+                "service.name",
+                "tft-stat",
+            )])),
+        )
+        .install_batch(opentelemetry_sdk::runtime::Tokio)
+        .map_err(|e| e.to_string())?;
+    let subscriber =
+        tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer));
+    tracing::subscriber::set_global_default(subscriber).map_err(|e| e.to_string())?;
+    Ok(true)
+}
+
+/// Flush spans still waiting to be exported
+pub fn shutdown() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
@@ -0,0 +1,126 @@
+use chrono::{DateTime, Duration, Utc};
+use log::{error, info};
+use mongodb::bson::{doc, Bson, Document};
+use mongodb::options::FindOneOptions;
+
+// MongoDB's TTL monitor runs every 60 seconds; documents expired for longer
+// than this mean it isn't removing them
+const TTL_LAG_WARNING: i64 = 60 * 60;
+
+/// Whether `listIndexes` output contains a TTL index on `_documentExpire`
+pub fn has_ttl_index(indexes: &[Document]) -> bool {
+    indexes.iter().any(|index| {
+        let on_expire = index
+            .get_document("key")
+            .is_ok_and(|key| key.contains_key("_documentExpire"));
+        on_expire && index.contains_key("expireAfterSeconds")
+    })
+}
+
+/// How long the oldest `_documentExpire` is past due, if beyond the warning threshold
+pub fn ttl_lag(oldest_expire: DateTime<Utc>, now: DateTime<Utc>) -> Option<Duration> {
+    let lag = now - oldest_expire;
+    if lag > Duration::seconds(TTL_LAG_WARNING) {
+        Some(lag)
+    } else {
+        None
+    }
+}
+
+/// Check that the collection has its TTL index and that expired documents are being
+/// removed. Problems are logged loudly; returns whether the collection looks healthy.
+pub async fn check_ttl(db: &mongodb::Database, collection_name: &str) -> bool {
+    let indexes = match db
+        .run_command(doc! {"listIndexes": collection_name}, None)
+        .await
+    {
+        Ok(ret) => ret
+            .get_document("cursor")
+            .and_then(|cursor| cursor.get_array("firstBatch"))
+            .map(|batch| {
+                batch
+                    .iter()
+                    .filter_map(Bson::as_document)
+                    .cloned()
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default(),
+        Err(e) => {
+            error!(
+                "TTL check: listIndexes on {} failed: {}",
+                collection_name, e
+            );
+            return false;
+        }
+    };
+    if !has_ttl_index(&indexes) {
+        error!(
+            "TTL check: {} has no TTL index on _documentExpire, documents will never expire",
+            collection_name
+        );
+        return false;
+    }
+
+    // Only sort on _documentExpire once we know it's indexed
+    let options = FindOneOptions::builder()
+        .sort(doc! {"_documentExpire": 1})
+        .build();
+    let oldest = db
+        .collection::<Document>(collection_name)
+        .find_one(doc! {"_documentExpire": {"$exists": true}}, options)
+        .await;
+    match oldest {
+        Ok(Some(oldest)) => {
+            if let Ok(expire) = oldest.get_datetime("_documentExpire") {
+                if let Some(lag) = ttl_lag(*expire, Utc::now()) {
+                    error!(
+                        "TTL check: {} has documents that expired {} hours ago, the TTL monitor isn't removing them",
+                        collection_name,
+                        lag.num_hours()
+                    );
+                    return false;
+                }
+            }
+        }
+        Ok(None) => (),
+        Err(e) => {
+            error!("TTL check: find_one on {} failed: {}", collection_name, e);
+            return false;
+        }
+    }
+    info!("TTL check: {} ok", collection_name);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_has_ttl_index() {
+        let id_index = doc! {"v": 2, "key": {"_id": 1}, "name": "_id_"};
+        let ttl_index = doc! {
+            "v": 2,
+            "key": {"_documentExpire": 1},
+            "name": "_documentExpire_1",
+            "expireAfterSeconds": 0,
+        };
+        let plain_index = doc! {"v": 2, "key": {"_documentExpire": 1}, "name": "_documentExpire_1"};
+        assert!(!has_ttl_index(&[]));
+        assert!(!has_ttl_index(std::slice::from_ref(&id_index)));
+        assert!(!has_ttl_index(&[id_index.clone(), plain_index]));
+        assert!(has_ttl_index(&[id_index, ttl_index]));
+    }
+
+    #[test]
+    fn test_ttl_lag() {
+        let now = Utc.ymd(2021, 3, 1).and_hms(12, 0, 0);
+        assert_eq!(ttl_lag(now + Duration::hours(1), now), None);
+        assert_eq!(ttl_lag(now - Duration::minutes(5), now), None);
+        assert_eq!(
+            ttl_lag(now - Duration::hours(30), now),
+            Some(Duration::hours(30))
+        );
+    }
+}
@@ -0,0 +1,245 @@
+use mongodb::bson::Document;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Whether to store `_typedMatch`, see `Config::typed_match`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TypedMatchMode {
+    Off,
+    /// Next to the raw match
+    Alongside,
+    /// In place of the raw match
+    Instead,
+}
+
+impl FromStr for TypedMatchMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<TypedMatchMode, String> {
+        match s.to_lowercase().as_str() {
+            "off" => Ok(TypedMatchMode::Off),
+            "alongside" => Ok(TypedMatchMode::Alongside),
+            "instead" => Ok(TypedMatchMode::Instead),
+            _ => Err(format!("Unknown typed match mode: {}", s)),
+        }
+    }
+}
+
+/// The parts of a match we analyse, in a shape of our own rather than whatever Riot
+/// sends. Stored as `_typedMatch`; deserializing it back into this struct fails loudly
+/// if the stored shape ever drifts from this definition.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TypedMatch {
+    pub match_id: String,
+    pub game_datetime: i64,
+    pub game_length: f32,
+    pub game_version: String,
+    pub queue_id: i32,
+    pub tft_set_number: i32,
+    pub participants: Vec<TypedParticipant>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TypedParticipant {
+    pub puuid: String,
+    pub placement: i32,
+    pub level: i32,
+    pub last_round: i32,
+    pub gold_left: i32,
+    pub total_damage_to_players: i32,
+    pub traits: Vec<TypedTrait>,
+    pub units: Vec<TypedUnit>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TypedTrait {
+    pub name: String,
+    pub num_units: i32,
+    pub tier_current: i32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TypedUnit {
+    pub character_id: String,
+    pub tier: i32,
+    pub rarity: i32,
+    pub items: Vec<i32>,
+}
+
+// Riot's match-v1 shape of the fields above. Parsed from the raw response rather than
+// riven's `Match`, so a field Riot drops, renames or retypes fails here.
+#[derive(Deserialize)]
+struct RawMatch {
+    metadata: RawMetadata,
+    info: RawInfo,
+}
+
+#[derive(Deserialize)]
+struct RawMetadata {
+    match_id: String,
+}
+
+#[derive(Deserialize)]
+struct RawInfo {
+    game_datetime: i64,
+    game_length: f32,
+    game_version: String,
+    queue_id: i32,
+    tft_set_number: i32,
+    participants: Vec<RawParticipant>,
+}
+
+#[derive(Deserialize)]
+struct RawParticipant {
+    puuid: String,
+    placement: i32,
+    level: i32,
+    last_round: i32,
+    gold_left: i32,
+    total_damage_to_players: i32,
+    traits: Vec<RawTrait>,
+    units: Vec<RawUnit>,
+}
+
+#[derive(Deserialize)]
+struct RawTrait {
+    name: String,
+    num_units: i32,
+    tier_current: i32,
+}
+
+#[derive(Deserialize)]
+struct RawUnit {
+    character_id: String,
+    tier: i32,
+    rarity: i32,
+    items: Vec<i32>,
+}
+
+impl From<RawParticipant> for TypedParticipant {
+    fn from(participant: RawParticipant) -> TypedParticipant {
+        TypedParticipant {
+            puuid: participant.puuid,
+            placement: participant.placement,
+            level: participant.level,
+            last_round: participant.last_round,
+            gold_left: participant.gold_left,
+            total_damage_to_players: participant.total_damage_to_players,
+            traits: participant
+                .traits
+                .into_iter()
+                .map(|t| TypedTrait {
+                    name: t.name,
+                    num_units: t.num_units,
+                    tier_current: t.tier_current,
+                })
+                .collect(),
+            units: participant
+                .units
+                .into_iter()
+                .map(|u| TypedUnit {
+                    character_id: u.character_id,
+                    tier: u.tier,
+                    rarity: u.rarity,
+                    items: u.items,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl TypedMatch {
+    /// From the body of a match-v1 response, failing if it no longer has the shape above
+    pub fn from_raw(body: &str) -> Result<TypedMatch, String> {
+        let raw: RawMatch = serde_json::from_str(body).map_err(|e| e.to_string())?;
+        Ok(TypedMatch {
+            match_id: raw.metadata.match_id,
+            game_datetime: raw.info.game_datetime,
+            game_length: raw.info.game_length,
+            game_version: raw.info.game_version,
+            queue_id: raw.info.queue_id,
+            tft_set_number: raw.info.tft_set_number,
+            participants: raw.info.participants.into_iter().map(Into::into).collect(),
+        })
+    }
+
+    pub fn to_document(&self) -> Result<Document, String> {
+        mongodb::bson::to_document(self).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::bson::{doc, from_document};
+
+    fn body() -> serde_json::Value {
+        serde_json::json!({
+            "metadata": {"data_version": "5", "match_id": "EUW1_1", "participants": ["a"]},
+            "info": {
+                "game_datetime": 1600000000000i64,
+                "game_length": 2000.5,
+                "game_version": "Version 11.1",
+                "participants": [{
+                    "companion": {"skin_ID": 1, "content_ID": "c", "species": "s"},
+                    "gold_left": 3,
+                    "last_round": 30,
+                    "level": 8,
+                    "placement": 1,
+                    "players_eliminated": 2,
+                    "puuid": "a",
+                    "time_eliminated": 1800.0,
+                    "total_damage_to_players": 100,
+                    "traits": [{"name": "Set4_Cultist", "num_units": 6, "tier_current": 2}],
+                    "units": [{
+                        "items": [16, 44],
+                        "character_id": "TFT4_Aatrox",
+                        "name": "",
+                        "rarity": 4,
+                        "tier": 2,
+                    }],
+                }],
+                "queue_id": 1100,
+                "tft_set_number": 4,
+            },
+        })
+    }
+
+    #[test]
+    fn test_typed_match_round_trip() {
+        let typed = TypedMatch::from_raw(&body().to_string()).unwrap();
+        assert_eq!(typed.participants[0].traits[0].name, "Set4_Cultist");
+        assert_eq!(typed.participants[0].units[0].items, vec![16, 44]);
+        let doc = typed.to_document().unwrap();
+        assert_eq!(doc.get_str("matchId"), Ok("EUW1_1"));
+        assert_eq!(from_document::<TypedMatch>(doc).unwrap(), typed);
+    }
+
+    #[test]
+    fn test_typed_match_raw_drift() {
+        // Riot renaming a field fails the conversion, where riven may fill a default
+        let mut body = body();
+        let unit = &mut body["info"]["participants"][0]["units"][0];
+        unit["itemNames"] = unit["items"].take();
+        unit.as_object_mut().unwrap().remove("items");
+        assert!(TypedMatch::from_raw(&body.to_string()).is_err());
+    }
+
+    #[test]
+    fn test_typed_match_shape_drift() {
+        // A stored document missing a field no longer matches the struct
+        let doc = doc! {"matchId": "EUW1_1", "gameDatetime": 1600000000000i64};
+        assert!(from_document::<TypedMatch>(doc).is_err());
+    }
+
+    #[test]
+    fn test_typed_match_mode() {
+        assert_eq!("Alongside".parse(), Ok(TypedMatchMode::Alongside));
+        assert_eq!("Instead".parse(), Ok(TypedMatchMode::Instead));
+        assert!("both".parse::<TypedMatchMode>().is_err());
+    }
+}